@@ -0,0 +1,100 @@
+## Language selection
+
+welcome = Добре дошли в системата за банкомати
+select-language = Изберете език
+invalid-language-choice = Невалиден избор, използва се { $language }
+
+## Card
+
+available-card-keys = Налични ключове на карти за тестване:
+test-card = { $index }. { $key } (Карта: { $card }, ПИН: { $pin })
+enter-card-key = Въведете ключа на вашата карта:
+enter-pin = Въведете вашия ПИН:
+card-key-validated = Ключът на картата е успешно валидиран
+invalid-card-key = Невалиден ключ на картата
+card-validation-failed = Валидирането на картата е неуспешно. Моля, вземете картата си.
+card-cancelled = Операцията е отказана. Моля, вземете картата си.
+card-attempts-left = Моля, опитайте отново. Оставащи опити: { $attempts }
+using-card = Използвате карта { $card }
+
+## Menu
+
+select-option = Изберете опция:
+menu-check-balance = Проверка на баланс
+menu-withdraw = Теглене на пари
+menu-change-language = Промени езика (Change language)
+menu-exit = Изход
+invalid-option = Невалидна опция. Моля, опитайте отново.
+goodbye = Благодарим ви, че използвахте нашия банкомат. Довиждане!
+
+## Questions
+
+# What the customer types to answer yes or no
+answer-yes = д
+answer-no = н
+yes-no = ({ answer-yes }/{ answer-no })
+
+## Balance and withdrawal
+
+# Placed before or after amounts as languages.toml says
+currency-symbol = $
+current-balance = Текущият ви баланс е: { $amount }
+enter-withdraw-amount = Въведете сума за теглене:
+invalid-amount = Невалидна сума
+want-receipt = Искате ли касова бележка?
+withdraw-success = Успешно изтеглихте { $amount }
+new-balance = Новият ви баланс е: { $balance }
+insufficient-funds = Недостатъчна наличност
+cannot-dispense = Този банкомат не може да изплати тази сума, моля изберете друга
+amount-over-limit = Този банкомат изплаща най-много { $limit } наведнъж
+printing-receipt = Отпечатване на касова бележка...
+receipt =
+    === КАСОВА БЕЛЕЖКА ===
+    Дата: { $date }
+    Изтеглена Сума: { $amount }
+    Нов Баланс: { $balance }
+    ====================
+receipt-stand-in =
+    === КАСОВА БЕЛЕЖКА ===
+    Дата: { $date }
+    Изтеглена Сума: { $amount }
+    Одобрено без връзка
+    ====================
+
+## Errors
+
+card-not-found = Картата не е намерена
+invalid-pin = Невалиден ПИН
+invalid-card-number = Невалиден номер на карта
+card-not-supported = Тази карта не се поддържа
+card-expired = Картата ви е изтекла
+card-blocked = Картата ви е блокирана. Моля, свържете се с вашата банка.
+card-retained = Картата ви е задържана. Моля, свържете се с вашата банка.
+server-error = Сървърна грешка
+unexpected-response = Неочакван отговор от сървъра
+bank-not-responding = Банката не отговаря.
+out-of-service = Временно извън употреба. Моля, изчакайте...
+back-in-service = Банкоматът отново работи.
+session-ended = Сесията ви приключи. Моля, вземете картата си.
+need-more-time = Нуждаете ли се от още време?
+session-timed-out = Времето на сесията ви изтече. Моля, вземете картата си.
+withdrawal-unconfirmed = Тегленето не можа да бъде потвърдено, затова беше отменено и не бяха изплатени пари. Всяка удържана от сметката ви сума ще бъде възстановена.
+bank-unavailable = Банката е недостъпна. Възможни са само тегления до { $limit }.
+stand-in-over-limit = Банката е недостъпна. Тегленията са ограничени до { $limit }, докато тя отново заработи.
+stand-in-approved = Балансът ви ще бъде обновен, когато банката отново е достъпна.
+
+## Full-screen UI
+
+soft-key-yes = Да
+soft-key-no = Не
+soft-key-cancel = Отказ
+soft-key-confirm = Потвърди
+soft-key-continue = Продължи
+take-card = Моля, вземете картата си.
+keypad-title = Клавиатура
+card-slot-title = Слот за карта
+receipt-title = Касова бележка
+card-slot-empty = Поставете карта
+card-slot-inserted = Карта { $card }
+card-slot-retained = Картата е задържана
+card-slot-ejected = Картата е върната
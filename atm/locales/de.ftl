@@ -0,0 +1,100 @@
+## Language selection
+
+welcome = Willkommen im Geldautomatensystem
+select-language = Sprache wählen
+invalid-language-choice = Ungültige Auswahl, es wird { $language } verwendet
+
+## Card
+
+available-card-keys = Verfügbare Kartenschlüssel zum Testen:
+test-card = { $index }. { $key } (Karte: { $card }, PIN: { $pin })
+enter-card-key = Geben Sie Ihren Kartenschlüssel ein:
+enter-pin = Geben Sie Ihre PIN ein:
+card-key-validated = Kartenschlüssel erfolgreich überprüft
+invalid-card-key = Ungültiger Kartenschlüssel
+card-validation-failed = Kartenprüfung fehlgeschlagen. Bitte entnehmen Sie Ihre Karte.
+card-cancelled = Abgebrochen. Bitte entnehmen Sie Ihre Karte.
+card-attempts-left = Bitte versuchen Sie es erneut. Verbleibende Versuche: { $attempts }
+using-card = Sie verwenden die Karte { $card }
+
+## Menu
+
+select-option = Wählen Sie eine Option:
+menu-check-balance = Kontostand abfragen
+menu-withdraw = Geld abheben
+menu-change-language = Sprache ändern (Change language)
+menu-exit = Beenden
+invalid-option = Ungültige Option. Bitte versuchen Sie es erneut.
+goodbye = Vielen Dank, dass Sie unseren Geldautomaten benutzt haben. Auf Wiedersehen!
+
+## Questions
+
+# What the customer types to answer yes or no
+answer-yes = j
+answer-no = n
+yes-no = ({ answer-yes }/{ answer-no })
+
+## Balance and withdrawal
+
+# Placed before or after amounts as languages.toml says
+currency-symbol = $
+current-balance = Ihr aktueller Kontostand beträgt: { $amount }
+enter-withdraw-amount = Geben Sie den Auszahlungsbetrag ein:
+invalid-amount = Ungültiger Betrag
+want-receipt = Möchten Sie einen Beleg?
+withdraw-success = Erfolgreich { $amount } abgehoben
+new-balance = Ihr neuer Kontostand beträgt: { $balance }
+insufficient-funds = Unzureichendes Guthaben
+cannot-dispense = Dieser Geldautomat kann diesen Betrag nicht auszahlen, bitte wählen Sie einen anderen
+amount-over-limit = Dieser Geldautomat zahlt höchstens { $limit } auf einmal aus
+printing-receipt = Beleg wird gedruckt...
+receipt =
+    === BELEG ===
+    Datum: { $date }
+    Abgehobener Betrag: { $amount }
+    Neuer Kontostand: { $balance }
+    =============
+receipt-stand-in =
+    === BELEG ===
+    Datum: { $date }
+    Abgehobener Betrag: { $amount }
+    Offline genehmigt
+    =============
+
+## Errors
+
+card-not-found = Karte nicht gefunden
+invalid-pin = Ungültige PIN
+invalid-card-number = Ungültige Kartennummer
+card-not-supported = Diese Karte wird nicht unterstützt
+card-expired = Ihre Karte ist abgelaufen
+card-blocked = Ihre Karte ist gesperrt. Bitte wenden Sie sich an Ihre Bank.
+card-retained = Ihre Karte wurde einbehalten. Bitte wenden Sie sich an Ihre Bank.
+server-error = Serverfehler
+unexpected-response = Unerwartete Antwort vom Server
+bank-not-responding = Die Bank antwortet nicht.
+out-of-service = Vorübergehend außer Betrieb. Bitte warten...
+back-in-service = Der Geldautomat ist wieder in Betrieb.
+session-ended = Ihre Sitzung wurde beendet. Bitte entnehmen Sie Ihre Karte.
+need-more-time = Benötigen Sie mehr Zeit?
+session-timed-out = Ihre Sitzung ist abgelaufen. Bitte entnehmen Sie Ihre Karte.
+withdrawal-unconfirmed = Ihre Auszahlung konnte nicht bestätigt werden, daher wurde sie storniert und es wurde kein Bargeld ausgegeben. Ein von Ihrem Konto abgebuchter Betrag wird erstattet.
+bank-unavailable = Die Bank ist nicht erreichbar. Es sind nur Auszahlungen bis { $limit } möglich.
+stand-in-over-limit = Die Bank ist nicht erreichbar. Auszahlungen sind bis dahin auf { $limit } begrenzt.
+stand-in-approved = Ihr Kontostand wird aktualisiert, sobald die Bank wieder erreichbar ist.
+
+## Full-screen UI
+
+soft-key-yes = Ja
+soft-key-no = Nein
+soft-key-cancel = Abbrechen
+soft-key-confirm = Bestätigen
+soft-key-continue = Weiter
+take-card = Bitte entnehmen Sie Ihre Karte.
+keypad-title = Tastatur
+card-slot-title = Kartenschlitz
+receipt-title = Beleg
+card-slot-empty = Karte einführen
+card-slot-inserted = Karte { $card }
+card-slot-retained = Karte einbehalten
+card-slot-ejected = Karte ausgegeben
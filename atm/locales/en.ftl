@@ -0,0 +1,100 @@
+## Language selection
+
+welcome = Welcome to the ATM System
+select-language = Select language
+invalid-language-choice = Invalid choice, defaulting to { $language }
+
+## Card
+
+available-card-keys = Available card keys for testing:
+test-card = { $index }. { $key } (Card: { $card }, PIN: { $pin })
+enter-card-key = Enter your card key:
+enter-pin = Enter your PIN:
+card-key-validated = Card key validated successfully
+invalid-card-key = Invalid card key
+card-validation-failed = Card validation failed. Please take your card.
+card-cancelled = Cancelled. Please take your card.
+card-attempts-left = Please try again. Attempts left: { $attempts }
+using-card = You are using card { $card }
+
+## Menu
+
+select-option = Select an option:
+menu-check-balance = Check Balance
+menu-withdraw = Withdraw Money
+menu-change-language = Change language
+menu-exit = Exit
+invalid-option = Invalid option. Please try again.
+goodbye = Thank you for using our ATM. Goodbye!
+
+## Questions
+
+# What the customer types to answer yes or no
+answer-yes = y
+answer-no = n
+yes-no = ({ answer-yes }/{ answer-no })
+
+## Balance and withdrawal
+
+# Placed before or after amounts as languages.toml says
+currency-symbol = $
+current-balance = Your current balance is: { $amount }
+enter-withdraw-amount = Enter amount to withdraw:
+invalid-amount = Invalid amount
+want-receipt = Do you want a receipt?
+withdraw-success = Successfully withdrew { $amount }
+new-balance = Your new balance is: { $balance }
+insufficient-funds = Insufficient funds
+cannot-dispense = This ATM cannot pay out that amount, please choose another
+amount-over-limit = This ATM pays out at most { $limit } at a time
+printing-receipt = Printing receipt...
+receipt =
+    === RECEIPT ===
+    Date: { $date }
+    Withdraw Amount: { $amount }
+    New Balance: { $balance }
+    ==============
+receipt-stand-in =
+    === RECEIPT ===
+    Date: { $date }
+    Withdraw Amount: { $amount }
+    Approved offline
+    ==============
+
+## Errors
+
+card-not-found = Card not found
+invalid-pin = Invalid PIN
+invalid-card-number = Invalid card number
+card-not-supported = This card is not supported
+card-expired = Your card has expired
+card-blocked = Your card is blocked. Please contact your bank.
+card-retained = Your card has been retained. Please contact your bank.
+server-error = Server error
+unexpected-response = Unexpected response from server
+bank-not-responding = The bank is not responding.
+out-of-service = Temporarily out of service. Please wait...
+back-in-service = The ATM is back in service.
+session-ended = Your session has ended. Please take your card.
+need-more-time = Do you need more time?
+session-timed-out = Your session has timed out. Please take your card.
+withdrawal-unconfirmed = Your withdrawal could not be confirmed, so it has been cancelled and no cash was dispensed. Any amount taken from your account will be returned.
+bank-unavailable = The bank cannot be reached. Only withdrawals of up to { $limit } are available.
+stand-in-over-limit = The bank cannot be reached. Withdrawals are limited to { $limit } until it is back.
+stand-in-approved = Your balance will be updated when the bank is reachable again.
+
+## Full-screen UI
+
+soft-key-yes = Yes
+soft-key-no = No
+soft-key-cancel = Cancel
+soft-key-confirm = Confirm
+soft-key-continue = Continue
+take-card = Please take your card.
+keypad-title = Keypad
+card-slot-title = Card slot
+receipt-title = Receipt
+card-slot-empty = Insert card
+card-slot-inserted = Card { $card }
+card-slot-retained = Card retained
+card-slot-ejected = Card ejected
@@ -0,0 +1,100 @@
+## Language selection
+
+welcome = Bun venit în sistemul de bancomate
+select-language = Selectați limba
+invalid-language-choice = Opțiune invalidă, se folosește { $language }
+
+## Card
+
+available-card-keys = Chei de card disponibile pentru testare:
+test-card = { $index }. { $key } (Card: { $card }, PIN: { $pin })
+enter-card-key = Introduceți cheia cardului:
+enter-pin = Introduceți codul PIN:
+card-key-validated = Cheia cardului a fost validată cu succes
+invalid-card-key = Cheie de card invalidă
+card-validation-failed = Validarea cardului a eșuat. Vă rugăm să vă luați cardul.
+card-cancelled = Anulat. Vă rugăm să vă luați cardul.
+card-attempts-left = Vă rugăm să încercați din nou. Încercări rămase: { $attempts }
+using-card = Folosiți cardul { $card }
+
+## Menu
+
+select-option = Selectați o opțiune:
+menu-check-balance = Verificare sold
+menu-withdraw = Retragere numerar
+menu-change-language = Schimbă limba (Change language)
+menu-exit = Ieșire
+invalid-option = Opțiune invalidă. Vă rugăm să încercați din nou.
+goodbye = Vă mulțumim că ați folosit bancomatul nostru. La revedere!
+
+## Questions
+
+# What the customer types to answer yes or no
+answer-yes = d
+answer-no = n
+yes-no = ({ answer-yes }/{ answer-no })
+
+## Balance and withdrawal
+
+# Placed before or after amounts as languages.toml says
+currency-symbol = $
+current-balance = Soldul dumneavoastră curent este: { $amount }
+enter-withdraw-amount = Introduceți suma de retras:
+invalid-amount = Sumă invalidă
+want-receipt = Doriți o chitanță?
+withdraw-success = Ați retras cu succes { $amount }
+new-balance = Noul dumneavoastră sold este: { $balance }
+insufficient-funds = Fonduri insuficiente
+cannot-dispense = Acest bancomat nu poate plăti această sumă, vă rugăm alegeți alta
+amount-over-limit = Acest bancomat plătește cel mult { $limit } o dată
+printing-receipt = Se tipărește chitanța...
+receipt =
+    === CHITANȚĂ ===
+    Data: { $date }
+    Sumă retrasă: { $amount }
+    Sold nou: { $balance }
+    ================
+receipt-stand-in =
+    === CHITANȚĂ ===
+    Data: { $date }
+    Sumă retrasă: { $amount }
+    Aprobat offline
+    ================
+
+## Errors
+
+card-not-found = Cardul nu a fost găsit
+invalid-pin = PIN invalid
+invalid-card-number = Număr de card invalid
+card-not-supported = Acest card nu este acceptat
+card-expired = Cardul dumneavoastră a expirat
+card-blocked = Cardul dumneavoastră este blocat. Vă rugăm să contactați banca.
+card-retained = Cardul dumneavoastră a fost reținut. Vă rugăm să contactați banca.
+server-error = Eroare de server
+unexpected-response = Răspuns neașteptat de la server
+bank-not-responding = Banca nu răspunde.
+out-of-service = Temporar scos din funcțiune. Vă rugăm așteptați...
+back-in-service = Bancomatul funcționează din nou.
+session-ended = Sesiunea dvs. s-a încheiat. Vă rugăm să vă luați cardul.
+need-more-time = Aveți nevoie de mai mult timp?
+session-timed-out = Sesiunea dvs. a expirat. Vă rugăm să vă luați cardul.
+withdrawal-unconfirmed = Retragerea nu a putut fi confirmată, așa că a fost anulată și nu s-au eliberat bani. Orice sumă luată din contul dvs. va fi returnată.
+bank-unavailable = Banca nu poate fi contactată. Sunt disponibile doar retrageri de până la { $limit }.
+stand-in-over-limit = Banca nu poate fi contactată. Retragerile sunt limitate la { $limit } până când revine.
+stand-in-approved = Soldul dvs. va fi actualizat când banca va fi din nou disponibilă.
+
+## Full-screen UI
+
+soft-key-yes = Da
+soft-key-no = Nu
+soft-key-cancel = Anulare
+soft-key-confirm = Confirmare
+soft-key-continue = Continuare
+take-card = Vă rugăm să vă luați cardul.
+keypad-title = Tastatură
+card-slot-title = Fantă card
+receipt-title = Chitanță
+card-slot-empty = Introduceți cardul
+card-slot-inserted = Card { $card }
+card-slot-retained = Card reținut
+card-slot-ejected = Card eliberat
@@ -0,0 +1,167 @@
+use crate::cassette::Cassettes;
+use crate::config::Config;
+use chrono::Local;
+use common::{Command, Endpoint, Response, Stream, receive_response, send_command};
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+const FIRST_RETRY: Duration = Duration::from_secs(1);
+const MAX_RETRY: Duration = Duration::from_secs(30);
+
+// Why a request got no answer
+#[derive(Debug)]
+pub enum RequestError {
+    // The bank never got the command, so it can be sent again
+    NotSent(io::Error),
+    // The connection broke while waiting; the bank may have carried the
+    // command out
+    NoResponse(io::Error),
+    // The bank did not answer within the request timeout; it too may have
+    // carried the command out
+    TimedOut(io::Error),
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RequestError::NotSent(e) => write!(f, "not sent: {}", e),
+            RequestError::NoResponse(e) => write!(f, "no response: {}", e),
+            RequestError::TimedOut(e) => write!(f, "timed out: {}", e),
+        }
+    }
+}
+
+impl From<RequestError> for io::Error {
+    fn from(error: RequestError) -> Self {
+        match error {
+            RequestError::NotSent(e) | RequestError::NoResponse(e) | RequestError::TimedOut(e) => e,
+        }
+    }
+}
+
+// Connection to the bank server shared by every ATM front-end. A failed
+// request drops the connection and `connect` makes a new one
+pub struct BankClient {
+    endpoint: Endpoint,
+    terminal_id: String,
+    location: String,
+    // Longest wait for each send and each answer
+    timeout: Duration,
+    stream: Option<Stream>,
+}
+
+impl BankClient {
+    pub fn new(config: &Config) -> Self {
+        BankClient {
+            endpoint: config.endpoint(),
+            terminal_id: config.terminal_id.clone(),
+            location: config.location.clone(),
+            timeout: Duration::from_secs(config.request_timeout),
+            stream: None,
+        }
+    }
+
+    // Connects and registers the terminal; the bank hangs up on terminals it
+    // does not know
+    pub fn connect(&mut self) -> io::Result<()> {
+        let stream = Stream::connect(&self.endpoint)?;
+        stream.set_timeouts(Some(self.timeout), Some(self.timeout))?;
+        self.stream = Some(stream);
+        let command = Command::RegisterTerminal {
+            terminal_id: self.terminal_id.clone(),
+            location: self.location.clone(),
+        };
+        let result = match self.request(&command)? {
+            Response::TerminalRegistered => Ok(()),
+            Response::ErrorUnknownTerminal => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "terminal {} is not registered with the bank",
+                    self.terminal_id
+                ),
+            )),
+            other => Err(io::Error::other(format!(
+                "unexpected response to registration: {:?}",
+                other
+            ))),
+        };
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    pub fn terminal_id(&self) -> &str {
+        &self.terminal_id
+    }
+
+    // Id for a withdrawal or advice, unique for this terminal
+    pub fn next_id(&self) -> String {
+        format!(
+            "{}-{}",
+            self.terminal_id,
+            Local::now().format("%Y%m%d%H%M%S%9f")
+        )
+    }
+
+    pub fn request(&mut self, command: &Command) -> Result<Response, RequestError> {
+        let Some(stream) = self.stream.as_mut() else {
+            return Err(RequestError::NotSent(io::ErrorKind::NotConnected.into()));
+        };
+        // A send that timed out left at most part of the command, which the
+        // bank drops along with the connection
+        if let Err(e) = send_command(stream, command) {
+            self.stream = None;
+            return Err(RequestError::NotSent(e));
+        }
+        // A late answer would be taken for the next command's, so the
+        // connection is given up
+        receive_response(stream).map_err(|e| {
+            self.stream = None;
+            match e.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => RequestError::TimedOut(e),
+                _ => RequestError::NoResponse(e),
+            }
+        })
+    }
+
+    // Each report carries the whole day's counts, so one that is lost is made
+    // good by the next
+    pub fn report_cassettes(&mut self, cassettes: &Cassettes) -> io::Result<()> {
+        let command = Command::ReportCassettes {
+            business_date: cassettes.business_date(),
+            cassettes: cassettes.counts().to_vec(),
+        };
+        match self.request(&command)? {
+            Response::CassettesRecorded => Ok(()),
+            other => Err(io::Error::other(format!(
+                "bank did not record cassettes: {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+// Delay before each attempt to reach the bank again, doubling up to a limit
+pub struct Backoff {
+    delay: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff { delay: FIRST_RETRY }
+    }
+}
+
+impl Backoff {
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(MAX_RETRY);
+        delay
+    }
+}
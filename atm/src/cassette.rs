@@ -0,0 +1,138 @@
+use chrono::{Local, NaiveDate};
+use common::{CassetteCount, write_json_atomic};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+// Cassettes of a freshly loaded ATM: (denomination, notes)
+const DEFAULT_CASSETTES: [(u32, u32); 4] = [(50, 200), (20, 500), (10, 500), (5, 500)];
+
+#[derive(Serialize, Deserialize)]
+struct CassetteState {
+    business_date: NaiveDate,
+    cassettes: Vec<CassetteCount>,
+}
+
+// The note cassettes of this ATM, kept in cassettes-<terminal id>.json so
+// the counts survive restarts
+pub struct Cassettes {
+    path: PathBuf,
+    state: CassetteState,
+}
+
+impl Cassettes {
+    pub fn load(data_dir: &Path, terminal_id: &str) -> io::Result<Self> {
+        let path = data_dir.join(format!("cassettes-{}.json", terminal_id));
+        let state = if path.exists() {
+            let reader = BufReader::new(File::open(&path)?);
+            serde_json::from_reader(reader).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid {}: {}", path.display(), e),
+                )
+            })?
+        } else {
+            CassetteState {
+                business_date: Local::now().date_naive(),
+                cassettes: DEFAULT_CASSETTES
+                    .iter()
+                    .map(|&(denomination, remaining)| CassetteCount {
+                        denomination,
+                        remaining,
+                        dispensed: 0,
+                    })
+                    .collect(),
+            }
+        };
+        let mut cassettes = Cassettes { path, state };
+        cassettes.roll_over();
+        cassettes.save()?;
+        Ok(cassettes)
+    }
+
+    pub fn business_date(&self) -> NaiveDate {
+        self.state.business_date
+    }
+
+    pub fn counts(&self) -> &[CassetteCount] {
+        &self.state.cassettes
+    }
+
+    // Notes to take from each cassette to pay out `amount`, or None when the
+    // notes left cannot make it up exactly
+    pub fn plan(&self, amount: f64) -> Option<Vec<u32>> {
+        let cents = (amount * 100.0).round() as i64;
+        if cents <= 0 || cents % 100 != 0 {
+            return None;
+        }
+
+        // Largest notes first, so the first plan found uses the fewest notes
+        let mut order: Vec<usize> = (0..self.state.cassettes.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(self.state.cassettes[i].denomination));
+        let mut plan = vec![0; self.state.cassettes.len()];
+        self.search(&order, cents / 100, &mut plan).then_some(plan)
+    }
+
+    fn search(&self, order: &[usize], rest: i64, plan: &mut [u32]) -> bool {
+        // Give up early when the cassettes left hold too little or only notes
+        // that cannot add up to the rest
+        let cassettes = || order.iter().map(|&i| &self.state.cassettes[i]);
+        let capacity: i64 = cassettes()
+            .map(|cassette| cassette.denomination as i64 * cassette.remaining as i64)
+            .sum();
+        let step = cassettes()
+            .filter(|cassette| cassette.remaining > 0)
+            .fold(0, |step, cassette| gcd(step, cassette.denomination as i64));
+        if rest == 0 {
+            return true;
+        }
+        if rest > capacity || step == 0 || rest % step != 0 {
+            return false;
+        }
+
+        let (&i, order) = order.split_first().unwrap();
+        let cassette = &self.state.cassettes[i];
+        if cassette.denomination == 0 {
+            return self.search(order, rest, plan);
+        }
+        let most = (rest / cassette.denomination as i64).min(cassette.remaining as i64);
+        for notes in (0..=most).rev() {
+            plan[i] = notes as u32;
+            if self.search(order, rest - notes * cassette.denomination as i64, plan) {
+                return true;
+            }
+        }
+        plan[i] = 0;
+        false
+    }
+
+    // Takes the planned notes out of the cassettes
+    pub fn dispense(&mut self, plan: &[u32]) -> io::Result<()> {
+        self.roll_over();
+        for (cassette, &notes) in self.state.cassettes.iter_mut().zip(plan) {
+            cassette.remaining -= notes;
+            cassette.dispensed += notes;
+        }
+        self.save()
+    }
+
+    // Starts counting dispensed notes afresh on a new business day
+    fn roll_over(&mut self) {
+        let today = Local::now().date_naive();
+        if self.state.business_date != today {
+            self.state.business_date = today;
+            for cassette in &mut self.state.cassettes {
+                cassette.dispensed = 0;
+            }
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        write_json_atomic(&self.path, &self.state)
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
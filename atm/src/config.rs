@@ -0,0 +1,282 @@
+use clap::Args;
+use common::{Endpoint, SOCKET_PATH, Settings, env_var, parse_env};
+use serde::Deserialize;
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+use tracing_subscriber::EnvFilter;
+
+// Read from the working directory when --config is not given
+pub const CONFIG_FILE: &str = "atm.toml";
+
+// Settings of one ATM. Each one comes from, in increasing priority, the
+// default, atm.toml, an ATM_* environment variable and a command-line flag
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub socket_path: PathBuf,
+    // host:port of the bank; when set the ATM connects over TCP instead of
+    // the Unix socket
+    pub bank_address: Option<String>,
+    pub terminal_id: String,
+    pub location: String,
+    // Language shown to each new customer; languages.toml's default otherwise
+    pub language: Option<String>,
+    // Catalogs to use instead of the ones built into the ATM
+    pub locales_dir: Option<PathBuf>,
+    // Where the cassette counts, the outbox and the log are kept
+    pub data_dir: PathBuf,
+    // Filter in the RUST_LOG syntax, e.g. "debug"
+    pub log_level: String,
+    pub max_withdrawal: f64,
+    // Most a card may take out while the bank is unreachable, until the bank
+    // has been told; 0 turns stand-in off
+    pub stand_in_limit: f64,
+    // Seconds to wait for the bank to take a command and to answer it
+    pub request_timeout: u64,
+    // Seconds a customer with a card in may leave the ATM waiting before
+    // being asked whether they need more time
+    pub idle_timeout: u64,
+    // Seconds to answer that question before the session ends
+    pub more_time_timeout: u64,
+    // Card keys a customer may try before the card is returned
+    pub card_attempts: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            socket_path: PathBuf::from(SOCKET_PATH),
+            bank_address: None,
+            terminal_id: "ATM-0001".to_string(),
+            location: "Head office".to_string(),
+            language: None,
+            locales_dir: None,
+            data_dir: PathBuf::from("."),
+            log_level: "info".to_string(),
+            max_withdrawal: 1000.0,
+            stand_in_limit: 0.0,
+            request_timeout: 30,
+            idle_timeout: 30,
+            more_time_timeout: 15,
+            card_attempts: 3,
+        }
+    }
+}
+
+// Command-line flags, each overriding the setting of the same name
+#[derive(Debug, Default, Args)]
+pub struct Overrides {
+    /// Unix socket of the bank
+    #[arg(long, value_name = "PATH")]
+    pub socket_path: Option<PathBuf>,
+    /// Connect to the bank over TCP at this address instead
+    #[arg(long, value_name = "HOST:PORT")]
+    pub bank_address: Option<String>,
+    /// Id the bank knows this ATM by
+    #[arg(long, value_name = "ID")]
+    pub terminal_id: Option<String>,
+    /// Where this ATM stands
+    #[arg(long)]
+    pub location: Option<String>,
+    /// Default language code, e.g. "bg"
+    #[arg(long, value_name = "CODE")]
+    pub language: Option<String>,
+    /// Directory with languages.toml and message catalogs to use instead of the built-in ones
+    #[arg(long, value_name = "DIR")]
+    pub locales_dir: Option<PathBuf>,
+    /// Directory for the cassette counts, outbox and log
+    #[arg(long, value_name = "DIR")]
+    pub data_dir: Option<PathBuf>,
+    /// Log filter, e.g. "debug" or "atm=debug"
+    #[arg(long, value_name = "FILTER")]
+    pub log_level: Option<String>,
+    /// Largest amount this ATM pays out at once
+    #[arg(long, value_name = "AMOUNT")]
+    pub max_withdrawal: Option<f64>,
+    /// Most a card may withdraw while the bank is unreachable; 0 is off
+    #[arg(long, value_name = "AMOUNT")]
+    pub stand_in_limit: Option<f64>,
+    /// Seconds to wait for the bank to answer
+    #[arg(long, value_name = "SECONDS")]
+    pub request_timeout: Option<u64>,
+    /// Seconds of inactivity before asking the customer if they need more time
+    #[arg(long, value_name = "SECONDS")]
+    pub idle_timeout: Option<u64>,
+    /// Seconds to answer before the session ends
+    #[arg(long, value_name = "SECONDS")]
+    pub more_time_timeout: Option<u64>,
+    /// Card keys a customer may try before the card is returned
+    #[arg(long, value_name = "COUNT")]
+    pub card_attempts: Option<u32>,
+}
+
+impl Settings for Config {
+    const FILE: &'static str = CONFIG_FILE;
+
+    type Overrides = Overrides;
+
+    fn apply_env(&mut self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(value) = env_var("ATM_SOCKET_PATH") {
+            self.socket_path = value.into();
+        }
+        if let Some(value) = env_var("ATM_BANK_ADDRESS") {
+            self.bank_address = Some(value);
+        }
+        if let Some(value) = env_var("ATM_TERMINAL_ID") {
+            self.terminal_id = value;
+        }
+        if let Some(value) = env_var("ATM_TERMINAL_LOCATION") {
+            self.location = value;
+        }
+        if let Some(value) = env_var("ATM_LANGUAGE") {
+            self.language = Some(value);
+        }
+        if let Some(value) = env_var("ATM_LOCALES_DIR") {
+            self.locales_dir = Some(value.into());
+        }
+        if let Some(value) = env_var("ATM_DATA_DIR") {
+            self.data_dir = value.into();
+        }
+        if let Some(value) = env_var("ATM_LOG") {
+            self.log_level = value;
+        }
+        parse_env(
+            "ATM_MAX_WITHDRAWAL",
+            &mut self.max_withdrawal,
+            &mut problems,
+        );
+        parse_env(
+            "ATM_STAND_IN_LIMIT",
+            &mut self.stand_in_limit,
+            &mut problems,
+        );
+        parse_env(
+            "ATM_REQUEST_TIMEOUT",
+            &mut self.request_timeout,
+            &mut problems,
+        );
+        parse_env("ATM_IDLE_TIMEOUT", &mut self.idle_timeout, &mut problems);
+        parse_env(
+            "ATM_MORE_TIME_TIMEOUT",
+            &mut self.more_time_timeout,
+            &mut problems,
+        );
+        parse_env("ATM_CARD_ATTEMPTS", &mut self.card_attempts, &mut problems);
+        problems
+    }
+
+    fn apply(&mut self, overrides: &Overrides) {
+        if let Some(value) = &overrides.socket_path {
+            self.socket_path = value.clone();
+        }
+        if let Some(value) = &overrides.bank_address {
+            self.bank_address = Some(value.clone());
+        }
+        if let Some(value) = &overrides.terminal_id {
+            self.terminal_id = value.clone();
+        }
+        if let Some(value) = &overrides.location {
+            self.location = value.clone();
+        }
+        if let Some(value) = &overrides.language {
+            self.language = Some(value.clone());
+        }
+        if let Some(value) = &overrides.locales_dir {
+            self.locales_dir = Some(value.clone());
+        }
+        if let Some(value) = &overrides.data_dir {
+            self.data_dir = value.clone();
+        }
+        if let Some(value) = &overrides.log_level {
+            self.log_level = value.clone();
+        }
+        if let Some(value) = overrides.max_withdrawal {
+            self.max_withdrawal = value;
+        }
+        if let Some(value) = overrides.stand_in_limit {
+            self.stand_in_limit = value;
+        }
+        if let Some(value) = overrides.request_timeout {
+            self.request_timeout = value;
+        }
+        if let Some(value) = overrides.idle_timeout {
+            self.idle_timeout = value;
+        }
+        if let Some(value) = overrides.more_time_timeout {
+            self.more_time_timeout = value;
+        }
+        if let Some(value) = overrides.card_attempts {
+            self.card_attempts = value;
+        }
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(address) = &self.bank_address
+            && address.to_socket_addrs().is_err()
+        {
+            problems.push(format!("bank_address \"{}\" is not host:port", address));
+        }
+        // The same rule the bank applies when a terminal is added
+        if self.terminal_id.is_empty()
+            || !self
+                .terminal_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            problems.push(format!(
+                "terminal_id \"{}\" may only contain letters, digits, '-' and '_'",
+                self.terminal_id
+            ));
+        }
+        if let Some(dir) = &self.locales_dir
+            && !dir.is_dir()
+        {
+            problems.push(format!("locales_dir {} is not a directory", dir.display()));
+        }
+        if !self.data_dir.is_dir() {
+            problems.push(format!(
+                "data_dir {} is not a directory",
+                self.data_dir.display()
+            ));
+        }
+        if let Err(e) = EnvFilter::try_new(&self.log_level) {
+            problems.push(format!("log_level \"{}\": {}", self.log_level, e));
+        }
+        if !(self.max_withdrawal.is_finite() && self.max_withdrawal > 0.0) {
+            problems.push(format!(
+                "max_withdrawal {} must be a positive amount",
+                self.max_withdrawal
+            ));
+        }
+        if !(self.stand_in_limit.is_finite() && self.stand_in_limit >= 0.0) {
+            problems.push(format!(
+                "stand_in_limit {} must be 0 or a positive amount",
+                self.stand_in_limit
+            ));
+        }
+        if self.request_timeout == 0 {
+            problems.push("request_timeout must be at least 1 second".to_string());
+        }
+        if self.idle_timeout == 0 {
+            problems.push("idle_timeout must be at least 1 second".to_string());
+        }
+        if self.more_time_timeout == 0 {
+            problems.push("more_time_timeout must be at least 1 second".to_string());
+        }
+        if self.card_attempts == 0 {
+            problems.push("card_attempts must be at least 1".to_string());
+        }
+        problems
+    }
+}
+
+impl Config {
+    pub fn endpoint(&self) -> Endpoint {
+        match &self.bank_address {
+            Some(address) => Endpoint::Tcp(address.clone()),
+            None => Endpoint::Unix(self.socket_path.clone()),
+        }
+    }
+}
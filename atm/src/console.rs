@@ -0,0 +1,118 @@
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+// What the line-mode ATM is waiting for the customer to type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prompt {
+    Language,
+    CardKey,
+    Pin,
+    Menu,
+    Amount,
+    Receipt,
+    MoreTime,
+}
+
+// Why no line came
+#[derive(Debug)]
+pub enum ReadError {
+    TimedOut,
+    Closed,
+    // The customer pressed a cancel key, on front-ends that have one
+    Cancelled,
+}
+
+// What the card reader holds, for front-ends that show it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CardSlot {
+    Empty,
+    // With the masked card number
+    Inserted(String),
+    Ejected,
+    Retained,
+}
+
+// Where the ATM reads what the customer types
+pub trait Input {
+    // The next line, trimmed, waiting at most `timeout` if one is given
+    fn read_line(&mut self, prompt: Prompt, timeout: Option<Duration>)
+    -> Result<String, ReadError>;
+
+    // Waits while nothing is asked of the customer, such as between attempts
+    // to reach the bank
+    fn pause(&mut self, duration: Duration) -> Result<(), ReadError> {
+        thread::sleep(duration);
+        Ok(())
+    }
+}
+
+// Where the ATM shows its screens
+pub trait Output {
+    fn write(&mut self, text: &str);
+
+    // The choices of the question that follows, answered with their number
+    fn menu(&mut self, options: &[String]) {
+        for (i, option) in options.iter().enumerate() {
+            self.write(&format!("{}. {}\n", i + 1, option));
+        }
+    }
+
+    fn receipt(&mut self, text: &str) {
+        self.write(&format!("{}\n", text));
+    }
+
+    // Front-ends with text of their own follow the customer's language
+    fn language(&mut self, _code: &str) {}
+
+    fn card_slot(&mut self, _slot: CardSlot) {}
+}
+
+// Lines typed at the terminal, read on their own thread so a wait can time out
+pub struct Stdin {
+    lines: Receiver<String>,
+}
+
+impl Stdin {
+    pub fn new() -> Self {
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Stdin { lines }
+    }
+}
+
+impl Input for Stdin {
+    fn read_line(
+        &mut self,
+        _prompt: Prompt,
+        timeout: Option<Duration>,
+    ) -> Result<String, ReadError> {
+        let line = match timeout {
+            Some(timeout) => self.lines.recv_timeout(timeout).map_err(|e| match e {
+                RecvTimeoutError::Timeout => ReadError::TimedOut,
+                RecvTimeoutError::Disconnected => ReadError::Closed,
+            })?,
+            None => self.lines.recv().map_err(|_| ReadError::Closed)?,
+        };
+        Ok(line.trim().to_string())
+    }
+}
+
+pub struct Stdout;
+
+impl Output for Stdout {
+    fn write(&mut self, text: &str) {
+        print!("{}", text);
+        io::stdout().flush().unwrap();
+    }
+}
@@ -0,0 +1,449 @@
+use chrono::{DateTime, TimeZone};
+use fluent_bundle::{FluentArgs, FluentBundle, FluentResource};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use unic_langid::LanguageIdentifier;
+
+pub const LANGUAGES_FILE: &str = "languages.toml";
+
+// The catalogs built into the ATM, used unless locales_dir is set
+const BUILT_IN: &[(&str, &str)] = &[
+    (LANGUAGES_FILE, include_str!("../locales/languages.toml")),
+    ("bg.ftl", include_str!("../locales/bg.ftl")),
+    ("de.ftl", include_str!("../locales/de.ftl")),
+    ("en.ftl", include_str!("../locales/en.ftl")),
+    ("ro.ftl", include_str!("../locales/ro.ftl")),
+];
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CurrencyPosition {
+    Before,
+    After,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Language {
+    pub code: String,
+    pub name: String,
+    pub decimal_separator: String,
+    pub group_separator: String,
+    pub currency_position: CurrencyPosition,
+    pub date_format: String,
+}
+
+#[derive(Deserialize)]
+struct LanguageList {
+    default: String,
+    language: Vec<Language>,
+}
+
+// Message catalogs for every configured language plus the current selection
+pub struct I18n {
+    languages: Vec<Language>,
+    bundles: HashMap<String, FluentBundle<FluentResource>>,
+    default: usize,
+    current: usize,
+}
+
+impl I18n {
+    // From the files in `dir`, or the catalogs built into the ATM
+    pub fn load(dir: Option<&Path>) -> io::Result<Self> {
+        let (path, contents) = read_file(dir, LANGUAGES_FILE)?;
+        let list: LanguageList = toml::from_str(&contents)
+            .map_err(|e| invalid_data(format!("Invalid {}: {}", path, e)))?;
+
+        let default = list
+            .language
+            .iter()
+            .position(|language| language.code == list.default)
+            .ok_or_else(|| {
+                invalid_data(format!(
+                    "Default language '{}' is not listed in {}",
+                    list.default, path
+                ))
+            })?;
+
+        let mut bundles = HashMap::new();
+        for language in &list.language {
+            bundles.insert(language.code.clone(), load_bundle(dir, &language.code)?);
+        }
+
+        Ok(I18n {
+            languages: list.language,
+            bundles,
+            default,
+            current: default,
+        })
+    }
+
+    pub fn languages(&self) -> &[Language] {
+        &self.languages
+    }
+
+    pub fn current(&self) -> &Language {
+        &self.languages[self.current]
+    }
+
+    pub fn default_language(&self) -> &Language {
+        &self.languages[self.default]
+    }
+
+    pub fn select(&mut self, index: usize) -> bool {
+        if index < self.languages.len() {
+            self.current = index;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Makes the language with this code the one each customer starts in
+    pub fn set_default(&mut self, code: &str) -> bool {
+        match self
+            .languages
+            .iter()
+            .position(|language| language.code == code)
+        {
+            Some(index) => {
+                self.default = index;
+                self.current = index;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.default;
+    }
+
+    // Selection prompt shown in every language, e.g. "Select language / Изберете език:"
+    pub fn language_prompt(&self) -> String {
+        let prompts: Vec<String> = self
+            .languages
+            .iter()
+            .map(|language| self.format_in(&language.code, "select-language", &[]))
+            .collect();
+        format!("{}:", prompts.join(" / "))
+    }
+
+    // Formats an amount with the current language's separators and currency placement
+    pub fn format_amount(&self, amount: f64) -> String {
+        let language = self.current();
+        let cents = (amount.abs() * 100.0).round() as u64;
+        let whole = (cents / 100).to_string();
+
+        let mut number = String::new();
+        if amount < 0.0 && cents > 0 {
+            number.push('-');
+        }
+        for (i, digit) in whole.chars().enumerate() {
+            if i > 0 && (whole.len() - i).is_multiple_of(3) {
+                number.push_str(&language.group_separator);
+            }
+            number.push(digit);
+        }
+        number.push_str(&language.decimal_separator);
+        number.push_str(&format!("{:02}", cents % 100));
+
+        let symbol = self.text("currency-symbol");
+        match language.currency_position {
+            CurrencyPosition::Before => match number.strip_prefix('-') {
+                Some(number) => format!("-{}{}", symbol, number),
+                None => format!("{}{}", symbol, number),
+            },
+            CurrencyPosition::After => format!("{} {}", number, symbol),
+        }
+    }
+
+    // Parses an amount typed by the customer with the language's separators.
+    // Group separators must sit between groups of three digits, so "5.000" is
+    // five thousand in German and "12.50" is refused there rather than misread.
+    // Only digits and at most two of them after the decimal separator are
+    // taken, so nothing reaches the bank that it would have to round
+    pub fn parse_amount(&self, input: &str) -> Option<f64> {
+        let language = self.current();
+        let input = input.trim();
+        let (whole, fraction) = match input.split_once(language.decimal_separator.as_str()) {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (input, None),
+        };
+        let digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+
+        let groups: Vec<&str> = if language.group_separator.is_empty() {
+            vec![whole]
+        } else {
+            whole.split(language.group_separator.as_str()).collect()
+        };
+        let first = groups[0];
+        if !digits(first) || (groups.len() > 1 && first.len() > 3) {
+            return None;
+        }
+        let mut number = first.to_string();
+        for group in &groups[1..] {
+            if group.len() != 3 || !digits(group) {
+                return None;
+            }
+            number.push_str(group);
+        }
+
+        let mut cents = number.parse::<u64>().ok()?.checked_mul(100)?;
+        if let Some(fraction) = fraction {
+            if !digits(fraction) || fraction.len() > 2 {
+                return None;
+            }
+            cents = cents.checked_add(format!("{:0<2}", fraction).parse().ok()?)?;
+        }
+        Some(cents as f64 / 100.0)
+    }
+
+    pub fn format_timestamp<Tz: TimeZone>(&self, timestamp: &DateTime<Tz>) -> String
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        timestamp.format(&self.current().date_format).to_string()
+    }
+
+    // The catalog's answer-yes, typed in either case
+    pub fn is_yes(&self, answer: &str) -> bool {
+        answer.trim().to_lowercase() == self.text("answer-yes").to_lowercase()
+    }
+
+    pub fn text(&self, id: &str) -> String {
+        self.format(id, &[])
+    }
+
+    pub fn format(&self, id: &str, args: &[(&str, String)]) -> String {
+        self.format_in(&self.current().code, id, args)
+    }
+
+    pub fn format_in(&self, code: &str, id: &str, args: &[(&str, String)]) -> String {
+        let mut fluent_args = FluentArgs::new();
+        for (name, value) in args {
+            fluent_args.set(*name, value.clone());
+        }
+
+        // Fall back to the default language, then to the message id itself
+        let default_code = &self.default_language().code;
+        for code in [code, default_code] {
+            let Some(bundle) = self.bundles.get(code) else {
+                continue;
+            };
+            let Some(pattern) = bundle.get_message(id).and_then(|message| message.value()) else {
+                continue;
+            };
+            let mut errors = Vec::new();
+            return bundle
+                .format_pattern(pattern, Some(&fluent_args), &mut errors)
+                .into_owned();
+        }
+        id.to_string()
+    }
+}
+
+// The file's contents and the name to give it in errors
+fn read_file(dir: Option<&Path>, name: &str) -> io::Result<(String, String)> {
+    match dir {
+        Some(dir) => {
+            let path = dir.join(name);
+            let contents = fs::read_to_string(&path).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("Failed to read {}: {}", path.display(), e),
+                )
+            })?;
+            Ok((path.display().to_string(), contents))
+        }
+        None => match BUILT_IN.iter().find(|(file, _)| *file == name) {
+            Some((_, contents)) => Ok((format!("built-in {}", name), contents.to_string())),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No built-in {}", name),
+            )),
+        },
+    }
+}
+
+fn load_bundle(dir: Option<&Path>, code: &str) -> io::Result<FluentBundle<FluentResource>> {
+    let (path, source) = read_file(dir, &format!("{}.ftl", code))?;
+    let resource = FluentResource::try_new(source)
+        .map_err(|(_, errors)| invalid_data(format!("Invalid {}: {:?}", path, errors)))?;
+
+    let langid: LanguageIdentifier = code
+        .parse()
+        .map_err(|e| invalid_data(format!("Invalid language code '{}': {}", code, e)))?;
+    let mut bundle = FluentBundle::new(vec![langid]);
+    // Unicode isolation marks around placeables only confuse terminals
+    bundle.set_use_isolating(false);
+    bundle
+        .add_resource(resource)
+        .map_err(|errors| invalid_data(format!("Invalid {}: {:?}", path, errors)))?;
+    Ok(bundle)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fluent_syntax::ast;
+    use std::collections::BTreeSet;
+
+    fn message_ids(code: &str) -> BTreeSet<String> {
+        let (_, source) = read_file(None, &format!("{}.ftl", code)).unwrap();
+        let resource = FluentResource::try_new(source).unwrap();
+        resource
+            .entries()
+            .filter_map(|entry| match entry {
+                ast::Entry::Message(message) => Some(message.id.name.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn catalogs_load() {
+        let i18n = I18n::load(None).unwrap();
+        assert_eq!(i18n.default_language().code, "en");
+        assert!(i18n.languages().len() >= 2);
+    }
+
+    #[test]
+    fn catalogs_contain_every_english_key() {
+        let i18n = I18n::load(None).unwrap();
+        let english = message_ids("en");
+
+        for language in i18n.languages() {
+            let ids = message_ids(&language.code);
+            let missing: Vec<_> = english.difference(&ids).collect();
+            assert!(
+                missing.is_empty(),
+                "{}.ftl is missing messages: {:?}",
+                language.code,
+                missing
+            );
+        }
+    }
+
+    #[test]
+    fn formats_placeholders_and_falls_back() {
+        let mut i18n = I18n::load(None).unwrap();
+        let args = [("card", "xxxx3456".to_string())];
+        assert_eq!(
+            i18n.format("using-card", &args),
+            "You are using card xxxx3456"
+        );
+
+        let bulgarian = i18n
+            .languages()
+            .iter()
+            .position(|language| language.code == "bg")
+            .unwrap();
+        assert!(i18n.select(bulgarian));
+        assert_eq!(
+            i18n.format("using-card", &args),
+            "Използвате карта xxxx3456"
+        );
+        assert_eq!(i18n.text("no-such-message"), "no-such-message");
+    }
+
+    #[test]
+    fn answers_yes_in_each_language() {
+        let mut i18n = I18n::load(None).unwrap();
+        let expected = [
+            ("en", "Y", "j"),
+            ("bg", "Д", "y"),
+            ("de", "j", "y"),
+            ("ro", "d", "y"),
+        ];
+        for (code, yes, not_yes) in expected {
+            let index = i18n
+                .languages()
+                .iter()
+                .position(|language| language.code == code)
+                .unwrap();
+            assert!(i18n.select(index));
+            assert!(i18n.is_yes(yes), "{}", code);
+            assert!(!i18n.is_yes(not_yes), "{}", code);
+            assert!(!i18n.is_yes(&i18n.text("answer-no")), "{}", code);
+        }
+    }
+
+    #[test]
+    fn formats_amounts_and_timestamps_per_locale() {
+        let mut i18n = I18n::load(None).unwrap();
+        let timestamp = chrono::Utc.with_ymd_and_hms(2024, 3, 9, 14, 5, 7).unwrap();
+        let expected = [
+            ("en", "$1,234,567.89", "-$5.00", "03/09/2024 02:05:07 PM"),
+            ("bg", "1 234 567,89 $", "-5,00 $", "09.03.2024 14:05:07"),
+            ("de", "1.234.567,89 $", "-5,00 $", "09.03.2024 14:05:07"),
+            ("ro", "1.234.567,89 $", "-5,00 $", "09.03.2024 14:05:07"),
+        ];
+
+        for (code, amount, negative, date) in expected {
+            let index = i18n
+                .languages()
+                .iter()
+                .position(|language| language.code == code)
+                .unwrap();
+            assert!(i18n.select(index));
+            assert_eq!(i18n.format_amount(1_234_567.891), amount);
+            assert_eq!(i18n.format_amount(-5.0), negative);
+            assert_eq!(i18n.format_timestamp(&timestamp), date);
+        }
+
+        assert_eq!(i18n.parse_amount("12,50"), Some(12.5));
+        assert_eq!(i18n.parse_amount("5.000"), Some(5000.0));
+        assert_eq!(i18n.parse_amount("1.234,50"), Some(1234.5));
+        assert_eq!(i18n.parse_amount("12.50"), None);
+        assert!(i18n.select(0));
+        assert_eq!(i18n.parse_amount("5,000.50"), Some(5000.5));
+        assert_eq!(i18n.parse_amount("12.50"), Some(12.5));
+        assert_eq!(i18n.parse_amount("1,23"), None);
+    }
+
+    #[test]
+    fn parses_only_amounts_in_cents() {
+        let mut i18n = I18n::load(None).unwrap();
+        // Each language's ten and a half, and more decimals than cents
+        let expected = [
+            ("en", "10.5", "10.555"),
+            ("bg", "10,5", "10,555"),
+            ("de", "10,5", "10,555"),
+            ("ro", "10,5", "10,555"),
+        ];
+        for (code, ten_and_a_half, too_precise) in expected {
+            let index = i18n
+                .languages()
+                .iter()
+                .position(|language| language.code == code)
+                .unwrap();
+            assert!(i18n.select(index));
+            assert_eq!(i18n.parse_amount(ten_and_a_half), Some(10.5), "{}", code);
+            assert_eq!(i18n.parse_amount(too_precise), None, "{}", code);
+            for input in ["1e2", "inf", "NaN", "-5", "+5", "", "12x", "0x10"] {
+                assert_eq!(i18n.parse_amount(input), None, "{} {:?}", code, input);
+            }
+            let decimal = i18n.current().decimal_separator.clone();
+            assert_eq!(
+                i18n.parse_amount(&format!("7{}", decimal)),
+                None,
+                "{}",
+                code
+            );
+            assert_eq!(
+                i18n.parse_amount(&format!("{}5", decimal)),
+                None,
+                "{}",
+                code
+            );
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use crate::config::Config;
+use std::fs::OpenOptions;
+use std::io;
+use std::sync::Mutex;
+use tracing_subscriber::EnvFilter;
+
+// Log lines are appended to atm-<terminal id>.log in the data directory, so
+// they never end up on the customer's screen
+pub fn init(config: &Config) -> io::Result<()> {
+    let path = config
+        .data_dir
+        .join(format!("atm-{}.log", config.terminal_id));
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(&config.log_level))
+        .with_ansi(false)
+        .with_writer(Mutex::new(file))
+        .init();
+    Ok(())
+}
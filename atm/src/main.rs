@@ -1,344 +1,826 @@
-use common::{Command, Response, SOCKET_PATH, receive_response, send_command};
-use std::io::{self, Write};
-use std::os::unix::net::UnixStream;
+mod bank;
+mod cassette;
+mod config;
+mod console;
+mod i18n;
+mod logging;
+mod outbox;
+mod scenario;
+mod stand_in;
+mod tui;
+
+use bank::{Backoff, BankClient, RequestError};
+use cassette::Cassettes;
+use chrono::Local;
+use clap::Parser;
+use common::{CardNumber, Command, ConfigError, Response, Settings};
+use config::{Config, Overrides};
+use console::{CardSlot, Input, Output, Prompt, ReadError, Stdin, Stdout};
+use i18n::{I18n, LANGUAGES_FILE};
+use outbox::Outbox;
+use scenario::Scenario;
+use stand_in::StandIn;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+// Test cards offered by the simulated card reader: (key, card number, PIN)
+const TEST_CARDS: [(&str, &str, &str); 2] = [
+    ("key123", "1234567890123452", "1234"),
+    ("key456", "9876543210987658", "4321"),
+];
+
+// Message ids of the main menu entries, in option order
+const MENU: [&str; 4] = [
+    "menu-check-balance",
+    "menu-withdraw",
+    "menu-change-language",
+    "menu-exit",
+];
+
+// Why a customer's session stopped before they finished
+enum Interrupted {
+    // Nobody answered "Do you need more time?"
+    TimedOut,
+    // Back to the menu, or the card is handed back if it has not been read
+    Cancelled,
+    InputClosed,
+}
+
+impl From<ReadError> for Interrupted {
+    fn from(error: ReadError) -> Self {
+        match error {
+            ReadError::TimedOut => Interrupted::TimedOut,
+            ReadError::Cancelled => Interrupted::Cancelled,
+            ReadError::Closed => Interrupted::InputClosed,
+        }
+    }
+}
 
-enum Language {
-    English,
-    Bulgarian,
+// Where the ATM is with the customer in front of it
+enum State {
+    // Welcome screen, waiting for the next customer
+    Idle,
+    // Reading the card; `attempts` card keys have failed so far
+    CardInserted { attempts: u32 },
+    // The card validated and the customer picks from the menu
+    Authenticated,
+    Transaction(Transaction),
+    // The card is returned, or kept, and the customer's details are cleared
+    Ejecting,
 }
 
+enum Transaction {
+    CheckBalance,
+    Withdraw,
+}
+
+#[allow(clippy::upper_case_acronyms)]
 struct ATM {
-    stream: UnixStream,
-    language: Language,
-    card_number: Option<String>,
+    bank: BankClient,
+    cassettes: Cassettes,
+    outbox: Outbox,
+    stand_in: StandIn,
+    i18n: I18n,
+    max_withdrawal: f64,
+    input: Box<dyn Input>,
+    output: Box<dyn Output>,
+    idle_timeout: Duration,
+    more_time_timeout: Duration,
+    card_attempts: u32,
+    // Kept to validate the card again after the connection was lost
+    card_key: Option<String>,
+    card_number: Option<CardNumber>,
     pin: Option<String>,
+    // The bank has accepted `pin` during this session
+    pin_verified: bool,
+    card_retained: bool,
 }
 
 impl ATM {
-    fn new() -> io::Result<Self> {
-        let stream = UnixStream::connect(SOCKET_PATH)?;
+    fn new(
+        bank: BankClient,
+        cassettes: Cassettes,
+        outbox: Outbox,
+        i18n: I18n,
+        config: &Config,
+        input: Box<dyn Input>,
+        output: Box<dyn Output>,
+    ) -> io::Result<Self> {
         Ok(ATM {
-            stream,
-            language: Language::English,
+            bank,
+            cassettes,
+            outbox,
+            stand_in: StandIn::new(config.stand_in_limit),
+            i18n,
+            max_withdrawal: config.max_withdrawal,
+            input,
+            output,
+            idle_timeout: Duration::from_secs(config.idle_timeout),
+            more_time_timeout: Duration::from_secs(config.more_time_timeout),
+            card_attempts: config.card_attempts,
+            card_key: None,
             card_number: None,
             pin: None,
+            pin_verified: false,
+            card_retained: false,
         })
     }
 
-    fn select_language(&mut self) {
-        println!("Select language / Изберете език:");
-        println!("1. English");
-        println!("2. Български");
-
-        let choice = self.read_choice();
-        match choice {
-            1 => self.language = Language::English,
-            2 => self.language = Language::Bulgarian,
-            _ => {
-                println!("Invalid choice, defaulting to English");
-                self.language = Language::English;
-            }
+    fn select_language(&mut self) -> Result<(), Interrupted> {
+        let prompt = self.i18n.language_prompt();
+        self.println(&prompt);
+        let languages: Vec<String> = self
+            .i18n
+            .languages()
+            .iter()
+            .map(|language| language.name.clone())
+            .collect();
+        self.output.menu(&languages);
+
+        let choice = self.read_choice(Prompt::Language)? as usize;
+        if choice == 0 || !self.i18n.select(choice - 1) {
+            self.i18n.reset();
+            let language = self.i18n.current().name.clone();
+            self.display_formatted("invalid-language-choice", &[("language", language)]);
         }
+        self.output.language(&self.i18n.current().code);
+        Ok(())
     }
 
-    fn display_message(&self, eng: &str, bg: &str) {
-        match self.language {
-            Language::English => println!("{}", eng),
-            Language::Bulgarian => println!("{}", bg),
-        }
+    fn println(&mut self, text: &str) {
+        self.output.write(&format!("{}\n", text));
     }
 
-    fn read_choice(&self) -> u32 {
-        print!("> ");
-        io::stdout().flush().unwrap();
+    fn display_message(&mut self, id: &str) {
+        let text = self.i18n.text(id);
+        self.println(&text);
+    }
 
-        let mut input = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .expect("Failed to read input");
-        input.trim().parse().unwrap_or(0)
+    fn display_formatted(&mut self, id: &str, args: &[(&str, String)]) {
+        let text = self.i18n.format(id, args);
+        self.println(&text);
     }
 
-    fn read_input(&self, prompt_eng: &str, prompt_bg: &str) -> String {
-        self.display_message(prompt_eng, prompt_bg);
-        print!("> ");
-        io::stdout().flush().unwrap();
+    fn read_choice(&mut self, prompt: Prompt) -> Result<u32, Interrupted> {
+        Ok(self.read_line(prompt)?.parse().unwrap_or(0))
+    }
 
-        let mut input = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .expect("Failed to read input");
-        input.trim().to_string()
+    fn read_input(&mut self, prompt_id: &str, prompt: Prompt) -> Result<String, Interrupted> {
+        self.display_message(prompt_id);
+        self.read_line(prompt)
     }
 
-    fn get_pin(&mut self) -> String {
+    // Once a card is in, a customer who types nothing for `idle_timeout` is
+    // asked whether they need more time; no answer ends the session
+    fn read_line(&mut self, prompt: Prompt) -> Result<String, Interrupted> {
+        self.output.write("> ");
+
+        if self.card_number.is_none() {
+            return Ok(self.input.read_line(prompt, None)?);
+        }
+        loop {
+            match self.input.read_line(prompt, Some(self.idle_timeout)) {
+                Ok(line) => return Ok(line),
+                Err(ReadError::TimedOut) => {}
+                Err(error) => return Err(error.into()),
+            }
+            let question = format!(
+                "\n{} {}: ",
+                self.i18n.text("need-more-time"),
+                self.i18n.text("yes-no")
+            );
+            self.output.write(&question);
+            match self
+                .input
+                .read_line(Prompt::MoreTime, Some(self.more_time_timeout))
+            {
+                Ok(answer) if self.i18n.is_yes(&answer) => self.output.write("> "),
+                Ok(_) | Err(ReadError::TimedOut | ReadError::Cancelled) => {
+                    return Err(Interrupted::TimedOut);
+                }
+                Err(ReadError::Closed) => return Err(Interrupted::InputClosed),
+            }
+        }
+    }
+
+    fn get_pin(&mut self) -> Result<String, Interrupted> {
         if let Some(pin) = &self.pin {
-            return pin.clone();
+            return Ok(pin.clone());
         }
 
         // PIN is not set, request from user
-        let pin = self.read_input("Enter your PIN:", "Въведете вашия ПИН:");
+        let pin = self.read_input("enter-pin", Prompt::Pin)?;
         self.pin = Some(pin.clone());
-        pin
+        Ok(pin)
     }
 
-    fn insert_card(&mut self) -> bool {
-        // In a real system, this would read from a card reader
-        // For simulation, we'll use predefined card keys
-        self.display_message(
-            "Available card keys for testing:",
-            "Налични ключове на карти за тестване:",
-        );
-        println!("1. key123 (Card: 1234567890123456, PIN: 1234)");
-        println!("2. key456 (Card: 9876543210987654, PIN: 4321)");
-
-        let card_key = self.read_input("Enter your card key:", "Въведете ключа на вашата карта:");
+    // Lost or stolen cards are kept by the machine and the session ends
+    fn retain_card(&mut self) {
+        self.display_message("card-retained");
+        self.end_session();
+        self.card_retained = true;
+        self.output.card_slot(CardSlot::Retained);
+    }
 
-        let command = Command::ValidateCardKey { card_key };
+    fn end_session(&mut self) {
+        self.card_key = None;
+        self.card_number = None;
+        self.pin = None;
+        self.pin_verified = false;
+    }
 
-        if let Err(e) = send_command(&mut self.stream, &command) {
-            self.display_message(
-                &format!("Error sending card key validation: {}", e),
-                &format!(
-                    "Грешка при изпращане на валидация на ключа на картата: {}",
-                    e
-                ),
-            );
-            return false;
-        }
+    // A customer whose PIN the bank has accepted carries on while the bank is
+    // unreachable, with only stand-in withdrawals on offer
+    fn may_stand_in(&self) -> bool {
+        self.stand_in.is_enabled() && self.card_number.is_some() && self.pin_verified
+    }
 
-        match receive_response(&mut self.stream) {
-            Ok(Response::ValidateCardKeySuccess { card_number }) => {
-                self.display_message(
-                    "Card key validated successfully",
-                    "Ключът на картата е успешно валидиран",
-                );
-                self.card_number = Some(card_number);
-                true
-            }
-            Ok(Response::ValidateCardKeyErrorInvalid) => {
-                self.display_message("Invalid card key", "Невалиден ключ на картата");
-                false
-            }
-            Ok(Response::ErrorCardNotFound) => {
-                self.display_message("Card not found", "Картата не е намерена");
-                false
-            }
-            Ok(Response::ErrorInvalidPin) => {
-                self.display_message("Invalid PIN", "Невалиден ПИН");
+    fn display_error(&mut self, response: Response) {
+        match response {
+            Response::ErrorCardNotFound => self.display_message("card-not-found"),
+            Response::ErrorInvalidPin => {
+                self.display_message("invalid-pin");
                 self.pin = None;
-                false
+                self.pin_verified = false;
             }
-            Ok(Response::ErrorServerInternal) => {
-                self.display_message("Server error", "Сървърна грешка");
-                false
+            Response::ErrorServerInternal => self.display_message("server-error"),
+            Response::ErrorInvalidCardNumber => self.display_message("invalid-card-number"),
+            Response::ErrorCardNotSupported => self.display_message("card-not-supported"),
+            Response::ErrorCardExpired => self.display_message("card-expired"),
+            Response::ErrorCardBlocked => self.display_message("card-blocked"),
+            Response::ErrorCardLost => self.retain_card(),
+            _ => self.display_message("unexpected-response"),
+        }
+    }
+
+    // Waits out a lost connection and sends the command again if that is
+    // safe. None means the command did not complete and the customer has
+    // been told why
+    fn request(&mut self, command: &Command) -> Result<Option<Response>, Interrupted> {
+        if self.may_stand_in() {
+            let Some(result) = self.request_once(command) else {
+                return Ok(None);
+            };
+            return match result {
+                Ok(response) => Ok(Some(response)),
+                Err(error) => {
+                    if let RequestError::TimedOut(_) = error {
+                        self.display_message("bank-not-responding");
+                    }
+                    let limit = self.i18n.format_amount(self.stand_in.limit());
+                    self.display_formatted("bank-unavailable", &[("limit", limit)]);
+                    Ok(None)
+                }
+            };
+        }
+        loop {
+            let error = match self.bank.request(command) {
+                Ok(response) => return Ok(Some(response)),
+                Err(error) => error,
+            };
+            if let RequestError::TimedOut(_) = error {
+                self.display_message("bank-not-responding");
             }
-            Ok(_) => {
-                self.display_message(
-                    "Unexpected response from server",
-                    "Неочакван отговор от сървъра",
-                );
-                false
+            let cancelled = cancel_withdrawal(&mut self.outbox, command, &error);
+            self.wait_for_bank()?;
+            if self.card_key.is_none() && !matches!(command, Command::ValidateCardKey { .. }) {
+                return Ok(None);
             }
-            Err(e) => {
-                self.display_message(
-                    &format!("Error receiving response: {}", e),
-                    &format!("Грешка при получаване на отговор: {}", e),
-                );
-                false
+            if cancelled {
+                self.display_message("withdrawal-unconfirmed");
+                return Ok(None);
             }
         }
     }
 
-    fn check_balance(&mut self) {
-        let card_number = self.card_number.clone().unwrap();
-        let pin = self.get_pin();
+    // A single attempt, reconnecting first if need be. None when the session
+    // ended because the card no longer validates
+    fn request_once(&mut self, command: &Command) -> Option<Result<Response, RequestError>> {
+        if !self.bank.is_connected() {
+            let card = self.card_key.as_deref().zip(self.card_number.as_ref());
+            let restored = restore(&mut self.bank, &self.cassettes, &mut self.outbox, card);
+            if let Ok(Restored::Ended) = restored {
+                self.display_message("session-ended");
+                self.end_session();
+                return None;
+            }
+        }
+        Some(self.bank.request(command))
+    }
 
-        let command = Command::CheckBalance { card_number, pin };
+    // Shows the out-of-service screen until the bank is back, then carries on
+    // with the customer's session if the card is still good
+    // The customer's idle time does not run meanwhile, as nothing is read
+    fn wait_for_bank(&mut self) -> Result<(), Interrupted> {
+        self.display_message("out-of-service");
+        let mut backoff = Backoff::default();
+        loop {
+            let card = self.card_key.as_deref().zip(self.card_number.as_ref());
+            match restore(&mut self.bank, &self.cassettes, &mut self.outbox, card) {
+                Ok(Restored::Resumed) => {
+                    self.display_message("back-in-service");
+                    return Ok(());
+                }
+                Ok(Restored::Ended) => {
+                    self.display_message("session-ended");
+                    self.end_session();
+                    return Ok(());
+                }
+                Err(_) => match self.input.pause(backoff.next_delay()) {
+                    Ok(()) => {}
+                    // Nobody is at the ATM to cancel or time out
+                    Err(ReadError::Closed) => return Err(Interrupted::InputClosed),
+                    Err(_) => {}
+                },
+            }
+        }
+    }
 
-        if let Err(e) = send_command(&mut self.stream, &command) {
-            self.display_message(
-                &format!("Error sending balance check: {}", e),
-                &format!("Грешка при изпращане на проверка на баланса: {}", e),
+    fn insert_card(&mut self) -> Result<bool, Interrupted> {
+        // In a real system, this would read from a card reader
+        // For simulation, we'll use predefined card keys
+        self.display_message("available-card-keys");
+        for (i, (key, card, pin)) in TEST_CARDS.iter().enumerate() {
+            self.display_formatted(
+                "test-card",
+                &[
+                    ("index", (i + 1).to_string()),
+                    ("key", key.to_string()),
+                    ("card", card.to_string()),
+                    ("pin", pin.to_string()),
+                ],
             );
-            return;
         }
 
-        match receive_response(&mut self.stream) {
-            Ok(Response::CheckBalanceSuccess { amount }) => {
-                self.display_message(
-                    &format!("Your current balance is: ${:.2}", amount),
-                    &format!("Текущият ви баланс е: ${:.2}", amount),
-                );
-            }
-            Ok(Response::ErrorCardNotFound) => {
-                self.display_message("Card not found", "Картата не е намерена");
-            }
-            Ok(Response::ErrorInvalidPin) => {
-                self.display_message("Invalid PIN", "Невалиден ПИН");
-                self.pin = None;
+        let card_key = self.read_input("enter-card-key", Prompt::CardKey)?;
+
+        let command = Command::ValidateCardKey {
+            card_key: card_key.clone(),
+        };
+        let Some(response) = self.request(&command)? else {
+            return Ok(false);
+        };
+
+        let validated = match response {
+            Response::ValidateCardKeySuccess { card_number } => {
+                match CardNumber::parse(&card_number) {
+                    Ok(card_number) => {
+                        self.display_message("card-key-validated");
+                        self.output
+                            .card_slot(CardSlot::Inserted(card_number.masked()));
+                        self.card_key = Some(card_key);
+                        self.card_number = Some(card_number);
+                        true
+                    }
+                    Err(_) => {
+                        self.display_message("invalid-card-number");
+                        false
+                    }
+                }
             }
-            Ok(Response::ErrorServerInternal) => {
-                self.display_message("Server error", "Сървърна грешка");
+            Response::ValidateCardKeyErrorInvalid => {
+                self.display_message("invalid-card-key");
+                false
             }
-            Ok(_) => {
-                self.display_message(
-                    "Unexpected response from server",
-                    "Неочакван отговор от сървъра",
-                );
+            other => {
+                self.display_error(other);
+                false
             }
-            Err(e) => {
-                self.display_message(
-                    &format!("Error receiving response: {}", e),
-                    &format!("Грешка при получаване на отговор: {}", e),
-                );
+        };
+        Ok(validated)
+    }
+
+    fn check_balance(&mut self) -> Result<(), Interrupted> {
+        let card_number = self.card_number.clone().unwrap().to_string();
+        let pin = self.get_pin()?;
+
+        let command = Command::CheckBalance { card_number, pin };
+        let Some(response) = self.request(&command)? else {
+            return Ok(());
+        };
+
+        match response {
+            Response::CheckBalanceSuccess { amount } => {
+                self.pin_verified = true;
+                let amount = self.i18n.format_amount(amount);
+                self.display_formatted("current-balance", &[("amount", amount)]);
             }
+            other => self.display_error(other),
         }
+        Ok(())
     }
 
-    fn withdraw(&mut self) {
-        let amount_str = self.read_input("Enter amount to withdraw:", "Въведете сума за теглене:");
+    fn withdraw(&mut self) -> Result<(), Interrupted> {
+        let amount_str = self.read_input("enter-withdraw-amount", Prompt::Amount)?;
 
-        let amount = match amount_str.parse::<f64>() {
-            Ok(amount) if amount > 0.0 => amount,
+        let amount = match self.i18n.parse_amount(&amount_str) {
+            Some(amount) if amount > 0.0 => amount,
             _ => {
-                self.display_message("Invalid amount", "Невалидна сума");
-                return;
+                self.display_message("invalid-amount");
+                return Ok(());
             }
         };
+        if amount > self.max_withdrawal {
+            let limit = self.i18n.format_amount(self.max_withdrawal);
+            self.display_formatted("amount-over-limit", &[("limit", limit)]);
+            return Ok(());
+        }
+        let Some(plan) = self.cassettes.plan(amount) else {
+            self.display_message("cannot-dispense");
+            return Ok(());
+        };
 
-        let want_receipt = self.read_input(
-            "Do you want a receipt? (y/n):",
-            "Искате ли касова бележка? (y/n):",
+        let question = format!(
+            "{} {}:",
+            self.i18n.text("want-receipt"),
+            self.i18n.text("yes-no")
         );
+        self.println(&question);
+        let want_receipt = self.read_line(Prompt::Receipt)?;
 
-        let want_receipt = want_receipt.to_lowercase() == "y";
+        let want_receipt = self.i18n.is_yes(&want_receipt);
 
-        let card_number = self.card_number.clone().unwrap();
-        let pin = self.get_pin();
+        let card_number = self.card_number.clone().unwrap().to_string();
+        let pin = self.get_pin()?;
 
         let command = Command::Withdraw {
-            card_number,
+            withdrawal_id: self.bank.next_id(),
+            card_number: card_number.clone(),
             pin,
             amount,
         };
 
-        if let Err(e) = send_command(&mut self.stream, &command) {
-            self.display_message(
-                &format!("Error sending withdrawal request: {}", e),
-                &format!("Грешка при изпращане на заявка за теглене: {}", e),
-            );
-            return;
-        }
-
-        match receive_response(&mut self.stream) {
-            Ok(Response::WithdrawSuccess { new_balance }) => {
-                self.display_message(
-                    &format!("Successfully withdrew ${:.2}", amount),
-                    &format!("Успешно изтеглихте ${:.2}", amount),
-                );
+        let response = if self.may_stand_in() {
+            match self.request_once(&command) {
+                None => return Ok(()),
+                Some(Ok(response)) => response,
+                Some(Err(RequestError::NotSent(_))) => {
+                    self.withdraw_offline(&card_number, amount, &plan, want_receipt);
+                    return Ok(());
+                }
+                Some(Err(error)) => {
+                    if let RequestError::TimedOut(_) = error {
+                        self.display_message("bank-not-responding");
+                    }
+                    cancel_withdrawal(&mut self.outbox, &command, &error);
+                    self.display_message("withdrawal-unconfirmed");
+                    return Ok(());
+                }
+            }
+        } else {
+            let Some(response) = self.request(&command)? else {
+                return Ok(());
+            };
+            response
+        };
 
-                self.display_message(
-                    &format!("Your new balance is: ${:.2}", new_balance),
-                    &format!("Новият ви баланс е: ${:.2}", new_balance),
-                );
+        match response {
+            Response::WithdrawSuccess { new_balance } => {
+                self.pin_verified = true;
+                dispense(&mut self.bank, &mut self.cassettes, &plan);
+                let amount = self.i18n.format_amount(amount);
+                let balance = self.i18n.format_amount(new_balance);
+                self.display_formatted("withdraw-success", &[("amount", amount.clone())]);
+                self.display_formatted("new-balance", &[("balance", balance.clone())]);
 
                 if want_receipt {
-                    self.display_message("Printing receipt...", "Отпечатване на касова бележка...");
-
-                    self.display_message(
-                        &format!("=== RECEIPT ===\nWithdraw Amount: ${:.2}\nNew Balance: ${:.2}\n==============", amount, new_balance),
-                        &format!("=== КАСОВА БЕЛЕЖКА ===\nИзтеглена Сума: ${:.2}\nНов Баланс: ${:.2}\n====================", amount, new_balance)
+                    self.display_message("printing-receipt");
+                    let date = self.i18n.format_timestamp(&Local::now());
+                    let receipt = self.i18n.format(
+                        "receipt",
+                        &[("date", date), ("amount", amount), ("balance", balance)],
                     );
+                    self.output.receipt(&receipt);
                 }
             }
-            Ok(Response::WithdrawErrorInsufficientFunds) => {
-                self.display_message("Insufficient funds", "Недостатъчна наличност");
-            }
-            Ok(Response::ErrorCardNotFound) => {
-                self.display_message("Card not found", "Картата не е намерена");
-            }
-            Ok(Response::ErrorInvalidPin) => {
-                self.display_message("Invalid PIN", "Невалиден ПИН");
-                self.pin = None;
+            Response::WithdrawErrorInsufficientFunds => {
+                self.pin_verified = true;
+                self.display_message("insufficient-funds");
             }
-            Ok(Response::ErrorServerInternal) => {
-                self.display_message("Server error", "Сървърна грешка");
-            }
-            Ok(_) => {
-                self.display_message(
-                    "Unexpected response from server",
-                    "Неочакван отговор от сървъра",
-                );
-            }
-            Err(e) => {
-                self.display_message(
-                    &format!("Error receiving response: {}", e),
-                    &format!("Грешка при получаване на отговор: {}", e),
-                );
+            Response::WithdrawErrorInvalidAmount => {
+                self.display_message("invalid-amount");
             }
+            other => self.display_error(other),
         }
+        Ok(())
     }
 
-    fn run(&mut self) {
-        println!("=============================");
-        println!("Welcome to the ATM System");
-        println!("=============================");
-
-        self.select_language();
-
-        if !self.insert_card() {
-            self.display_message(
-                "Card validation failed. Exiting...",
-                "Валидирането на картата е неуспешно. Изход...",
-            );
+    // Pays out on the ATM's own authority and queues an advice for the bank
+    fn withdraw_offline(&mut self, card_number: &str, amount: f64, plan: &[u32], receipt: bool) {
+        if !self.stand_in.allows(&self.outbox, card_number, amount) {
+            let limit = self.i18n.format_amount(self.stand_in.limit());
+            self.display_formatted("stand-in-over-limit", &[("limit", limit)]);
             return;
         }
+        let advice_id = self.bank.next_id();
+        if let Err(e) = self
+            .stand_in
+            .approve(&mut self.outbox, advice_id, card_number, amount)
+        {
+            error!(error = %e, "Cannot save stand-in withdrawal");
+            self.display_message("server-error");
+            return;
+        }
+        dispense(&mut self.bank, &mut self.cassettes, plan);
+        let amount = self.i18n.format_amount(amount);
+        self.display_formatted("withdraw-success", &[("amount", amount.clone())]);
+        self.display_message("stand-in-approved");
+
+        if receipt {
+            self.display_message("printing-receipt");
+            let date = self.i18n.format_timestamp(&Local::now());
+            let receipt = self
+                .i18n
+                .format("receipt-stand-in", &[("date", date), ("amount", amount)]);
+            self.output.receipt(&receipt);
+        }
+    }
 
+    // Serves one customer after another until the input is closed
+    fn run(&mut self) {
+        let mut state = State::Idle;
         loop {
-            println!();
-            if let Some(card_number) = &self.card_number {
-                let last_four = &card_number[card_number.len() - 4..];
-                let x_count = card_number.len() - 4;
-                let masked_card = format!("{}{}", "x".repeat(x_count), last_four);
-                self.display_message(
-                    &format!("You are using card {}", masked_card),
-                    &format!("Използвате карта {}", masked_card),
-                );
-            }
-            self.display_message("Select an option:", "Изберете опция:");
-            self.display_message(
-                "1. Check Balance\n2. Withdraw Money\n3. Change language\n4. Exit",
-                "1. Проверка на баланс\n2. Теглене на пари\n3. Промени езика (Change language)\n4. Изход",
-            );
+            state = match self.step(state) {
+                Ok(next) => next,
+                // A customer who walked away leaves the ATM ready for the
+                // next one
+                Err(Interrupted::TimedOut) => {
+                    self.display_message("session-timed-out");
+                    State::Ejecting
+                }
+                Err(Interrupted::Cancelled) if self.card_number.is_some() => State::Authenticated,
+                Err(Interrupted::Cancelled) => {
+                    self.display_message("card-cancelled");
+                    State::Ejecting
+                }
+                Err(Interrupted::InputClosed) => return,
+            };
+        }
+    }
 
-            let choice = self.read_choice();
-            match choice {
-                1 => self.check_balance(),
-                2 => self.withdraw(),
-                3 => self.select_language(),
-                4 => {
-                    self.display_message(
-                        "Thank you for using our ATM. Goodbye!",
-                        "Благодарим ви, че използвахте нашия банкомат. Довиждане!",
-                    );
-                    break;
+    fn step(&mut self, state: State) -> Result<State, Interrupted> {
+        match state {
+            State::Idle => {
+                self.println("=============================");
+                self.display_message("welcome");
+                self.println("=============================");
+
+                if !self.bank.is_connected() {
+                    self.wait_for_bank()?;
                 }
-                _ => {
-                    self.display_message(
-                        "Invalid option. Please try again.",
-                        "Невалидна опция. Моля, опитайте отново.",
-                    );
+
+                self.select_language()?;
+                self.output.card_slot(CardSlot::Empty);
+                Ok(State::CardInserted { attempts: 0 })
+            }
+            State::CardInserted { attempts } => {
+                if self.insert_card()? {
+                    return Ok(State::Authenticated);
+                }
+                if self.card_retained {
+                    return Ok(State::Ejecting);
+                }
+                let attempts = attempts + 1;
+                if attempts >= self.card_attempts {
+                    self.display_message("card-validation-failed");
+                    return Ok(State::Ejecting);
+                }
+                let left = (self.card_attempts - attempts).to_string();
+                self.display_formatted("card-attempts-left", &[("attempts", left)]);
+                Ok(State::CardInserted { attempts })
+            }
+            State::Authenticated => {
+                self.println("");
+                if let Some(card) = self.card_number.as_ref().map(CardNumber::masked) {
+                    self.display_formatted("using-card", &[("card", card)]);
+                }
+                self.display_message("select-option");
+                let options: Vec<String> = MENU.iter().map(|id| self.i18n.text(id)).collect();
+                self.output.menu(&options);
+
+                match self.read_choice(Prompt::Menu)? {
+                    1 => Ok(State::Transaction(Transaction::CheckBalance)),
+                    2 => Ok(State::Transaction(Transaction::Withdraw)),
+                    3 => {
+                        self.select_language()?;
+                        Ok(State::Authenticated)
+                    }
+                    4 => {
+                        self.display_message("goodbye");
+                        Ok(State::Ejecting)
+                    }
+                    _ => {
+                        self.display_message("invalid-option");
+                        Ok(State::Authenticated)
+                    }
+                }
+            }
+            State::Transaction(transaction) => {
+                match transaction {
+                    Transaction::CheckBalance => self.check_balance()?,
+                    Transaction::Withdraw => self.withdraw()?,
                 }
+                // The card was kept, or no longer validated after an outage
+                if self.card_number.is_none() {
+                    Ok(State::Ejecting)
+                } else {
+                    Ok(State::Authenticated)
+                }
+            }
+            State::Ejecting => {
+                self.end_session();
+                if !self.card_retained {
+                    self.output.card_slot(CardSlot::Ejected);
+                }
+                self.card_retained = false;
+                self.i18n.reset();
+                self.output.language(&self.i18n.current().code);
+                self.println("");
+                Ok(State::Idle)
             }
         }
     }
 }
 
+// How a customer's session stands once the bank is reachable again
+enum Restored {
+    // No card was inserted, or it still validates to the same card
+    Resumed,
+    // The card no longer validates; it is returned and the session ends
+    Ended,
+}
+
+// One attempt to get the bank back after the connection was lost. The bank
+// keeps no state for a session, so the card is validated again to decide
+// whether the customer may carry on
+fn restore(
+    bank: &mut BankClient,
+    cassettes: &Cassettes,
+    outbox: &mut Outbox,
+    card: Option<(&str, &CardNumber)>,
+) -> io::Result<Restored> {
+    bank.connect()?;
+    // Counts that were not reported while offline; a refusal is retried with
+    // the next report
+    if let Err(e) = bank.report_cassettes(cassettes)
+        && !bank.is_connected()
+    {
+        return Err(e);
+    }
+    // Booked before the card is validated again, so its balance is current
+    if let Err(e) = outbox.upload(bank) {
+        if !bank.is_connected() {
+            return Err(e);
+        }
+        error!(error = %e, "Cannot send queued commands");
+    }
+    let Some((card_key, card_number)) = card else {
+        return Ok(Restored::Resumed);
+    };
+    let command = Command::ValidateCardKey {
+        card_key: card_key.to_string(),
+    };
+    match bank.request(&command)? {
+        Response::ValidateCardKeySuccess {
+            card_number: validated,
+        } if validated == card_number.to_string() => Ok(Restored::Resumed),
+        _ => Ok(Restored::Ended),
+    }
+}
+
+// A withdrawal the bank may have booked, though no cash was paid out, is
+// reversed once the bank is back. Returns whether it was one
+fn cancel_withdrawal(outbox: &mut Outbox, command: &Command, error: &RequestError) -> bool {
+    if matches!(error, RequestError::NotSent(_)) || !matches!(command, Command::Withdraw { .. }) {
+        return false;
+    }
+    if let Err(e) = outbox.reverse(command) {
+        error!(error = %e, "Cannot save withdrawal reversal");
+    }
+    true
+}
+
+// Pays out the notes and tells the bank what the cassettes hold now
+fn dispense(bank: &mut BankClient, cassettes: &mut Cassettes, plan: &[u32]) {
+    if let Err(e) = cassettes.dispense(plan) {
+        error!(error = %e, "Cannot save cassette counts");
+    }
+    // Reported when the bank is back otherwise
+    if bank.is_connected()
+        && let Err(e) = bank.report_cassettes(cassettes)
+    {
+        warn!(error = %e, "Cannot report cassette counts");
+    }
+}
+
+#[derive(Parser)]
+#[command(name = "atm", about = "ATM client")]
+struct Cli {
+    /// Configuration file [default: atm.toml if present]
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Full-screen terminal interface instead of line prompts
+    #[arg(long)]
+    tui: bool,
+    /// Play the customer's part from a scenario file and check the screens
+    #[arg(long, value_name = "FILE", conflicts_with = "tui")]
+    scenario: Option<PathBuf>,
+    #[command(flatten)]
+    overrides: Overrides,
+}
+
+// Exits when the configured language has no catalog
+fn load_i18n(config: &Config) -> io::Result<I18n> {
+    let mut i18n = I18n::load(config.locales_dir.as_deref())?;
+    if let Some(language) = &config.language
+        && !i18n.set_default(language)
+    {
+        let languages = match &config.locales_dir {
+            Some(dir) => dir.join(LANGUAGES_FILE).display().to_string(),
+            None => format!("the built-in {}", LANGUAGES_FILE),
+        };
+        let problem = format!("language \"{}\" is not listed in {}", language, languages);
+        eprintln!("atm: {}", ConfigError(vec![problem]));
+        process::exit(2);
+    }
+    Ok(i18n)
+}
+
 fn main() -> io::Result<()> {
+    let cli = Cli::parse();
+    let config = match Config::load(cli.config.as_deref(), &cli.overrides) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("atm: {}", e);
+            process::exit(2);
+        }
+    };
+    let i18n = load_i18n(&config)?;
+
+    logging::init(&config)?;
+    info!(terminal = %config.terminal_id, "ATM started");
+
+    let mut bank = BankClient::new(&config);
+    let cassettes = Cassettes::load(&config.data_dir, bank.terminal_id())?;
+    let mut outbox = Outbox::load(&config.data_dir, bank.terminal_id())?;
+    // An unknown terminal is a setup problem; an unreachable bank only puts
+    // the ATM out of service until it is back
+    match restore(&mut bank, &cassettes, &mut outbox, None) {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => return Err(e),
+        Err(e) => warn!(error = %e, "Cannot connect to the bank"),
+        Ok(_) => {}
+    }
+    if outbox.pending() > 0 {
+        warn!(
+            commands = outbox.pending(),
+            "Commands are waiting for the bank"
+        );
+    }
+    if !outbox.refused().is_empty() {
+        warn!(
+            commands = outbox.refused().len(),
+            path = %outbox.refused_path().display(),
+            "Commands the bank refused are waiting to be settled by hand"
+        );
+    }
+
+    if cli.tui {
+        // The machine's labels follow the customer's language on their own
+        let (input, output) = tui::start(load_i18n(&config)?);
+        let (input, output) = (Box::new(input), Box::new(output));
+        ATM::new(bank, cassettes, outbox, i18n, &config, input, output)?.run();
+        return Ok(());
+    }
+
+    if let Some(path) = &cli.scenario {
+        let played = Scenario::load(path).and_then(|scenario| scenario.play(&i18n));
+        let (script, transcript, outcome) = match played {
+            Ok(played) => played,
+            Err(e) => {
+                eprintln!("atm: {}", e);
+                process::exit(2);
+            }
+        };
+        let (input, output) = (Box::new(script), Box::new(transcript));
+        ATM::new(bank, cassettes, outbox, i18n, &config, input, output)?.run();
+        let failures = outcome.failures();
+        if !failures.is_empty() {
+            for failure in &failures {
+                eprintln!("atm: {}", failure);
+            }
+            process::exit(1);
+        }
+        println!("Scenario passed");
+        return Ok(());
+    }
+
     println!("Starting ATM client...");
 
-    let mut atm = ATM::new()?;
+    let (input, output) = (Box::new(Stdin::new()), Box::new(Stdout));
+    let mut atm = ATM::new(bank, cassettes, outbox, i18n, &config, input, output)?;
     atm.run();
 
     Ok(())
@@ -0,0 +1,158 @@
+use crate::bank::BankClient;
+use chrono::{DateTime, Local};
+use common::{Command, Response, write_json_atomic};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use tracing::error;
+
+// A queued command the bank turned down, with its answer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Refused {
+    pub command: Command,
+    pub response: Response,
+    pub refused_at: DateTime<Local>,
+}
+
+// Commands the bank has to get even if the ATM restarts first: withdrawals
+// approved in stand-in and reversals of withdrawals that were not paid out.
+// Kept in outbox-<terminal id>.json and sent in order once the bank answers.
+// Those the bank refuses move to refused-<terminal id>.json, where they stay
+// until an operator has settled them by hand
+pub struct Outbox {
+    path: PathBuf,
+    commands: Vec<Command>,
+    refused_path: PathBuf,
+    refused: Vec<Refused>,
+}
+
+impl Outbox {
+    pub fn load(data_dir: &Path, terminal_id: &str) -> io::Result<Self> {
+        let path = data_dir.join(format!("outbox-{}.json", terminal_id));
+        let refused_path = data_dir.join(format!("refused-{}.json", terminal_id));
+        Ok(Outbox {
+            commands: read_list(&path)?,
+            path,
+            refused: read_list(&refused_path)?,
+            refused_path,
+        })
+    }
+
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    pub fn pending(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn refused(&self) -> &[Refused] {
+        &self.refused
+    }
+
+    pub fn refused_path(&self) -> &Path {
+        &self.refused_path
+    }
+
+    // Queues the command; it is on disk before this returns
+    pub fn push(&mut self, command: Command) -> io::Result<()> {
+        self.commands.push(command);
+        if let Err(e) = self.save() {
+            self.commands.pop();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // Queues the reversal of a withdrawal that got no answer and for which
+    // no cash was paid out
+    pub fn reverse(&mut self, withdrawal: &Command) -> io::Result<()> {
+        let Command::Withdraw {
+            withdrawal_id,
+            card_number,
+            amount,
+            ..
+        } = withdrawal
+        else {
+            return Ok(());
+        };
+        self.push(Command::ReverseWithdrawal {
+            withdrawal_id: withdrawal_id.clone(),
+            card_number: card_number.clone(),
+            amount: *amount,
+        })
+    }
+
+    // Sends the queued commands in order, dropping each once the bank has
+    // answered it. Stops at the first one that could not be delivered
+    pub fn upload(&mut self, bank: &mut BankClient) -> io::Result<()> {
+        while let Some(command) = self.commands.first() {
+            match bank.request(command)? {
+                Response::AdviceRecorded
+                | Response::WithdrawalReversed
+                | Response::WithdrawalNotFound => {}
+                Response::ErrorServerInternal => {
+                    return Err(io::Error::other(format!(
+                        "bank could not book {}",
+                        describe(command)
+                    )));
+                }
+                // Nothing to gain from sending it again; the difference has
+                // to be settled by hand. Kept before it leaves the outbox, so
+                // a crash in between can only keep it twice
+                response => {
+                    error!(
+                        command = %describe(command),
+                        response = ?response,
+                        path = %self.refused_path.display(),
+                        "Bank refused a queued command; it has to be settled by hand"
+                    );
+                    self.refused.push(Refused {
+                        command: command.clone(),
+                        response,
+                        refused_at: Local::now(),
+                    });
+                    if let Err(e) = write_json_atomic(&self.refused_path, &self.refused) {
+                        self.refused.pop();
+                        return Err(e);
+                    }
+                }
+            }
+            self.commands.remove(0);
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> io::Result<()> {
+        write_json_atomic(&self.path, &self.commands)
+    }
+}
+
+fn read_list<T: DeserializeOwned>(path: &Path) -> io::Result<Vec<T>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let reader = BufReader::new(File::open(path)?);
+    serde_json::from_reader(reader).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid {}: {}", path.display(), e),
+        )
+    })
+}
+
+// Names a queued command without its card number
+fn describe(command: &Command) -> String {
+    match command {
+        Command::WithdrawalAdvice { advice_id, .. } => {
+            format!("stand-in withdrawal {}", advice_id)
+        }
+        Command::ReverseWithdrawal { withdrawal_id, .. } => {
+            format!("reversal of withdrawal {}", withdrawal_id)
+        }
+        _ => "command".to_string(),
+    }
+}
@@ -0,0 +1,231 @@
+use crate::console::{Input, Output, Prompt, ReadError};
+use crate::i18n::I18n;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+
+// One customer's visit to the ATM, played by `--scenario` instead of someone
+// at the keyboard
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    // Code of the language picked on the welcome screen
+    pub language: String,
+    pub card_key: String,
+    pub pin: String,
+    // Text shown once the card has been read
+    #[serde(default)]
+    pub expect: Vec<String>,
+    #[serde(default)]
+    pub operations: Vec<Step>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    pub operation: Operation,
+    // As the customer types it, so in the scenario's language
+    pub amount: Option<String>,
+    #[serde(default)]
+    pub receipt: bool,
+    // Text shown before the menu comes back
+    #[serde(default)]
+    pub expect: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Balance,
+    Withdraw,
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let scenario: Scenario =
+            toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e.message()))?;
+        for (i, step) in scenario.operations.iter().enumerate() {
+            if let Operation::Withdraw = step.operation
+                && step.amount.is_none()
+            {
+                return Err(format!(
+                    "{}: operation {} is a withdrawal without an amount",
+                    path.display(),
+                    i + 1
+                ));
+            }
+        }
+        Ok(scenario)
+    }
+
+    // The scripted keyboard and the screen it checks, both feeding `Outcome`
+    pub fn play(self, i18n: &I18n) -> Result<(Script, Transcript, Outcome), String> {
+        let Some(index) = i18n
+            .languages()
+            .iter()
+            .position(|language| language.code == self.language)
+        else {
+            return Err(format!(
+                "language \"{}\" is not offered by the ATM",
+                self.language
+            ));
+        };
+        let record = Rc::new(RefCell::new(Record::default()));
+        let script = Script {
+            yes: i18n.format_in(&self.language, "answer-yes", &[]),
+            no: i18n.format_in(&self.language, "answer-no", &[]),
+            scenario: self,
+            language: index + 1,
+            record: record.clone(),
+            started: false,
+            next: 0,
+            pending: None,
+        };
+        Ok((script, Transcript(record.clone()), Outcome(record)))
+    }
+}
+
+#[derive(Default)]
+struct Record {
+    // Everything the ATM showed and the script typed
+    text: String,
+    failures: Vec<String>,
+}
+
+impl Record {
+    fn write(&mut self, text: &str) {
+        self.text.push_str(text);
+        print!("{}", text);
+        io::stdout().flush().unwrap();
+    }
+}
+
+// Expected text still to be checked, and where in the transcript it may appear
+struct Expectation {
+    label: String,
+    from: usize,
+    texts: Vec<String>,
+}
+
+// Answers each prompt from the scenario and checks the screens shown since
+// the previous answer whenever the menu comes back
+pub struct Script {
+    scenario: Scenario,
+    // Menu choice of the scenario's language
+    language: usize,
+    // Answers to yes/no questions in that language
+    yes: String,
+    no: String,
+    record: Rc<RefCell<Record>>,
+    started: bool,
+    // Index of the operation to choose at the next menu
+    next: usize,
+    pending: Option<Expectation>,
+}
+
+impl Script {
+    fn expect(&mut self, label: String, texts: Vec<String>) {
+        let from = self.record.borrow().text.len();
+        self.pending = Some(Expectation { label, from, texts });
+    }
+
+    fn check(&mut self) {
+        let Some(expectation) = self.pending.take() else {
+            return;
+        };
+        let mut record = self.record.borrow_mut();
+        let shown = record.text[expectation.from..].to_string();
+        for text in expectation.texts {
+            if !shown.contains(&text) {
+                let failure = format!("{}: \"{}\" was not shown", expectation.label, text);
+                record.failures.push(failure);
+            }
+        }
+    }
+
+    // The ATM is back at the welcome screen, so the customer has left
+    fn finish(&mut self) {
+        self.check();
+        self.record.borrow_mut().write("\n");
+        if self.next < self.scenario.operations.len() {
+            let failure = format!("session ended before operation {}", self.next + 1);
+            self.record.borrow_mut().failures.push(failure);
+        }
+    }
+
+    // Shown in the transcript as if it had been typed
+    fn answer(&self, answer: String) -> String {
+        self.record.borrow_mut().write(&format!("{}\n", answer));
+        answer
+    }
+
+    fn current(&self) -> &Step {
+        &self.scenario.operations[self.next - 1]
+    }
+}
+
+impl Input for Script {
+    fn read_line(
+        &mut self,
+        prompt: Prompt,
+        _timeout: Option<Duration>,
+    ) -> Result<String, ReadError> {
+        let answer = match prompt {
+            Prompt::Language if self.started => {
+                self.finish();
+                return Err(ReadError::Closed);
+            }
+            Prompt::Language => {
+                self.started = true;
+                let texts = self.scenario.expect.clone();
+                self.expect("card".to_string(), texts);
+                self.language.to_string()
+            }
+            Prompt::CardKey => self.scenario.card_key.clone(),
+            Prompt::Pin => self.scenario.pin.clone(),
+            Prompt::Menu => {
+                self.check();
+                let Some(step) = self.scenario.operations.get(self.next) else {
+                    // Exit
+                    return Ok(self.answer("4".to_string()));
+                };
+                let choice = match step.operation {
+                    Operation::Balance => "1",
+                    Operation::Withdraw => "2",
+                };
+                let texts = step.expect.clone();
+                self.next += 1;
+                self.expect(format!("operation {}", self.next), texts);
+                choice.to_string()
+            }
+            Prompt::Amount => self.current().amount.clone().unwrap_or_default(),
+            Prompt::Receipt if self.current().receipt => self.yes.clone(),
+            Prompt::Receipt => self.no.clone(),
+            Prompt::MoreTime => self.yes.clone(),
+        };
+        Ok(self.answer(answer))
+    }
+}
+
+// What the ATM showed during the scenario, echoed to stdout
+pub struct Transcript(Rc<RefCell<Record>>);
+
+impl Output for Transcript {
+    fn write(&mut self, text: &str) {
+        self.0.borrow_mut().write(text);
+    }
+}
+
+// Read once the ATM has stopped
+pub struct Outcome(Rc<RefCell<Record>>);
+
+impl Outcome {
+    pub fn failures(&self) -> Vec<String> {
+        self.0.borrow().failures.clone()
+    }
+}
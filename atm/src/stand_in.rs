@@ -0,0 +1,58 @@
+use crate::outbox::Outbox;
+use chrono::Local;
+use common::{Command, to_cents};
+use std::io;
+
+// Lets the ATM approve withdrawals on its own while the bank is unreachable.
+// Each one is queued in the outbox as an advice for the bank
+pub struct StandIn {
+    limit: f64,
+}
+
+impl StandIn {
+    pub fn new(limit: f64) -> Self {
+        StandIn { limit }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.limit > 0.0
+    }
+
+    pub fn limit(&self) -> f64 {
+        self.limit
+    }
+
+    // Whether `amount` fits under what the card may still take out before the
+    // bank has booked its earlier stand-in withdrawals
+    pub fn allows(&self, outbox: &Outbox, card_number: &str, amount: f64) -> bool {
+        let queued: f64 = outbox
+            .commands()
+            .iter()
+            .filter_map(|command| match command {
+                Command::WithdrawalAdvice {
+                    card_number: advised,
+                    amount,
+                    ..
+                } if advised == card_number => Some(amount),
+                _ => None,
+            })
+            .sum();
+        self.is_enabled() && to_cents(queued + amount) <= to_cents(self.limit)
+    }
+
+    // Queues the advice; it is on disk before any cash is paid out
+    pub fn approve(
+        &self,
+        outbox: &mut Outbox,
+        advice_id: String,
+        card_number: &str,
+        amount: f64,
+    ) -> io::Result<()> {
+        outbox.push(Command::WithdrawalAdvice {
+            advice_id,
+            card_number: card_number.to_string(),
+            amount,
+            approved_at: Local::now(),
+        })
+    }
+}
@@ -0,0 +1,456 @@
+use crate::console::{CardSlot, Input, Output, Prompt, ReadError};
+use crate::i18n::I18n;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Alignment, Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+const KEYPAD: [[&str; 3]; 4] = [
+    ["1", "2", "3"],
+    ["4", "5", "6"],
+    ["7", "8", "9"],
+    ["CLR", "0", "ENT"],
+];
+
+// What a key press did to the line being entered
+enum Key {
+    Answer(String),
+    Cancel,
+    Quit,
+    None,
+}
+
+// The screen of the ATM, drawn full-screen with soft keys beside it and the
+// keypad, card slot and receipt printer below
+struct Tui {
+    // Taken while a frame is drawn from the rest of the state
+    terminal: Option<DefaultTerminal>,
+    // For the machine's own labels, in the customer's language
+    i18n: I18n,
+    // Everything the ATM has shown since the customer last answered
+    screen: String,
+    // Where the screen stood before "Do you need more time?" was asked
+    before_question: Option<usize>,
+    prompt: Option<Prompt>,
+    // The choices of the last menu, one per soft key; every menu question
+    // comes with its own
+    menu: Vec<String>,
+    input: String,
+    card_slot: CardSlot,
+    receipt: Vec<String>,
+}
+
+// The customer's side of the full-screen ATM: the keys and the screen
+pub fn start(i18n: I18n) -> (Keys, Screen) {
+    let tui = Rc::new(RefCell::new(Tui {
+        terminal: Some(ratatui::init()),
+        i18n,
+        screen: String::new(),
+        before_question: None,
+        prompt: None,
+        menu: Vec::new(),
+        input: String::new(),
+        card_slot: CardSlot::Empty,
+        receipt: Vec::new(),
+    }));
+    (Keys(tui.clone()), Screen(tui))
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+pub struct Keys(Rc<RefCell<Tui>>);
+
+impl Input for Keys {
+    // Ctrl-C closes the input, which stops the ATM
+    fn read_line(
+        &mut self,
+        prompt: Prompt,
+        timeout: Option<Duration>,
+    ) -> Result<String, ReadError> {
+        let mut tui = self.0.borrow_mut();
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        tui.prompt = Some(prompt);
+        tui.input.clear();
+        let result = tui.read_key(deadline);
+        tui.prompt = None;
+        match &result {
+            Err(ReadError::TimedOut) => {
+                if tui.before_question.is_none() {
+                    tui.before_question = Some(tui.screen.len());
+                }
+            }
+            // The question goes and the screen the customer left comes back
+            Ok(_) if prompt == Prompt::MoreTime => {
+                if let Some(len) = tui.before_question.take() {
+                    tui.screen.truncate(len);
+                }
+            }
+            Ok(_) | Err(ReadError::Cancelled) => {
+                tui.screen.clear();
+                tui.before_question = None;
+            }
+            Err(ReadError::Closed) => {}
+        }
+        result
+    }
+
+    // Keys pressed meanwhile are ignored, except Ctrl-C
+    fn pause(&mut self, duration: Duration) -> Result<(), ReadError> {
+        let mut tui = self.0.borrow_mut();
+        let deadline = Instant::now() + duration;
+        loop {
+            tui.draw();
+            let left = deadline.saturating_duration_since(Instant::now());
+            if !event::poll(left).map_err(|_| ReadError::Closed)? {
+                return Ok(());
+            }
+            if let Ok(Event::Key(key)) = event::read()
+                && is_quit(&key)
+            {
+                return Err(ReadError::Closed);
+            }
+        }
+    }
+}
+
+pub struct Screen(Rc<RefCell<Tui>>);
+
+impl Output for Screen {
+    fn write(&mut self, text: &str) {
+        let mut tui = self.0.borrow_mut();
+        // The line-mode prompt marker; the input line is drawn instead
+        if text != "> " {
+            tui.screen.push_str(text);
+        }
+        tui.draw();
+    }
+
+    fn menu(&mut self, options: &[String]) {
+        let mut tui = self.0.borrow_mut();
+        tui.menu = options.to_vec();
+        tui.draw();
+    }
+
+    fn receipt(&mut self, text: &str) {
+        let mut tui = self.0.borrow_mut();
+        tui.receipt = text.lines().map(str::to_string).collect();
+        tui.draw();
+    }
+
+    fn language(&mut self, code: &str) {
+        let mut tui = self.0.borrow_mut();
+        if let Some(index) = tui
+            .i18n
+            .languages()
+            .iter()
+            .position(|language| language.code == code)
+        {
+            tui.i18n.select(index);
+        }
+    }
+
+    fn card_slot(&mut self, slot: CardSlot) {
+        let mut tui = self.0.borrow_mut();
+        if slot == CardSlot::Empty {
+            tui.receipt.clear();
+        }
+        tui.card_slot = slot;
+        tui.draw();
+    }
+}
+
+fn is_quit(key: &KeyEvent) -> bool {
+    key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c')
+}
+
+impl Tui {
+    fn read_key(&mut self, deadline: Option<Instant>) -> Result<String, ReadError> {
+        loop {
+            self.draw();
+            if let Some(deadline) = deadline {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if !event::poll(left).map_err(|_| ReadError::Closed)? {
+                    return Err(ReadError::TimedOut);
+                }
+            }
+            let Event::Key(key) = event::read().map_err(|_| ReadError::Closed)? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match self.handle_key(key) {
+                Key::Answer(answer) => return Ok(answer),
+                Key::Cancel => return Err(ReadError::Cancelled),
+                Key::Quit => return Err(ReadError::Closed),
+                Key::None => {}
+            }
+        }
+    }
+
+    fn takes_text(&self) -> bool {
+        matches!(
+            self.prompt,
+            Some(Prompt::CardKey | Prompt::Pin | Prompt::Amount)
+        )
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Key {
+        if is_quit(&key) {
+            return Key::Quit;
+        }
+        match key.code {
+            KeyCode::F(n) if (1..=8).contains(&n) => self.soft_key(n),
+            KeyCode::Enter if self.takes_text() => Key::Answer(std::mem::take(&mut self.input)),
+            KeyCode::Esc => {
+                self.input.clear();
+                Key::None
+            }
+            KeyCode::Backspace => {
+                self.input.pop();
+                Key::None
+            }
+            KeyCode::Char(c) if self.takes_text() => {
+                self.type_char(c);
+                Key::None
+            }
+            // The letters the customer's language answers with
+            KeyCode::Char(c) if matches!(self.prompt, Some(Prompt::Receipt | Prompt::MoreTime)) => {
+                let typed = c.to_lowercase().to_string();
+                if typed == self.text("answer-yes").to_lowercase() {
+                    self.soft_key(1)
+                } else if typed == self.text("answer-no").to_lowercase() {
+                    self.soft_key(2)
+                } else {
+                    Key::None
+                }
+            }
+            // Outside of text entry the number keys double as soft keys
+            KeyCode::Char(c) => match c.to_digit(10) {
+                Some(n @ 1..=8) => self.soft_key(n as u8),
+                _ => Key::None,
+            },
+            _ => Key::None,
+        }
+    }
+
+    fn type_char(&mut self, c: char) {
+        let accepted = match self.prompt {
+            Some(Prompt::CardKey) => c.is_ascii_alphanumeric(),
+            Some(Prompt::Pin) => c.is_ascii_digit() && self.input.len() < 12,
+            // Either separator of any language
+            Some(Prompt::Amount) => c.is_ascii_digit() || matches!(c, '.' | ',' | ' '),
+            _ => false,
+        };
+        if accepted {
+            self.input.push(c);
+        }
+    }
+
+    fn soft_key(&mut self, n: u8) -> Key {
+        match (self.prompt, n) {
+            (Some(Prompt::Language | Prompt::Menu), n) if usize::from(n) <= self.menu.len() => {
+                Key::Answer(n.to_string())
+            }
+            (Some(Prompt::Receipt | Prompt::MoreTime), 1) => Key::Answer(self.text("answer-yes")),
+            (Some(Prompt::Receipt | Prompt::MoreTime), 2) => Key::Answer(self.text("answer-no")),
+            (Some(Prompt::CardKey | Prompt::Pin | Prompt::Amount), 4) => Key::Cancel,
+            (Some(Prompt::CardKey | Prompt::Pin | Prompt::Amount), 8) => {
+                Key::Answer(std::mem::take(&mut self.input))
+            }
+            _ => Key::None,
+        }
+    }
+
+    fn text(&self, id: &str) -> String {
+        self.i18n.text(id)
+    }
+
+    fn soft_key_labels(&self) -> [Option<String>; 8] {
+        let mut labels: [Option<String>; 8] = Default::default();
+        match self.prompt {
+            Some(Prompt::Language | Prompt::Menu) => {
+                for (label, option) in labels.iter_mut().zip(&self.menu) {
+                    *label = Some(option.clone());
+                }
+            }
+            Some(Prompt::Receipt | Prompt::MoreTime) => {
+                labels[0] = Some(self.text("soft-key-yes"));
+                labels[1] = Some(self.text("soft-key-no"));
+            }
+            Some(Prompt::CardKey | Prompt::Pin | Prompt::Amount) => {
+                labels[3] = Some(self.text("soft-key-cancel"));
+                labels[7] = Some(self.text("soft-key-confirm"));
+            }
+            None => {}
+        }
+        labels
+    }
+
+    // A failed draw is made good by the next one
+    fn draw(&mut self) {
+        if let Some(mut terminal) = self.terminal.take() {
+            let _ = terminal.draw(|frame| self.render(frame));
+            self.terminal = Some(terminal);
+        }
+    }
+
+    fn render(&self, frame: &mut Frame) {
+        let outer = Block::default()
+            .borders(Borders::ALL)
+            .title(" ATM ")
+            .title_alignment(Alignment::Center);
+        let area = outer.inner(frame.area());
+        frame.render_widget(outer, frame.area());
+
+        let [top, bottom] =
+            Layout::vertical([Constraint::Min(12), Constraint::Length(8)]).areas(area);
+        let [left, screen, right] = Layout::horizontal([
+            Constraint::Percentage(30),
+            Constraint::Percentage(40),
+            Constraint::Percentage(30),
+        ])
+        .areas(top);
+        let [keypad, card_slot, receipt] = Layout::horizontal([
+            Constraint::Length(24),
+            Constraint::Length(30),
+            Constraint::Min(20),
+        ])
+        .areas(bottom);
+
+        let labels = self.soft_key_labels();
+        self.draw_soft_keys(frame, left, &labels[..4], 1, Alignment::Left);
+        self.draw_soft_keys(frame, right, &labels[4..], 5, Alignment::Right);
+        self.draw_screen(frame, screen);
+        self.draw_keypad(frame, keypad);
+        self.draw_card_slot(frame, card_slot);
+        self.draw_receipt(frame, receipt);
+    }
+
+    fn draw_soft_keys(
+        &self,
+        frame: &mut Frame,
+        area: Rect,
+        labels: &[Option<String>],
+        first_key: usize,
+        alignment: Alignment,
+    ) {
+        let rows = Layout::vertical([Constraint::Ratio(1, 4); 4]).split(area);
+        for (i, label) in labels.iter().enumerate() {
+            let key = format!("[F{}]", first_key + i);
+            let text = match (label, alignment) {
+                (Some(label), Alignment::Right) => format!("{} ◀ {}", label, key),
+                (Some(label), _) => format!("{} ▶ {}", key, label),
+                (None, _) => key,
+            };
+            let style = if label.is_some() {
+                Style::default().add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(Color::DarkGray)
+            };
+            let [row] = Layout::vertical([Constraint::Length(2)])
+                .flex(ratatui::layout::Flex::Center)
+                .areas(rows[i]);
+            frame.render_widget(
+                Paragraph::new(text)
+                    .style(style)
+                    .alignment(alignment)
+                    .wrap(Wrap { trim: true }),
+                row,
+            );
+        }
+    }
+
+    fn draw_screen(&self, frame: &mut Frame, area: Rect) {
+        let mut lines: Vec<Line> = self
+            .screen
+            .trim_matches('\n')
+            .lines()
+            .map(|line| Line::from(line.to_string()))
+            .collect();
+        if self.takes_text() {
+            let shown = match self.prompt {
+                Some(Prompt::Pin) => "*".repeat(self.input.len()),
+                _ => self.input.clone(),
+            };
+            lines.push(Line::from(""));
+            lines.push(
+                Line::from(format!("> {}_", shown)).style(Style::default().fg(Color::Yellow)),
+            );
+        }
+        // The latest lines are the ones that matter when they do not all fit
+        let height = usize::from(area.height.saturating_sub(2));
+        let skipped = lines.len().saturating_sub(height);
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .style(Style::default().fg(Color::Green));
+        frame.render_widget(
+            Paragraph::new(lines.split_off(skipped))
+                .block(block)
+                .alignment(Alignment::Center)
+                .wrap(Wrap { trim: false }),
+            area,
+        );
+    }
+
+    fn draw_keypad(&self, frame: &mut Frame, area: Rect) {
+        let lines: Vec<Line> = KEYPAD
+            .iter()
+            .map(|row| Line::from(row.map(|key| format!("[{:^3}]", key)).join(" ")))
+            .collect();
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(format!(" {} ", self.text("keypad-title")));
+        frame.render_widget(
+            Paragraph::new(lines)
+                .block(block)
+                .alignment(Alignment::Center),
+            area,
+        );
+    }
+
+    fn draw_card_slot(&self, frame: &mut Frame, area: Rect) {
+        let (status, color) = match &self.card_slot {
+            CardSlot::Empty => (self.text("card-slot-empty"), Color::Gray),
+            CardSlot::Inserted(masked_card) => (
+                self.i18n
+                    .format("card-slot-inserted", &[("card", masked_card.clone())]),
+                Color::Green,
+            ),
+            CardSlot::Ejected => (self.text("card-slot-ejected"), Color::Yellow),
+            CardSlot::Retained => (self.text("card-slot-retained"), Color::Red),
+        };
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(format!(" {} ", self.text("card-slot-title")));
+        frame.render_widget(
+            Paragraph::new(vec![Line::from(""), Line::from(status)])
+                .style(Style::default().fg(color))
+                .block(block)
+                .alignment(Alignment::Center),
+            area,
+        );
+    }
+
+    fn draw_receipt(&self, frame: &mut Frame, area: Rect) {
+        let lines: Vec<Line> = self
+            .receipt
+            .iter()
+            .map(|line| Line::from(line.as_str()))
+            .collect();
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(format!(" {} ", self.text("receipt-title")));
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+}
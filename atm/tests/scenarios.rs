@@ -0,0 +1,50 @@
+// Every scenario in atm/scenarios played by the ATM against a bank started
+// in-process with the test accounts
+use bank::testing::{TEST_TERMINAL, TempDir, TestBank};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+fn play(scenario: &Path) -> io::Result<()> {
+    let bank = TestBank::start()?;
+    let data_dir = TempDir::new()?;
+    // Run where no atm.toml is found
+    let output = Command::new(env!("CARGO_BIN_EXE_atm"))
+        .current_dir(data_dir.path())
+        .arg("--scenario")
+        .arg(scenario)
+        .arg("--socket-path")
+        .arg(&bank.config().socket_path)
+        .arg("--terminal-id")
+        .arg(TEST_TERMINAL)
+        .arg("--data-dir")
+        .arg(data_dir.path())
+        .output()?;
+    assert!(
+        output.status.success(),
+        "{} failed:\n{}{}",
+        scenario.display(),
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(())
+}
+
+#[test]
+fn scenarios_pass() -> io::Result<()> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+    let mut played = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "toml")
+        {
+            play(&path)?;
+            played += 1;
+        }
+    }
+    assert!(played > 0);
+    Ok(())
+}
@@ -0,0 +1,422 @@
+use crate::handler::Bank;
+use crate::ledger::{LedgerAccount, Posting, PostingKind};
+use crate::logging::mask_card;
+use crate::routing::{ROUTING_FILE, Router, RoutingConfig};
+use crate::server::State;
+use crate::settlement;
+use crate::store::{Change, write_snapshot};
+use chrono::Local;
+use common::{
+    Account, AccountSummary, AdminCommand, AdminResponse, CardNumber, CardStatus, LedgerBalance,
+    default_expiry, from_cents, to_cents,
+};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use tracing::{error, info};
+
+pub const ADMIN_TOKEN_ENV: &str = "BANK_ADMIN_TOKEN";
+pub const ADMIN_TOKEN_FILE: &str = "admin_token";
+
+const MIN_PIN_LENGTH: usize = 4;
+const MAX_PIN_LENGTH: usize = 12;
+
+// The admin credential comes from the environment, or from a file readable
+// only by the bank's user that is generated on first start
+pub fn load_admin_token(path: &Path) -> io::Result<String> {
+    if let Ok(token) = env::var(ADMIN_TOKEN_ENV)
+        && !token.is_empty()
+    {
+        return Ok(token);
+    }
+
+    if path.exists() {
+        return Ok(fs::read_to_string(path)?.trim().to_string());
+    }
+
+    let token = format!("{:016x}{:016x}", random_u64()?, random_u64()?);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    writeln!(file, "{}", token)?;
+    info!(path = %path.display(), "Generated admin token");
+    Ok(token)
+}
+
+pub fn handle_admin_command(state: &State, command: AdminCommand) -> io::Result<AdminResponse> {
+    let response = match command {
+        AdminCommand::ListSessions => AdminResponse::Sessions {
+            sessions: state.sessions.lock().unwrap().list(),
+        },
+        AdminCommand::KickSession { session_id } => {
+            if state.sessions.lock().unwrap().kick(session_id) {
+                info!(session_id, "Kicked session");
+                AdminResponse::SessionKicked
+            } else {
+                AdminResponse::ErrorSessionNotFound
+            }
+        }
+        AdminCommand::ListTerminals => AdminResponse::Terminals {
+            terminals: state.terminals.lock().unwrap().list(),
+        },
+        AdminCommand::AddTerminal {
+            terminal_id,
+            location,
+        } => {
+            if !valid_terminal_id(&terminal_id) {
+                AdminResponse::ErrorInvalidTerminalId
+            } else if state
+                .terminals
+                .lock()
+                .unwrap()
+                .add(terminal_id.clone(), location)?
+            {
+                info!(terminal = %terminal_id, "Added terminal");
+                AdminResponse::TerminalAdded
+            } else {
+                AdminResponse::ErrorTerminalExists
+            }
+        }
+        AdminCommand::RemoveTerminal { terminal_id } => {
+            if state.terminals.lock().unwrap().remove(&terminal_id)? {
+                let kicked = state.sessions.lock().unwrap().kick_terminal(&terminal_id);
+                info!(terminal = %terminal_id, sessions = kicked, "Removed terminal");
+                AdminResponse::TerminalRemoved
+            } else {
+                AdminResponse::ErrorTerminalNotFound
+            }
+        }
+        AdminCommand::Snapshot => {
+            let path = write_snapshot(&state.config, &state.bank.lock().unwrap().accounts)?;
+            AdminResponse::SnapshotWritten {
+                path: path.display().to_string(),
+            }
+        }
+        AdminCommand::ReloadRouting => reload_routing(state),
+        AdminCommand::Settle { date } => {
+            let bank = state.bank.lock().unwrap();
+            let date = date.unwrap_or_else(|| Local::now().date_naive());
+            let terminals = settlement::settle(&bank.ledger, &bank.cassette_reports, date);
+            let (csv_path, json_path) =
+                settlement::write_report(&state.config.data_dir, date, &terminals)?;
+            info!(%date, terminals = terminals.len(), "Settled business day");
+            AdminResponse::Settled {
+                date,
+                terminals,
+                csv_path: csv_path.display().to_string(),
+                json_path: json_path.display().to_string(),
+            }
+        }
+        // The server stops once the reply has been sent
+        AdminCommand::Shutdown => AdminResponse::ShuttingDown,
+        command @ (AdminCommand::ListAccounts
+        | AdminCommand::CreateAccount { .. }
+        | AdminCommand::CloseAccount { .. }
+        | AdminCommand::IssueCardKey { .. }
+        | AdminCommand::ResetPin { .. }
+        | AdminCommand::UnlockCard { .. }
+        | AdminCommand::AdjustBalance { .. }
+        | AdminCommand::FreezeAccount { .. }
+        | AdminCommand::CheckBooks) => {
+            handle_account_command(&mut state.bank.lock().unwrap(), command)?
+        }
+    };
+    Ok(response)
+}
+
+// Re-reads routing.json; the settings in bank.toml need a restart
+pub fn reload_routing(state: &State) -> AdminResponse {
+    match RoutingConfig::load(&state.config.data_file(ROUTING_FILE)) {
+        Ok(config) => {
+            state.bank.lock().unwrap().router = Router::new(config);
+            info!("Routing configuration reloaded");
+            AdminResponse::RoutingReloaded
+        }
+        Err(e) => AdminResponse::ErrorRouting {
+            message: e.to_string(),
+        },
+    }
+}
+
+fn handle_account_command(bank: &mut Bank, command: AdminCommand) -> io::Result<AdminResponse> {
+    let mut changes = Vec::new();
+
+    let response = match command {
+        AdminCommand::ListAccounts => {
+            let mut accounts: Vec<AccountSummary> =
+                bank.accounts.values().map(AccountSummary::from).collect();
+            accounts.sort_by(|a, b| a.card_number.cmp(&b.card_number));
+            return Ok(AdminResponse::Accounts { accounts });
+        }
+        AdminCommand::CreateAccount {
+            name,
+            pin,
+            initial_balance,
+            card_number,
+        } => create_account(bank, name, pin, initial_balance, card_number, &mut changes),
+        AdminCommand::CloseAccount { card_number } => match bank.accounts.get(&card_number) {
+            None => AdminResponse::ErrorAccountNotFound,
+            // In cents, so rounding left over from past postings does not keep it open
+            Some(account) if to_cents(account.balance) != 0 => AdminResponse::ErrorBalanceNotZero {
+                balance: account.balance,
+            },
+            Some(_) => {
+                bank.remove_account(&card_number);
+                changes.push(Change::Removed(card_number));
+                AdminResponse::AccountClosed
+            }
+        },
+        AdminCommand::IssueCardKey { card_number } => {
+            let card_key = generate_card_key(bank)?;
+            match bank.account_mut(&card_number) {
+                None => AdminResponse::ErrorAccountNotFound,
+                Some(account) => {
+                    // A new key replaces the physical card, so the old one stops working
+                    account.card_key = card_key.clone();
+                    account.issued_on = Local::now().date_naive();
+                    account.expires_on = default_expiry();
+                    if account.status != CardStatus::Blocked {
+                        account.status = CardStatus::Active;
+                    }
+                    changes.push(Change::Updated(card_number));
+                    AdminResponse::CardKeyIssued { card_key }
+                }
+            }
+        }
+        AdminCommand::ResetPin {
+            card_number,
+            new_pin,
+        } => match bank.account_mut(&card_number) {
+            None => AdminResponse::ErrorAccountNotFound,
+            Some(_) if !valid_pin(&new_pin) => AdminResponse::ErrorInvalidPinFormat,
+            Some(account) => {
+                account.pin = new_pin;
+                changes.push(Change::Updated(card_number));
+                AdminResponse::PinReset
+            }
+        },
+        AdminCommand::UnlockCard { card_number } => match bank.account_mut(&card_number) {
+            None => AdminResponse::ErrorAccountNotFound,
+            Some(account) if account.status != CardStatus::Blocked => {
+                AdminResponse::ErrorCardNotBlocked
+            }
+            Some(account) => {
+                account.status = CardStatus::Active;
+                changes.push(Change::Updated(card_number));
+                AdminResponse::CardUnlocked
+            }
+        },
+        AdminCommand::AdjustBalance {
+            card_number,
+            amount,
+            reason,
+        } => match bank.accounts.get(&card_number) {
+            None => AdminResponse::ErrorAccountNotFound,
+            Some(_) if !amount.is_finite() || to_cents(amount) == 0 => {
+                AdminResponse::ErrorInvalidAmount
+            }
+            Some(_) if reason.trim().is_empty() => AdminResponse::ErrorMissingReason,
+            Some(account) if to_cents(account.balance) + to_cents(amount) < 0 => {
+                AdminResponse::ErrorInsufficientFunds
+            }
+            Some(_) => {
+                info!(
+                    card = %mask_card(&card_number),
+                    amount,
+                    reason = %reason,
+                    "Balance adjusted"
+                );
+                let customer = LedgerAccount::Customer(card_number.clone());
+                let (debit, credit) = if amount > 0.0 {
+                    (LedgerAccount::Adjustments, customer)
+                } else {
+                    (customer, LedgerAccount::Adjustments)
+                };
+                let posting = Posting::new(
+                    debit,
+                    credit,
+                    to_cents(amount).abs(),
+                    PostingKind::Adjustment,
+                    Some(reason),
+                );
+                bank.post(posting, &mut changes);
+                AdminResponse::BalanceAdjusted {
+                    new_balance: bank.accounts[&card_number].balance,
+                }
+            }
+        },
+        AdminCommand::FreezeAccount { card_number } => match bank.account_mut(&card_number) {
+            None => AdminResponse::ErrorAccountNotFound,
+            Some(account) => {
+                account.status = CardStatus::Blocked;
+                info!(card = %mask_card(&card_number), "Froze account");
+                changes.push(Change::Updated(card_number));
+                AdminResponse::AccountFrozen
+            }
+        },
+        AdminCommand::CheckBooks => {
+            let problems = bank.ledger.check(&bank.accounts);
+            if problems.is_empty() {
+                AdminResponse::BooksBalanced {
+                    postings: bank.ledger.postings().len(),
+                    balances: bank
+                        .ledger
+                        .balances()
+                        .map(|(account, balance)| LedgerBalance {
+                            account: account.to_string(),
+                            balance: from_cents(balance),
+                        })
+                        .collect(),
+                }
+            } else {
+                AdminResponse::ErrorBooksUnbalanced { problems }
+            }
+        }
+        // Answered by handle_admin_command without the bank's lock
+        AdminCommand::ListSessions
+        | AdminCommand::KickSession { .. }
+        | AdminCommand::Snapshot
+        | AdminCommand::ReloadRouting
+        | AdminCommand::Shutdown
+        | AdminCommand::Settle { .. }
+        | AdminCommand::ListTerminals
+        | AdminCommand::AddTerminal { .. }
+        | AdminCommand::RemoveTerminal { .. } => AdminResponse::ErrorServerInternal,
+    };
+
+    bank.commit(&changes)?;
+    Ok(response)
+}
+
+fn create_account(
+    bank: &mut Bank,
+    name: String,
+    pin: String,
+    initial_balance: f64,
+    card_number: Option<String>,
+    changes: &mut Vec<Change>,
+) -> AdminResponse {
+    if !valid_pin(&pin) {
+        return AdminResponse::ErrorInvalidPinFormat;
+    }
+    if !initial_balance.is_finite() || to_cents(initial_balance) < 0 {
+        return AdminResponse::ErrorInvalidAmount;
+    }
+
+    let card_number = match card_number {
+        Some(card_number) => match CardNumber::parse(&card_number) {
+            Ok(card_number) if bank.router.is_on_us(&card_number) => card_number,
+            _ => return AdminResponse::ErrorInvalidCardNumber,
+        },
+        None => match next_card_number(bank) {
+            Some(card_number) => card_number,
+            None => return AdminResponse::ErrorServerInternal,
+        },
+    };
+    if bank.accounts.contains_key(card_number.as_str()) {
+        return AdminResponse::ErrorAccountExists;
+    }
+    // Late advices, reversals and settlement entries for the old card would
+    // land on the new customer
+    if bank
+        .ledger
+        .has_postings(&LedgerAccount::Customer(card_number.to_string()))
+    {
+        return AdminResponse::ErrorCardNumberUsed;
+    }
+
+    let card_key = match generate_card_key(bank) {
+        Ok(card_key) => card_key,
+        Err(e) => {
+            error!(error = %e, "Could not generate a card key");
+            return AdminResponse::ErrorServerInternal;
+        }
+    };
+    // The balance is set by the opening posting below
+    let account = Account {
+        card_key: card_key.clone(),
+        card_number: card_number.clone(),
+        pin,
+        balance: 0.0,
+        name,
+        status: CardStatus::Active,
+        issued_on: Local::now().date_naive(),
+        expires_on: default_expiry(),
+    };
+    bank.insert_account(account);
+    changes.push(Change::Updated(card_number.to_string()));
+    if to_cents(initial_balance) > 0 {
+        let posting = Posting::new(
+            LedgerAccount::OpeningBalances,
+            LedgerAccount::Customer(card_number.to_string()),
+            to_cents(initial_balance),
+            PostingKind::OpeningBalance,
+            None,
+        );
+        bank.post(posting, changes);
+    }
+
+    AdminResponse::AccountCreated {
+        card_number: card_number.to_string(),
+        card_key,
+    }
+}
+
+fn valid_pin(pin: &str) -> bool {
+    (MIN_PIN_LENGTH..=MAX_PIN_LENGTH).contains(&pin.len())
+        && pin.chars().all(|c| c.is_ascii_digit())
+}
+
+// Next account number within the bank's own BIN that was never issued, None
+// once they run out. Closed accounts still have their postings in the books
+fn next_card_number(bank: &Bank) -> Option<CardNumber> {
+    let bin = bank.router.own_bin()?;
+    let booked = bank
+        .ledger
+        .balances()
+        .filter_map(|(account, _)| match account {
+            LedgerAccount::Customer(card_number) => Some(card_number),
+            _ => None,
+        });
+    let last = bank
+        .accounts
+        .keys()
+        .chain(booked)
+        .filter_map(|card_number| CardNumber::parse(card_number).ok())
+        .filter(|card_number| card_number.bin() == bin)
+        .map(|card_number| card_number.account_number())
+        .max()
+        .unwrap_or(0);
+    CardNumber::from_parts(bin, last + 1).ok()
+}
+
+// Terminal ids end up in ledger account names, file names and CSV reports
+fn valid_terminal_id(terminal_id: &str) -> bool {
+    !terminal_id.is_empty()
+        && terminal_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// From the operating system's CSPRNG, as tokens and card keys must not be
+// guessable
+fn random_u64() -> io::Result<u64> {
+    Ok(getrandom::u64()?)
+}
+
+fn generate_card_key(bank: &Bank) -> io::Result<String> {
+    loop {
+        let card_key = format!("key{:016x}", random_u64()?);
+        if !bank
+            .accounts
+            .values()
+            .any(|account| account.card_key == card_key)
+        {
+            return Ok(card_key);
+        }
+    }
+}
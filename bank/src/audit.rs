@@ -0,0 +1,131 @@
+use chrono::Local;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+pub const AUDIT_FILE: &str = "audit.log";
+
+// Previous hash of the first record
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Deserialize)]
+struct Chained {
+    seq: u64,
+    prev: String,
+}
+
+// Append-only record of security-relevant events. Each line is
+// "<sha256 of the record> <record as JSON>" and every record carries the hash
+// of the one before it, so editing, removing or reordering records breaks
+// the chain
+pub struct AuditLog {
+    path: PathBuf,
+    file: File,
+    seq: u64,
+    last_hash: String,
+}
+
+impl AuditLog {
+    // Continues the chain of an existing log. A broken chain is reported as an
+    // error; the caller decides whether to carry on
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<(Self, Result<u64, String>)> {
+        let path = path.into();
+        let verified = verify(&path)?;
+        let (seq, last_hash) = last_record(&path)?;
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok((
+            AuditLog {
+                path,
+                file,
+                seq,
+                last_hash,
+            },
+            verified,
+        ))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Appends one event; `details` must be a JSON object
+    pub fn record(&mut self, actor: &str, event: &str, details: Value) -> io::Result<()> {
+        let mut record = Map::new();
+        record.insert("seq".to_string(), json!(self.seq + 1));
+        record.insert("time".to_string(), json!(Local::now().to_rfc3339()));
+        record.insert("prev".to_string(), json!(self.last_hash));
+        record.insert("actor".to_string(), json!(actor));
+        record.insert("event".to_string(), json!(event));
+        if let Value::Object(details) = details {
+            record.extend(details);
+        }
+
+        let record = serde_json::to_string(&record)?;
+        let hash = hash(&record);
+        self.file
+            .write_all(format!("{} {}\n", hash, record).as_bytes())?;
+        self.file.sync_data()?;
+        self.seq += 1;
+        self.last_hash = hash;
+        Ok(())
+    }
+}
+
+fn hash(record: &str) -> String {
+    Sha256::digest(record.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn last_record(path: &Path) -> io::Result<(u64, String)> {
+    let mut last = (0, GENESIS_HASH.to_string());
+    if !path.exists() {
+        return Ok(last);
+    }
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if let Some((hash, record)) = line.split_once(' ')
+            && let Ok(chained) = serde_json::from_str::<Chained>(record)
+        {
+            last = (chained.seq, hash.to_string());
+        }
+    }
+    Ok(last)
+}
+
+// Checks every record against its hash and the hash of the record before it.
+// Returns the number of records, or the first problem found
+pub fn verify(path: &Path) -> io::Result<Result<u64, String>> {
+    if !path.exists() {
+        return Ok(Ok(0));
+    }
+
+    let mut prev = GENESIS_HASH.to_string();
+    let mut records = 0;
+    for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        let number = number + 1;
+        let Some((stored_hash, record)) = line.split_once(' ') else {
+            return Ok(Err(format!("line {} is not an audit record", number)));
+        };
+        let Ok(chained) = serde_json::from_str::<Chained>(record) else {
+            return Ok(Err(format!("line {} is not an audit record", number)));
+        };
+        if hash(record) != stored_hash {
+            return Ok(Err(format!("record {} was altered", chained.seq)));
+        }
+        if chained.prev != prev || chained.seq != records + 1 {
+            return Ok(Err(format!(
+                "record {} does not follow record {}",
+                chained.seq, records
+            )));
+        }
+        prev = stored_hash.to_string();
+        records += 1;
+    }
+    Ok(Ok(records))
+}
@@ -0,0 +1,489 @@
+use bank::admin::{ADMIN_TOKEN_ENV, ADMIN_TOKEN_FILE};
+use bank::audit::{self, AUDIT_FILE};
+use bank::config::{Config, Overrides};
+use bank::store::{self, ACCOUNTS_FILE};
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand};
+use common::{
+    AdminCommand, AdminRequest, AdminResponse, Settings, SettlementStatus, receive_message,
+    send_message,
+};
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+// Where `create` and `reset-pin` take the PIN from when it is set
+const PIN_ENV: &str = "BANK_ADMIN_PIN";
+
+/// Manage the accounts of a running bank server
+#[derive(Parser)]
+#[command(name = "bank-admin")]
+struct Cli {
+    /// Admin token; defaults to $BANK_ADMIN_TOKEN, then the bank's admin_token file
+    #[arg(long, global = true)]
+    token: Option<String>,
+    /// The bank's configuration file, for its admin socket and data directory
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Action,
+}
+
+#[derive(Subcommand)]
+enum Action {
+    /// List all accounts
+    List,
+    /// Open a new account and issue its card; the PIN is read from $BANK_ADMIN_PIN or stdin
+    Create {
+        #[arg(long)]
+        name: String,
+        #[arg(skip)]
+        pin: String,
+        #[arg(long, default_value_t = 0.0)]
+        balance: f64,
+        /// Card number to use instead of the next free one in the bank's BIN
+        #[arg(long)]
+        card_number: Option<String>,
+    },
+    /// Close an account with a zero balance
+    Close { card_number: String },
+    /// Issue a new card key, replacing the current card
+    IssueKey { card_number: String },
+    /// Set a new PIN, read from $BANK_ADMIN_PIN or stdin
+    ResetPin {
+        card_number: String,
+        #[arg(skip)]
+        new_pin: String,
+    },
+    /// Unblock a blocked card
+    Unlock { card_number: String },
+    /// Credit (positive) or debit (negative) an account
+    Adjust {
+        card_number: String,
+        #[arg(allow_negative_numbers = true)]
+        amount: f64,
+        #[arg(long)]
+        reason: String,
+    },
+    /// Block all use of an account's card
+    Freeze { card_number: String },
+    /// List connected ATMs
+    Sessions,
+    /// Disconnect an ATM session
+    Kick { session_id: u64 },
+    /// Write a copy of all accounts to the snapshots directory
+    Snapshot,
+    /// Re-read routing.json; the settings in bank.toml need a restart
+    ReloadRouting,
+    /// Stop the bank server
+    Shutdown,
+    /// Verify that the books balance and print the trial balance
+    CheckBooks,
+    /// Reconcile each ATM's withdrawals with its cassette counts and write a settlement report
+    Settle {
+        /// Business day to settle, YYYY-MM-DD; defaults to today
+        #[arg(long)]
+        date: Option<NaiveDate>,
+    },
+    /// List the ATMs allowed to connect
+    Terminals,
+    /// Allow a new ATM to connect
+    AddTerminal {
+        terminal_id: String,
+        #[arg(long)]
+        location: String,
+    },
+    /// Stop accepting an ATM and disconnect it
+    RemoveTerminal { terminal_id: String },
+    /// Check the hash chain of the audit log; works without the server
+    VerifyAudit {
+        /// Defaults to audit.log in the bank's data directory
+        #[arg(long)]
+        file: Option<PathBuf>,
+    },
+    /// Give card numbers in accounts.json that fail the Luhn check their check
+    /// digit; run while the server is stopped
+    RepairCardNumbers,
+}
+
+impl Action {
+    // Fills in the PIN of the commands that set one
+    fn read_pin(&mut self) -> io::Result<()> {
+        if let Action::Create { pin, .. } | Action::ResetPin { new_pin: pin, .. } = self {
+            *pin = read_pin()?;
+        }
+        Ok(())
+    }
+}
+
+// Actions bank-admin carries out itself, without the server
+enum LocalAction {
+    VerifyAudit { file: Option<PathBuf> },
+    RepairCardNumbers,
+}
+
+impl TryFrom<Action> for AdminCommand {
+    type Error = LocalAction;
+
+    fn try_from(action: Action) -> Result<Self, LocalAction> {
+        Ok(match action {
+            Action::List => AdminCommand::ListAccounts,
+            Action::Create {
+                name,
+                pin,
+                balance,
+                card_number,
+            } => AdminCommand::CreateAccount {
+                name,
+                pin,
+                initial_balance: balance,
+                card_number,
+            },
+            Action::Close { card_number } => AdminCommand::CloseAccount { card_number },
+            Action::IssueKey { card_number } => AdminCommand::IssueCardKey { card_number },
+            Action::ResetPin {
+                card_number,
+                new_pin,
+            } => AdminCommand::ResetPin {
+                card_number,
+                new_pin,
+            },
+            Action::Unlock { card_number } => AdminCommand::UnlockCard { card_number },
+            Action::Adjust {
+                card_number,
+                amount,
+                reason,
+            } => AdminCommand::AdjustBalance {
+                card_number,
+                amount,
+                reason,
+            },
+            Action::Freeze { card_number } => AdminCommand::FreezeAccount { card_number },
+            Action::Sessions => AdminCommand::ListSessions,
+            Action::Kick { session_id } => AdminCommand::KickSession { session_id },
+            Action::Snapshot => AdminCommand::Snapshot,
+            Action::ReloadRouting => AdminCommand::ReloadRouting,
+            Action::Shutdown => AdminCommand::Shutdown,
+            Action::CheckBooks => AdminCommand::CheckBooks,
+            Action::Settle { date } => AdminCommand::Settle { date },
+            Action::Terminals => AdminCommand::ListTerminals,
+            Action::AddTerminal {
+                terminal_id,
+                location,
+            } => AdminCommand::AddTerminal {
+                terminal_id,
+                location,
+            },
+            Action::RemoveTerminal { terminal_id } => AdminCommand::RemoveTerminal { terminal_id },
+            Action::VerifyAudit { file } => return Err(LocalAction::VerifyAudit { file }),
+            Action::RepairCardNumbers => return Err(LocalAction::RepairCardNumbers),
+        })
+    }
+}
+
+// A PIN on the command line would show up in the process list and the shell
+// history, so it comes from the environment or stdin, unechoed on a terminal
+fn read_pin() -> io::Result<String> {
+    if let Ok(pin) = std::env::var(PIN_ENV) {
+        return Ok(pin);
+    }
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        let mut pin = String::new();
+        stdin.read_line(&mut pin)?;
+        return Ok(pin.trim().to_string());
+    }
+    let pin = prompt_hidden("PIN: ")?;
+    if prompt_hidden("Repeat PIN: ")? != pin {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the PINs do not match",
+        ));
+    }
+    Ok(pin)
+}
+
+fn prompt_hidden(prompt: &str) -> io::Result<String> {
+    eprint!("{}", prompt);
+    let fd = libc::STDIN_FILENO;
+    let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let echoing = termios;
+    termios.c_lflag &= !libc::ECHO;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut line = String::new();
+    let read = io::stdin().read_line(&mut line);
+    // Echo comes back even if the read failed
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &echoing) };
+    eprintln!();
+    read?;
+    Ok(line.trim().to_string())
+}
+
+fn admin_token(config: &Config, token: Option<String>) -> String {
+    token
+        .or_else(|| std::env::var(ADMIN_TOKEN_ENV).ok())
+        .or_else(|| fs::read_to_string(config.data_file(ADMIN_TOKEN_FILE)).ok())
+        .map(|token| token.trim().to_string())
+        .unwrap_or_default()
+}
+
+fn send(config: &Config, request: &AdminRequest) -> io::Result<AdminResponse> {
+    let mut stream = UnixStream::connect(&config.admin_socket_path)?;
+    send_message(&mut stream, request)?;
+    receive_message(&mut stream)
+}
+
+// Prints the outcome and reports whether the command succeeded
+fn print_response(response: AdminResponse) -> bool {
+    match response {
+        AdminResponse::Accounts { accounts } => {
+            println!(
+                "{:<20} {:<24} {:>12} {:<8} {:<10} {:<10}",
+                "CARD NUMBER", "NAME", "BALANCE", "STATUS", "ISSUED", "EXPIRES"
+            );
+            for account in accounts {
+                println!(
+                    "{:<20} {:<24} {:>12.2} {:<8} {:<10} {:<10}",
+                    account.card_number,
+                    account.name,
+                    account.balance,
+                    format!("{:?}", account.status),
+                    account.issued_on,
+                    account.expires_on
+                );
+            }
+        }
+        AdminResponse::AccountCreated {
+            card_number,
+            card_key,
+        } => println!("Created account {} with card key {}", card_number, card_key),
+        AdminResponse::AccountClosed => println!("Account closed"),
+        AdminResponse::CardKeyIssued { card_key } => println!("Issued card key {}", card_key),
+        AdminResponse::PinReset => println!("PIN reset"),
+        AdminResponse::CardUnlocked => println!("Card unlocked"),
+        AdminResponse::BalanceAdjusted { new_balance } => {
+            println!("New balance: {:.2}", new_balance)
+        }
+        AdminResponse::AccountFrozen => println!("Account frozen"),
+        AdminResponse::Sessions { sessions } => {
+            println!(
+                "{:>8} {:<12} {:<20} {:>8} {:<20}",
+                "SESSION", "TERMINAL", "CONNECTED", "COMMANDS", "LAST CARD"
+            );
+            for session in sessions {
+                println!(
+                    "{:>8} {:<12} {:<20} {:>8} {:<20}",
+                    session.session_id,
+                    session.terminal_id.as_deref().unwrap_or("-"),
+                    session.connected_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    session.commands,
+                    session.card.as_deref().unwrap_or("-")
+                );
+            }
+        }
+        AdminResponse::SessionKicked => println!("Session disconnected"),
+        AdminResponse::Terminals { terminals } => {
+            println!("{:<12} {:<30} {:<20}", "TERMINAL", "LOCATION", "LAST SEEN");
+            for terminal in terminals {
+                let last_seen = terminal
+                    .last_seen
+                    .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_else(|| "never".to_string());
+                println!(
+                    "{:<12} {:<30} {:<20}",
+                    terminal.terminal_id, terminal.location, last_seen
+                );
+            }
+        }
+        AdminResponse::TerminalAdded => println!("Terminal added"),
+        AdminResponse::TerminalRemoved => println!("Terminal removed"),
+        AdminResponse::SnapshotWritten { path } => println!("Snapshot written to {}", path),
+        AdminResponse::RoutingReloaded => println!("Routing configuration reloaded"),
+        AdminResponse::ShuttingDown => println!("Bank server is shutting down"),
+        AdminResponse::BooksBalanced { postings, balances } => {
+            println!("{:<30} {:>12}", "LEDGER ACCOUNT", "BALANCE");
+            for line in balances {
+                println!("{:<30} {:>12.2}", line.account, line.balance);
+            }
+            println!("Books balance across {} postings", postings);
+        }
+        AdminResponse::Settled {
+            date,
+            terminals,
+            csv_path,
+            json_path,
+        } => {
+            println!(
+                "{:<12} {:>11} {:>12} {:>12} {:>12}  STATUS",
+                "TERMINAL", "WITHDRAWALS", "BANK", "ATM", "DIFFERENCE"
+            );
+            let optional = |amount: Option<f64>| {
+                amount
+                    .map(|amount| format!("{:.2}", amount))
+                    .unwrap_or_else(|| "-".to_string())
+            };
+            for terminal in &terminals {
+                println!(
+                    "{:<12} {:>11} {:>12.2} {:>12} {:>12}  {:?}",
+                    terminal.terminal_id,
+                    terminal.withdrawals,
+                    terminal.bank_total,
+                    optional(terminal.atm_total),
+                    optional(terminal.difference),
+                    terminal.status
+                );
+            }
+            println!(
+                "Settlement for {} written to {} and {}",
+                date, csv_path, json_path
+            );
+            if terminals
+                .iter()
+                .any(|terminal| terminal.status != SettlementStatus::Balanced)
+            {
+                eprintln!("Warning: not every terminal balanced");
+                return false;
+            }
+        }
+        error => {
+            let message = match error {
+                AdminResponse::ErrorAccountNotFound => "account not found".to_string(),
+                AdminResponse::ErrorAccountExists => "account already exists".to_string(),
+                AdminResponse::ErrorCardNumberUsed => {
+                    "card number belonged to a closed account and is not issued again".to_string()
+                }
+                AdminResponse::ErrorInvalidCardNumber => {
+                    "invalid card number or not one of the bank's BINs".to_string()
+                }
+                AdminResponse::ErrorInvalidPinFormat => "PIN must be 4 to 12 digits".to_string(),
+                AdminResponse::ErrorInvalidAmount => "invalid amount".to_string(),
+                AdminResponse::ErrorMissingReason => "a reason is required".to_string(),
+                AdminResponse::ErrorBalanceNotZero { balance } => {
+                    format!("balance is {:.2}, adjust it to zero first", balance)
+                }
+                AdminResponse::ErrorInsufficientFunds => {
+                    "adjustment would make the balance negative".to_string()
+                }
+                AdminResponse::ErrorCardNotBlocked => "card is not blocked".to_string(),
+                AdminResponse::ErrorSessionNotFound => "no such session".to_string(),
+                AdminResponse::ErrorTerminalExists => "terminal already exists".to_string(),
+                AdminResponse::ErrorTerminalNotFound => "no such terminal".to_string(),
+                AdminResponse::ErrorInvalidTerminalId => {
+                    "terminal ids may only contain letters, digits, '-' and '_'".to_string()
+                }
+                AdminResponse::ErrorRouting { message } => {
+                    format!("routing configuration not reloaded: {}", message)
+                }
+                AdminResponse::ErrorBooksUnbalanced { problems } => {
+                    format!("books do not balance:\n  {}", problems.join("\n  "))
+                }
+                AdminResponse::ErrorUnauthorized => {
+                    format!("wrong admin token (see {})", ADMIN_TOKEN_FILE)
+                }
+                _ => "internal server error".to_string(),
+            };
+            eprintln!("Error: {}", message);
+            return false;
+        }
+    }
+    true
+}
+
+fn verify_audit(path: &Path) -> ExitCode {
+    match audit::verify(path) {
+        Ok(Ok(records)) => {
+            println!("{} holds {} intact records", path.display(), records);
+            ExitCode::SUCCESS
+        }
+        Ok(Err(problem)) => {
+            eprintln!(
+                "Error: {} has been tampered with: {}",
+                path.display(),
+                problem
+            );
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("Error reading {}: {}", path.display(), e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn repair_card_numbers(path: &Path) -> ExitCode {
+    match store::repair_card_numbers(path) {
+        Ok(repaired) if repaired.is_empty() => {
+            println!("Every card number in {} is valid", path.display());
+            ExitCode::SUCCESS
+        }
+        Ok(repaired) => {
+            for (old, new) in &repaired {
+                println!("{} is now {}", old, new);
+            }
+            println!(
+                "Repaired {} card numbers in {}; the previous file is kept as a .bak",
+                repaired.len(),
+                path.display()
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Error repairing {}: {}", path.display(), e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let config = match Config::load(cli.config.as_deref(), &Overrides::default()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut action = cli.command;
+    if let Err(e) = action.read_pin() {
+        eprintln!("Error reading the PIN: {}", e);
+        return ExitCode::FAILURE;
+    }
+    let command = match AdminCommand::try_from(action) {
+        Ok(command) => command,
+        Err(LocalAction::VerifyAudit { file }) => {
+            return verify_audit(&file.unwrap_or_else(|| config.data_file(AUDIT_FILE)));
+        }
+        Err(LocalAction::RepairCardNumbers) => {
+            return repair_card_numbers(&config.data_file(ACCOUNTS_FILE));
+        }
+    };
+    let request = AdminRequest {
+        token: admin_token(&config, cli.token),
+        command,
+    };
+
+    match send(&config, &request) {
+        Ok(response) => {
+            if print_response(response) {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Err(e) => {
+            eprintln!(
+                "Error talking to the bank at {}: {} (is the server running?)",
+                config.admin_socket_path.display(),
+                e
+            );
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use clap::Args;
+use common::{ADMIN_SOCKET_PATH, SOCKET_PATH, Settings, env_var, parse_env};
+use serde::Deserialize;
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+use tracing_subscriber::EnvFilter;
+
+// Read from the working directory when --config is not given
+pub const CONFIG_FILE: &str = "bank.toml";
+
+// Settings of the bank server. Each one comes from, in increasing priority,
+// the default, bank.toml, a BANK_* environment variable and a command-line flag
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub socket_path: PathBuf,
+    pub admin_socket_path: PathBuf,
+    // Loopback host:port to also accept ATMs over TCP
+    pub tcp_address: Option<String>,
+    // "off" disables the Prometheus endpoint
+    pub metrics_address: String,
+    // Accounts, terminals, audit log and the other files the bank keeps
+    pub data_dir: PathBuf,
+    pub store: String,
+    pub log_level: String,
+    pub log_format: String,
+    pub max_withdrawal: f64,
+    pub max_connections: usize,
+    // Seconds ATMs get to finish their last command when the bank stops
+    pub shutdown_timeout: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            socket_path: PathBuf::from(SOCKET_PATH),
+            admin_socket_path: PathBuf::from(ADMIN_SOCKET_PATH),
+            tcp_address: None,
+            metrics_address: "127.0.0.1:9898".to_string(),
+            data_dir: PathBuf::from("."),
+            store: "json".to_string(),
+            log_level: "info".to_string(),
+            log_format: "text".to_string(),
+            max_withdrawal: 5000.0,
+            max_connections: 64,
+            shutdown_timeout: 10,
+        }
+    }
+}
+
+// Command-line flags, each overriding the setting of the same name
+#[derive(Debug, Default, Args)]
+pub struct Overrides {
+    /// Unix socket ATMs connect to
+    #[arg(long, value_name = "PATH")]
+    pub socket_path: Option<PathBuf>,
+    /// Unix socket for bank-admin
+    #[arg(long, value_name = "PATH")]
+    pub admin_socket_path: Option<PathBuf>,
+    /// Also accept ATMs over TCP on this loopback address
+    #[arg(long, value_name = "HOST:PORT")]
+    pub tcp_address: Option<String>,
+    /// Address of the Prometheus endpoint, or "off"
+    #[arg(long, value_name = "HOST:PORT")]
+    pub metrics_address: Option<String>,
+    /// Directory holding the bank's files
+    #[arg(long, value_name = "DIR")]
+    pub data_dir: Option<PathBuf>,
+    /// Account store: json, wal, sqlite or memory
+    #[arg(long)]
+    pub store: Option<String>,
+    /// Log filter, e.g. "debug" or "bank=debug"
+    #[arg(long, value_name = "FILTER")]
+    pub log_level: Option<String>,
+    /// Log format: text or json
+    #[arg(long, value_name = "FORMAT")]
+    pub log_format: Option<String>,
+    /// Largest amount a single withdrawal may be for
+    #[arg(long, value_name = "AMOUNT")]
+    pub max_withdrawal: Option<f64>,
+    /// Most ATM connections served at once
+    #[arg(long, value_name = "COUNT")]
+    pub max_connections: Option<usize>,
+    /// Seconds ATMs get to finish their last command on shutdown
+    #[arg(long, value_name = "SECONDS")]
+    pub shutdown_timeout: Option<u64>,
+}
+
+impl Settings for Config {
+    const FILE: &'static str = CONFIG_FILE;
+
+    type Overrides = Overrides;
+
+    fn apply_env(&mut self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(value) = env_var("BANK_SOCKET_PATH") {
+            self.socket_path = value.into();
+        }
+        if let Some(value) = env_var("BANK_ADMIN_SOCKET_PATH") {
+            self.admin_socket_path = value.into();
+        }
+        if let Some(value) = env_var("BANK_TCP_ADDRESS") {
+            self.tcp_address = Some(value);
+        }
+        if let Some(value) = env_var("BANK_METRICS_ADDR") {
+            self.metrics_address = value;
+        }
+        if let Some(value) = env_var("BANK_DATA_DIR") {
+            self.data_dir = value.into();
+        }
+        if let Some(value) = env_var("BANK_STORE") {
+            self.store = value;
+        }
+        if let Some(value) = env_var("BANK_LOG") {
+            self.log_level = value;
+        }
+        if let Some(value) = env_var("BANK_LOG_FORMAT") {
+            self.log_format = value;
+        }
+        parse_env(
+            "BANK_MAX_WITHDRAWAL",
+            &mut self.max_withdrawal,
+            &mut problems,
+        );
+        parse_env(
+            "BANK_MAX_CONNECTIONS",
+            &mut self.max_connections,
+            &mut problems,
+        );
+        parse_env(
+            "BANK_SHUTDOWN_TIMEOUT",
+            &mut self.shutdown_timeout,
+            &mut problems,
+        );
+        problems
+    }
+
+    fn apply(&mut self, overrides: &Overrides) {
+        if let Some(value) = &overrides.socket_path {
+            self.socket_path = value.clone();
+        }
+        if let Some(value) = &overrides.admin_socket_path {
+            self.admin_socket_path = value.clone();
+        }
+        if let Some(value) = &overrides.tcp_address {
+            self.tcp_address = Some(value.clone());
+        }
+        if let Some(value) = &overrides.metrics_address {
+            self.metrics_address = value.clone();
+        }
+        if let Some(value) = &overrides.data_dir {
+            self.data_dir = value.clone();
+        }
+        if let Some(value) = &overrides.store {
+            self.store = value.clone();
+        }
+        if let Some(value) = &overrides.log_level {
+            self.log_level = value.clone();
+        }
+        if let Some(value) = &overrides.log_format {
+            self.log_format = value.clone();
+        }
+        if let Some(value) = overrides.max_withdrawal {
+            self.max_withdrawal = value;
+        }
+        if let Some(value) = overrides.max_connections {
+            self.max_connections = value;
+        }
+        if let Some(value) = overrides.shutdown_timeout {
+            self.shutdown_timeout = value;
+        }
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.socket_path == self.admin_socket_path {
+            problems.push(format!(
+                "socket_path and admin_socket_path are both {}",
+                self.socket_path.display()
+            ));
+        }
+        if let Some(address) = &self.tcp_address {
+            // PINs cross the connection in the clear and a terminal is known
+            // only by its id, so ATMs elsewhere come in through a tunnel
+            match address.to_socket_addrs() {
+                Ok(mut addrs) => {
+                    if !addrs.all(|addr| addr.ip().is_loopback()) {
+                        problems.push(format!(
+                            "tcp_address \"{}\" is not a loopback address",
+                            address
+                        ));
+                    }
+                }
+                Err(_) => {
+                    problems.push(format!("tcp_address \"{}\" is not host:port", address));
+                }
+            }
+        }
+        if self.metrics_address != "off" && self.metrics_address.to_socket_addrs().is_err() {
+            problems.push(format!(
+                "metrics_address \"{}\" is not host:port or \"off\"",
+                self.metrics_address
+            ));
+        }
+        if !self.data_dir.is_dir() {
+            problems.push(format!(
+                "data_dir {} is not a directory",
+                self.data_dir.display()
+            ));
+        }
+        if !["json", "wal", "sqlite", "memory"].contains(&self.store.as_str()) {
+            problems.push(format!(
+                "store \"{}\" is not json, wal, sqlite or memory",
+                self.store
+            ));
+        }
+        if let Err(e) = EnvFilter::try_new(&self.log_level) {
+            problems.push(format!("log_level \"{}\": {}", self.log_level, e));
+        }
+        if !["text", "json"].contains(&self.log_format.as_str()) {
+            problems.push(format!(
+                "log_format \"{}\" is not text or json",
+                self.log_format
+            ));
+        }
+        if !(self.max_withdrawal.is_finite() && self.max_withdrawal > 0.0) {
+            problems.push(format!(
+                "max_withdrawal {} must be a positive amount",
+                self.max_withdrawal
+            ));
+        }
+        if self.max_connections == 0 {
+            problems.push("max_connections must be at least 1".to_string());
+        }
+        problems
+    }
+}
+
+impl Config {
+    // Files the bank keeps live under data_dir
+    pub fn data_file(&self, name: &str) -> PathBuf {
+        self.data_dir.join(name)
+    }
+}
@@ -0,0 +1,493 @@
+use crate::ledger::{Ledger, LedgerAccount, Posting, PostingKind, Voided};
+use crate::logging::mask_card;
+use crate::metrics::metrics;
+use crate::routing::{Route, Router};
+use crate::settlement::CassetteReports;
+use crate::store::{AccountStore, Change};
+use chrono::Local;
+use common::{Account, CardStatus, Command, Response, from_cents, to_cents};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::time::Instant;
+use tracing::{error, warn};
+
+// State shared by every connection to the bank
+pub struct Bank {
+    pub accounts: HashMap<String, Account>,
+    pub ledger: Ledger,
+    pub router: Router,
+    pub cassette_reports: CassetteReports,
+    // Largest single withdrawal in cents, whoever issued the card
+    max_withdrawal: i64,
+    store: Box<dyn AccountStore>,
+    // Withdrawals reversed before they were booked, by terminal id and
+    // withdrawal id
+    voided: HashSet<(String, String)>,
+    // The accounts changed since the last commit as they were before, None
+    // for new ones, to put back if the store cannot commit the changes
+    before: HashMap<String, Option<Account>>,
+}
+
+impl Bank {
+    pub fn new(
+        mut store: Box<dyn AccountStore>,
+        router: Router,
+        cassette_reports: CassetteReports,
+        max_withdrawal: f64,
+    ) -> io::Result<Self> {
+        let accounts = store.load()?;
+        let history = store.load_history()?;
+        let ledger = Ledger::new(history.postings);
+        let voided = history.voided.iter().map(Voided::key).collect();
+        let mut bank = Bank {
+            accounts,
+            ledger,
+            router,
+            cassette_reports,
+            max_withdrawal: to_cents(max_withdrawal),
+            store,
+            voided,
+            before: HashMap::new(),
+        };
+        bank.open_books()?;
+        let problems = bank.ledger.check(&bank.accounts);
+        if !problems.is_empty() {
+            for problem in &problems {
+                error!(problem = %problem, "Ledger check failed");
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the books do not balance: {}", problems.join("; ")),
+            ));
+        }
+        Ok(bank)
+    }
+
+    // Sets the balance of every account to what its postings add up to, and
+    // books the stored balance of any account the ledger has never seen as
+    // brought forward, so the ledger starts out agreeing with the accounts
+    fn open_books(&mut self) -> io::Result<()> {
+        let mut changes = Vec::new();
+        let mut card_numbers: Vec<String> = self.accounts.keys().cloned().collect();
+        card_numbers.sort();
+        for card_number in card_numbers {
+            let customer = LedgerAccount::Customer(card_number.clone());
+            let balance = to_cents(self.accounts[&card_number].balance);
+            if self.ledger.has_postings(&customer) {
+                let booked = self.ledger.balance(&customer);
+                if booked != balance {
+                    // The store saved the postings of a command but not the
+                    // balances they led to
+                    warn!(
+                        card = %mask_card(&card_number),
+                        stored = from_cents(balance),
+                        booked = from_cents(booked),
+                        "Restored the balance from the ledger"
+                    );
+                    self.account_mut(&card_number).unwrap().balance = from_cents(booked);
+                    changes.push(Change::Updated(card_number));
+                }
+                continue;
+            }
+            if balance == 0 {
+                continue;
+            }
+            let posting = if balance > 0 {
+                Posting::new(
+                    LedgerAccount::OpeningBalances,
+                    customer,
+                    balance,
+                    PostingKind::BroughtForward,
+                    None,
+                )
+            } else {
+                Posting::new(
+                    customer,
+                    LedgerAccount::OpeningBalances,
+                    -balance,
+                    PostingKind::BroughtForward,
+                    None,
+                )
+            };
+            self.post(posting, &mut changes);
+        }
+        self.commit(&changes)
+    }
+
+    // Handles a command from the registered ATM `terminal_id`
+    pub fn handle(&mut self, terminal_id: &str, command: Command) -> io::Result<Response> {
+        let mut changes = Vec::new();
+        if let Command::Withdraw { amount, .. } = &command
+            && to_cents(*amount) > self.max_withdrawal
+        {
+            return Ok(Response::WithdrawErrorInvalidAmount);
+        }
+        // A withdrawal that was held up until after the ATM reversed it
+        if let Command::Withdraw { withdrawal_id, .. } = &command
+            && self
+                .voided
+                .contains(&(terminal_id.to_string(), withdrawal_id.clone()))
+        {
+            return Ok(Response::WithdrawErrorReversed);
+        }
+        // Booking the id again would debit the customer twice, while a
+        // reversal only gives back the first
+        if let Command::Withdraw { withdrawal_id, .. } = &command
+            && self
+                .find_posting(terminal_id, &withdrawal_description(withdrawal_id))
+                .is_some()
+        {
+            warn!(
+                terminal = %terminal_id,
+                withdrawal_id,
+                "Refused a withdrawal id that was already booked"
+            );
+            return Ok(Response::WithdrawErrorDuplicate);
+        }
+        // The ATM resends advices it got no answer to
+        if let Command::WithdrawalAdvice { advice_id, .. } = &command
+            && self.has_advice(terminal_id, advice_id)
+        {
+            return Ok(Response::AdviceRecorded);
+        }
+        let response = match self.router.route(&command) {
+            Route::OnUs => self.handle_on_us(terminal_id, command, &mut changes),
+            Route::Foreign(issuer) => {
+                self.handle_foreign(terminal_id, &issuer, command, &mut changes)
+            }
+            Route::Unsupported => Response::ErrorCardNotSupported,
+            Route::InvalidCardNumber => Response::ErrorInvalidCardNumber,
+        };
+        self.commit(&changes)?;
+        Ok(response)
+    }
+
+    // Books a posting and sets the balance of any customer account it touches
+    // to what the ledger now holds for it
+    pub fn post(&mut self, posting: Posting, changes: &mut Vec<Change>) {
+        let touched = [posting.debit.clone(), posting.credit.clone()];
+        self.ledger.post(posting.clone());
+        for ledger_account in &touched {
+            let balance = from_cents(self.ledger.balance(ledger_account));
+            if let LedgerAccount::Customer(card_number) = ledger_account
+                && let Some(account) = self.account_mut(card_number)
+            {
+                account.balance = balance;
+                changes.push(Change::Updated(card_number.clone()));
+            }
+        }
+        changes.push(Change::Posted(posting));
+    }
+
+    // Changes to accounts go through these, so that they can be undone
+    pub fn account_mut(&mut self, card_number: &str) -> Option<&mut Account> {
+        let account = self.accounts.get(card_number)?;
+        self.before
+            .entry(card_number.to_string())
+            .or_insert_with(|| Some(account.clone()));
+        self.accounts.get_mut(card_number)
+    }
+
+    pub fn insert_account(&mut self, account: Account) {
+        self.before
+            .entry(account.card_number.to_string())
+            .or_insert_with(|| self.accounts.get(account.card_number.as_str()).cloned());
+        self.accounts
+            .insert(account.card_number.to_string(), account);
+    }
+
+    pub fn remove_account(&mut self, card_number: &str) -> Option<Account> {
+        let account = self.accounts.remove(card_number)?;
+        self.before
+            .entry(card_number.to_string())
+            .or_insert_with(|| Some(account.clone()));
+        Some(account)
+    }
+
+    // Persists changes made to `accounts` before the caller replies. If the
+    // store fails, the accounts and the ledger are put back as they were
+    // before the changes, so memory never holds what the store does not
+    pub fn commit(&mut self, changes: &[Change]) -> io::Result<()> {
+        let before = std::mem::take(&mut self.before);
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let started = Instant::now();
+        let result = self.store.commit(&self.accounts, changes);
+        metrics().store_commit(started.elapsed());
+        if let Err(e) = &result {
+            error!(error = %e, "Cannot commit changes; rolled them back");
+            let posted = changes
+                .iter()
+                .filter(|change| matches!(change, Change::Posted(_)))
+                .count();
+            self.ledger
+                .truncate(self.ledger.postings().len().saturating_sub(posted));
+            for change in changes {
+                if let Change::Voided(voided) = change {
+                    self.voided.remove(&voided.key());
+                }
+            }
+            for (card_number, account) in before {
+                match account {
+                    Some(account) => self.accounts.insert(card_number, account),
+                    None => self.accounts.remove(&card_number),
+                };
+            }
+        }
+        result
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.store.flush(&self.accounts)
+    }
+
+    fn has_advice(&self, terminal_id: &str, advice_id: &str) -> bool {
+        self.find_posting(terminal_id, &advice_description(advice_id))
+            .is_some()
+    }
+
+    fn find_posting(&self, terminal_id: &str, description: &str) -> Option<&Posting> {
+        self.ledger.find(terminal_id, description)
+    }
+
+    // Gives back what was booked for the withdrawal, once. A withdrawal the
+    // bank never booked is marked as reversed instead, so that it is refused
+    // if it arrives after all
+    fn reverse(
+        &mut self,
+        terminal_id: &str,
+        withdrawal_id: &str,
+        changes: &mut Vec<Change>,
+    ) -> Response {
+        let description = reversal_description(withdrawal_id);
+        if self.find_posting(terminal_id, &description).is_some() {
+            return Response::WithdrawalReversed;
+        }
+        let Some(withdrawal) =
+            self.find_posting(terminal_id, &withdrawal_description(withdrawal_id))
+        else {
+            let voided = Voided::new(terminal_id, withdrawal_id);
+            if self.voided.insert(voided.key()) {
+                changes.push(Change::Voided(voided));
+            }
+            return Response::WithdrawalNotFound;
+        };
+        let mut posting = Posting::new(
+            withdrawal.credit.clone(),
+            withdrawal.debit.clone(),
+            withdrawal.amount,
+            PostingKind::Reversal,
+            Some(description),
+        )
+        .at_terminal(terminal_id);
+        posting.created_at = withdrawal.created_at;
+        self.post(posting, changes);
+        Response::WithdrawalReversed
+    }
+
+    // Books cash paid out for other issuers' cards as owed to us by the network
+    fn handle_foreign(
+        &mut self,
+        terminal_id: &str,
+        issuer: &str,
+        command: Command,
+        changes: &mut Vec<Change>,
+    ) -> Response {
+        let response = self.router.forward(issuer, &command);
+        let (amount, description, created_at) = match (&command, &response) {
+            (
+                Command::Withdraw {
+                    withdrawal_id,
+                    amount,
+                    ..
+                },
+                Response::WithdrawSuccess { .. },
+            ) => (
+                *amount,
+                Some(withdrawal_description(withdrawal_id)),
+                Local::now(),
+            ),
+            (Command::ReverseWithdrawal { withdrawal_id, .. }, Response::WithdrawalReversed) => {
+                return self.reverse(terminal_id, withdrawal_id, changes);
+            }
+            (
+                Command::WithdrawalAdvice {
+                    advice_id,
+                    amount,
+                    approved_at,
+                    ..
+                },
+                Response::AdviceRecorded,
+            ) => (*amount, Some(advice_description(advice_id)), *approved_at),
+            _ => return response,
+        };
+        let amount = to_cents(amount);
+        if amount > 0 {
+            let mut posting = Posting::new(
+                LedgerAccount::Network(issuer.to_string()),
+                LedgerAccount::AtmCash(terminal_id.to_string()),
+                amount,
+                PostingKind::Withdrawal,
+                description,
+            )
+            .at_terminal(terminal_id);
+            posting.created_at = created_at;
+            self.post(posting, changes);
+        }
+        response
+    }
+
+    fn handle_on_us(
+        &mut self,
+        terminal_id: &str,
+        command: Command,
+        changes: &mut Vec<Change>,
+    ) -> Response {
+        match command {
+            // Answered by the server when the connection is set up
+            Command::RegisterTerminal { .. } => Response::ErrorServerInternal,
+            Command::ValidateCardKey { card_key } => {
+                match self
+                    .accounts
+                    .values()
+                    .find(|account| account.card_key == card_key)
+                {
+                    Some(account) => match card_status_error(account) {
+                        Some(error) => error,
+                        None => Response::ValidateCardKeySuccess {
+                            card_number: account.card_number.to_string(),
+                        },
+                    },
+                    None => Response::ValidateCardKeyErrorInvalid,
+                }
+            }
+            Command::Withdraw {
+                withdrawal_id,
+                card_number,
+                pin,
+                amount,
+            } => {
+                let Some(account) = self.accounts.get(&card_number) else {
+                    return Response::ErrorCardNotFound;
+                };
+                let amount = to_cents(amount);
+                if let Some(error) = card_status_error(account) {
+                    error
+                } else if account.pin != pin {
+                    Response::ErrorInvalidPin
+                } else if amount <= 0 {
+                    Response::WithdrawErrorInvalidAmount
+                } else if to_cents(account.balance) >= amount {
+                    let posting = Posting::new(
+                        LedgerAccount::Customer(card_number.clone()),
+                        LedgerAccount::AtmCash(terminal_id.to_string()),
+                        amount,
+                        PostingKind::Withdrawal,
+                        Some(withdrawal_description(&withdrawal_id)),
+                    )
+                    .at_terminal(terminal_id);
+                    self.post(posting, changes);
+                    Response::WithdrawSuccess {
+                        new_balance: self.accounts[&card_number].balance,
+                    }
+                } else {
+                    Response::WithdrawErrorInsufficientFunds
+                }
+            }
+            Command::CheckBalance { card_number, pin } => {
+                if let Some(account) = self.accounts.get(&card_number) {
+                    if let Some(error) = card_status_error(account) {
+                        error
+                    } else if account.pin != pin {
+                        Response::ErrorInvalidPin
+                    } else {
+                        Response::CheckBalanceSuccess {
+                            amount: account.balance,
+                        }
+                    }
+                } else {
+                    Response::ErrorCardNotFound
+                }
+            }
+            // Whatever the card's status, since the money is the customer's
+            Command::ReverseWithdrawal { withdrawal_id, .. } => {
+                self.reverse(terminal_id, &withdrawal_id, changes)
+            }
+            // Booked whatever the card's status or balance, since the cash is
+            // already paid out; a negative balance is left for the bank to
+            // recover from the customer
+            Command::WithdrawalAdvice {
+                advice_id,
+                card_number,
+                amount,
+                approved_at,
+            } => {
+                if !self.accounts.contains_key(&card_number) {
+                    return Response::ErrorCardNotFound;
+                }
+                let amount = to_cents(amount);
+                if amount <= 0 {
+                    return Response::WithdrawErrorInvalidAmount;
+                }
+                let mut posting = Posting::new(
+                    LedgerAccount::Customer(card_number.clone()),
+                    LedgerAccount::AtmCash(terminal_id.to_string()),
+                    amount,
+                    PostingKind::Withdrawal,
+                    Some(advice_description(&advice_id)),
+                )
+                .at_terminal(terminal_id);
+                // Settled on the business day the cash left the ATM
+                posting.created_at = approved_at;
+                self.post(posting, changes);
+                let balance = self.accounts[&card_number].balance;
+                if balance < 0.0 {
+                    warn!(
+                        card = %mask_card(&card_number),
+                        balance,
+                        "Stand-in withdrawal overdrew the account"
+                    );
+                }
+                Response::AdviceRecorded
+            }
+            Command::ReportCassettes {
+                business_date,
+                cassettes,
+            } => match self.cassette_reports.record(
+                terminal_id.to_string(),
+                business_date,
+                cassettes,
+            ) {
+                Ok(()) => Response::CassettesRecorded,
+                Err(e) => {
+                    error!(error = %e, "Error saving cassette report");
+                    Response::ErrorServerInternal
+                }
+            },
+        }
+    }
+}
+
+// Postings carry the id the ATM gave them, so each is booked or reversed once
+fn withdrawal_description(withdrawal_id: &str) -> String {
+    format!("withdrawal {}", withdrawal_id)
+}
+
+fn reversal_description(withdrawal_id: &str) -> String {
+    format!("reversal {}", withdrawal_id)
+}
+
+fn advice_description(advice_id: &str) -> String {
+    format!("stand-in {}", advice_id)
+}
+
+// Rejects cards that may not be used, whatever the command
+fn card_status_error(account: &Account) -> Option<Response> {
+    match account.status {
+        CardStatus::Blocked => Some(Response::ErrorCardBlocked),
+        CardStatus::Lost | CardStatus::Stolen => Some(Response::ErrorCardLost),
+        _ if account.is_expired_on(Local::now().date_naive()) => Some(Response::ErrorCardExpired),
+        _ => None,
+    }
+}
@@ -0,0 +1,298 @@
+use chrono::{DateTime, Local};
+use common::{Account, from_cents, to_cents};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+// An account in the books: a customer's account or one of the bank's own
+// general ledger accounts
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LedgerAccount {
+    Customer(String),
+    // Cash held in one ATM, by terminal id; empty for payouts booked before
+    // terminals were told apart
+    AtmCash(String),
+    // Owed to the bank by the card network for payouts on foreign cards
+    Network(String),
+    // Counterpart of balance corrections made by operators
+    Adjustments,
+    // Counterpart of money that was on accounts before it was booked here
+    OpeningBalances,
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LedgerAccount::Customer(card_number) => write!(f, "customer:{}", card_number),
+            LedgerAccount::AtmCash(terminal_id) if terminal_id.is_empty() => write!(f, "atm_cash"),
+            LedgerAccount::AtmCash(terminal_id) => write!(f, "atm_cash:{}", terminal_id),
+            LedgerAccount::Network(issuer) => write!(f, "network:{}", issuer),
+            LedgerAccount::Adjustments => write!(f, "adjustments"),
+            LedgerAccount::OpeningBalances => write!(f, "opening_balances"),
+        }
+    }
+}
+
+impl FromStr for LedgerAccount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "atm_cash" => Ok(LedgerAccount::AtmCash(String::new())),
+            "adjustments" => Ok(LedgerAccount::Adjustments),
+            "opening_balances" => Ok(LedgerAccount::OpeningBalances),
+            _ => match s.split_once(':') {
+                Some(("customer", card_number)) => {
+                    Ok(LedgerAccount::Customer(card_number.to_string()))
+                }
+                Some(("atm_cash", terminal_id)) => {
+                    Ok(LedgerAccount::AtmCash(terminal_id.to_string()))
+                }
+                Some(("network", issuer)) => Ok(LedgerAccount::Network(issuer.to_string())),
+                _ => Err(format!("unknown ledger account {}", s)),
+            },
+        }
+    }
+}
+
+// Stored under the names used in ledger account names and the database
+impl Serialize for LedgerAccount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for LedgerAccount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PostingKind {
+    Withdrawal,
+    // Gives back a withdrawal the ATM did not pay out
+    Reversal,
+    Adjustment,
+    OpeningBalance,
+    // Books a stored balance that has no postings behind it
+    BroughtForward,
+}
+
+impl PostingKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PostingKind::Withdrawal => "withdrawal",
+            PostingKind::Reversal => "reversal",
+            PostingKind::Adjustment => "adjustment",
+            PostingKind::OpeningBalance => "opening_balance",
+            PostingKind::BroughtForward => "brought_forward",
+        }
+    }
+}
+
+impl FromStr for PostingKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "withdrawal" => Ok(PostingKind::Withdrawal),
+            "reversal" => Ok(PostingKind::Reversal),
+            "adjustment" => Ok(PostingKind::Adjustment),
+            "opening_balance" => Ok(PostingKind::OpeningBalance),
+            "brought_forward" => Ok(PostingKind::BroughtForward),
+            _ => Err(format!("unknown posting kind {}", s)),
+        }
+    }
+}
+
+impl Serialize for PostingKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for PostingKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+// Moves `amount` cents, always positive, from the debit account to the credit account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Posting {
+    pub debit: LedgerAccount,
+    pub credit: LedgerAccount,
+    pub amount: i64,
+    pub kind: PostingKind,
+    pub description: Option<String>,
+    // The ATM the posting was made from; None for operator postings
+    pub terminal_id: Option<String>,
+    pub created_at: DateTime<Local>,
+}
+
+impl Posting {
+    pub fn new(
+        debit: LedgerAccount,
+        credit: LedgerAccount,
+        amount: i64,
+        kind: PostingKind,
+        description: Option<String>,
+    ) -> Self {
+        Posting {
+            debit,
+            credit,
+            amount,
+            kind,
+            description,
+            terminal_id: None,
+            created_at: Local::now(),
+        }
+    }
+
+    pub fn at_terminal(mut self, terminal_id: &str) -> Self {
+        self.terminal_id = Some(terminal_id.to_string());
+        self
+    }
+}
+
+// A withdrawal reversed before the bank booked it, so that it is refused if
+// it arrives after all
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Voided {
+    pub terminal_id: String,
+    pub withdrawal_id: String,
+    pub created_at: DateTime<Local>,
+}
+
+impl Voided {
+    pub fn new(terminal_id: &str, withdrawal_id: &str) -> Self {
+        Voided {
+            terminal_id: terminal_id.to_string(),
+            withdrawal_id: withdrawal_id.to_string(),
+            created_at: Local::now(),
+        }
+    }
+
+    pub fn key(&self) -> (String, String) {
+        (self.terminal_id.clone(), self.withdrawal_id.clone())
+    }
+}
+
+// Every posting made since the books were opened, and the balances they add up to.
+// Balances are credits minus debits, so a customer's balance is what the bank owes them
+#[derive(Default)]
+pub struct Ledger {
+    postings: Vec<Posting>,
+    balances: BTreeMap<LedgerAccount, i64>,
+    // Postings made from an ATM by terminal id and description, which names
+    // the id the ATM gave the withdrawal or advice
+    by_reference: HashMap<(String, String), usize>,
+}
+
+impl Ledger {
+    pub fn new(postings: Vec<Posting>) -> Self {
+        let mut ledger = Ledger::default();
+        for posting in postings {
+            ledger.post(posting);
+        }
+        ledger
+    }
+
+    pub fn post(&mut self, posting: Posting) {
+        *self.balances.entry(posting.debit.clone()).or_default() -= posting.amount;
+        *self.balances.entry(posting.credit.clone()).or_default() += posting.amount;
+        if let (Some(terminal_id), Some(description)) = (&posting.terminal_id, &posting.description)
+        {
+            self.by_reference
+                .entry((terminal_id.clone(), description.clone()))
+                .or_insert(self.postings.len());
+        }
+        self.postings.push(posting);
+    }
+
+    // The first posting made from the ATM with this description
+    pub fn find(&self, terminal_id: &str, description: &str) -> Option<&Posting> {
+        let index = self
+            .by_reference
+            .get(&(terminal_id.to_string(), description.to_string()))?;
+        Some(&self.postings[*index])
+    }
+
+    // Drops every posting after the first `len`, as if it had never been made
+    pub fn truncate(&mut self, len: usize) {
+        let mut postings = std::mem::take(&mut self.postings);
+        postings.truncate(len);
+        *self = Ledger::new(postings);
+    }
+
+    pub fn balance(&self, account: &LedgerAccount) -> i64 {
+        self.balances.get(account).copied().unwrap_or(0)
+    }
+
+    pub fn has_postings(&self, account: &LedgerAccount) -> bool {
+        self.balances.contains_key(account)
+    }
+
+    pub fn postings(&self) -> &[Posting] {
+        &self.postings
+    }
+
+    pub fn balances(&self) -> impl Iterator<Item = (&LedgerAccount, i64)> {
+        self.balances
+            .iter()
+            .map(|(account, balance)| (account, *balance))
+    }
+
+    // Recomputes every balance from the postings and lists anything that does
+    // not add up; an empty list proves the books balance
+    pub fn check(&self, accounts: &HashMap<String, Account>) -> Vec<String> {
+        let mut problems = Vec::new();
+        let recomputed = Ledger::new(self.postings.clone());
+
+        for posting in &self.postings {
+            if posting.amount <= 0 || posting.debit == posting.credit {
+                problems.push(format!(
+                    "invalid posting of {} from {} to {}",
+                    posting.amount, posting.debit, posting.credit
+                ));
+            }
+        }
+
+        let total: i64 = recomputed.balances.values().sum();
+        if total != 0 {
+            problems.push(format!("balances sum to {:.2}", from_cents(total)));
+        }
+
+        for (account, balance) in &recomputed.balances {
+            if self.balance(account) != *balance {
+                problems.push(format!(
+                    "{} is {:.2} but its postings add up to {:.2}",
+                    account,
+                    from_cents(self.balance(account)),
+                    from_cents(*balance)
+                ));
+            }
+        }
+
+        for account in accounts.values() {
+            let booked =
+                recomputed.balance(&LedgerAccount::Customer(account.card_number.to_string()));
+            if to_cents(account.balance) != booked {
+                problems.push(format!(
+                    "account {} shows {:.2} but the ledger has {:.2}",
+                    account.card_number,
+                    account.balance,
+                    from_cents(booked)
+                ));
+            }
+        }
+
+        problems
+    }
+}
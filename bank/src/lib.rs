@@ -0,0 +1,14 @@
+pub mod admin;
+pub mod audit;
+pub mod config;
+pub mod handler;
+pub mod ledger;
+pub mod logging;
+pub mod metrics;
+pub mod routing;
+pub mod server;
+pub mod session;
+pub mod settlement;
+pub mod store;
+pub mod terminal;
+pub mod testing;
@@ -0,0 +1,162 @@
+use crate::config::Config;
+use common::{AdminCommand, CardNumber, Command};
+use serde::Serialize;
+use std::fmt;
+use std::io::{self, IsTerminal};
+use tracing_subscriber::EnvFilter;
+
+// The filter is in the RUST_LOG syntax, e.g. "debug" or "bank=debug"; the
+// config has already checked that it parses
+pub fn init(config: &Config) {
+    let filter = EnvFilter::new(&config.log_level);
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(io::stdout().is_terminal());
+    if config.log_format == "json" {
+        builder.json().with_current_span(true).init();
+    } else {
+        builder.init();
+    }
+}
+
+// Shows only the last four digits, also of numbers that are not valid cards
+pub fn mask_card(card_number: &str) -> String {
+    match CardNumber::parse(card_number) {
+        Ok(card_number) => card_number.masked(),
+        Err(_) => {
+            let visible = card_number.len().saturating_sub(4);
+            let tail: String = card_number.chars().skip(visible).collect();
+            format!("{}{}", "x".repeat(visible), tail)
+        }
+    }
+}
+
+// Name of an enum variant as it goes over the wire, e.g. "Withdraw"
+pub fn variant_name<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        Ok(serde_json::Value::Object(fields)) => fields.keys().next().cloned().unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+// Debug output of a command without its PINs and card keys, and with card
+// numbers masked
+pub struct Redacted<'a, T>(pub &'a T);
+
+const HIDDEN: &str = "<redacted>";
+
+impl fmt::Debug for Redacted<'_, Command> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Command::RegisterTerminal {
+                terminal_id,
+                location,
+            } => f
+                .debug_struct("RegisterTerminal")
+                .field("terminal_id", terminal_id)
+                .field("location", location)
+                .finish(),
+            Command::ValidateCardKey { .. } => f
+                .debug_struct("ValidateCardKey")
+                .field("card_key", &HIDDEN)
+                .finish(),
+            Command::Withdraw {
+                withdrawal_id,
+                card_number,
+                amount,
+                ..
+            } => f
+                .debug_struct("Withdraw")
+                .field("withdrawal_id", withdrawal_id)
+                .field("card_number", &mask_card(card_number))
+                .field("pin", &HIDDEN)
+                .field("amount", amount)
+                .finish(),
+            Command::ReverseWithdrawal {
+                withdrawal_id,
+                card_number,
+                amount,
+            } => f
+                .debug_struct("ReverseWithdrawal")
+                .field("withdrawal_id", withdrawal_id)
+                .field("card_number", &mask_card(card_number))
+                .field("amount", amount)
+                .finish(),
+            Command::CheckBalance { card_number, .. } => f
+                .debug_struct("CheckBalance")
+                .field("card_number", &mask_card(card_number))
+                .field("pin", &HIDDEN)
+                .finish(),
+            Command::WithdrawalAdvice {
+                advice_id,
+                card_number,
+                amount,
+                ..
+            } => f
+                .debug_struct("WithdrawalAdvice")
+                .field("advice_id", advice_id)
+                .field("card_number", &mask_card(card_number))
+                .field("amount", amount)
+                .finish_non_exhaustive(),
+            Command::ReportCassettes { business_date, .. } => f
+                .debug_struct("ReportCassettes")
+                .field("business_date", business_date)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+impl fmt::Debug for Redacted<'_, AdminCommand> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut command = f.debug_struct(&variant_name(self.0));
+        match self.0 {
+            AdminCommand::CreateAccount {
+                name,
+                initial_balance,
+                card_number,
+                ..
+            } => command
+                .field("name", name)
+                .field("pin", &HIDDEN)
+                .field("initial_balance", initial_balance)
+                .field("card_number", &card_number.as_deref().map(mask_card)),
+            AdminCommand::ResetPin { card_number, .. } => command
+                .field("card_number", &mask_card(card_number))
+                .field("new_pin", &HIDDEN),
+            AdminCommand::AdjustBalance {
+                card_number,
+                amount,
+                reason,
+            } => command
+                .field("card_number", &mask_card(card_number))
+                .field("amount", amount)
+                .field("reason", reason),
+            AdminCommand::CloseAccount { card_number }
+            | AdminCommand::IssueCardKey { card_number }
+            | AdminCommand::UnlockCard { card_number }
+            | AdminCommand::FreezeAccount { card_number } => {
+                command.field("card_number", &mask_card(card_number))
+            }
+            AdminCommand::KickSession { session_id } => command.field("session_id", session_id),
+            AdminCommand::Settle { date } => command.field("date", date),
+            AdminCommand::AddTerminal {
+                terminal_id,
+                location,
+            } => command
+                .field("terminal_id", terminal_id)
+                .field("location", location),
+            AdminCommand::RemoveTerminal { terminal_id } => {
+                command.field("terminal_id", terminal_id)
+            }
+            AdminCommand::ListAccounts
+            | AdminCommand::ListSessions
+            | AdminCommand::Snapshot
+            | AdminCommand::ReloadRouting
+            | AdminCommand::Shutdown
+            | AdminCommand::CheckBooks
+            | AdminCommand::ListTerminals => &mut command,
+        };
+        command.finish()
+    }
+}
@@ -0,0 +1,231 @@
+use crate::server::State;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+// Upper bounds in seconds, shared by every latency histogram
+const BUCKETS: [f64; 12] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+#[derive(Default)]
+struct Histogram {
+    // Observations per bucket, not yet cumulative
+    counts: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.counts[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(self.counts) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, separator, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+            name, labels, separator, self.count
+        );
+        let braces = |labels: &str| {
+            if labels.is_empty() {
+                String::new()
+            } else {
+                format!("{{{}}}", labels)
+            }
+        };
+        let _ = writeln!(out, "{}_sum{} {}", name, braces(labels), self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, braces(labels), self.count);
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    commands: BTreeMap<String, u64>,
+    responses: BTreeMap<(String, String), u64>,
+    command_seconds: BTreeMap<String, Histogram>,
+    failed_pins: u64,
+    store_commit_seconds: Histogram,
+}
+
+// Counters and histograms for the whole process
+#[derive(Default)]
+pub struct Metrics {
+    registry: Mutex<Registry>,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    // Records one ATM command, the variant of its response and how long it took
+    pub fn command(&self, command: &str, response: &str, elapsed: Duration) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry.commands.entry(command.to_string()).or_default() += 1;
+        *registry
+            .responses
+            .entry((command.to_string(), response.to_string()))
+            .or_default() += 1;
+        registry
+            .command_seconds
+            .entry(command.to_string())
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+        if response == "ErrorInvalidPin" {
+            registry.failed_pins += 1;
+        }
+    }
+
+    pub fn store_commit(&self, elapsed: Duration) {
+        self.registry
+            .lock()
+            .unwrap()
+            .store_commit_seconds
+            .observe(elapsed.as_secs_f64());
+    }
+
+    // The Prometheus text exposition format
+    pub fn render(&self, connected_atms: usize) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "# HELP bank_connected_atms ATM sessions currently open."
+        );
+        let _ = writeln!(out, "# TYPE bank_connected_atms gauge");
+        let _ = writeln!(out, "bank_connected_atms {}", connected_atms);
+
+        let _ = writeln!(
+            out,
+            "# HELP bank_commands_total ATM commands handled, by type."
+        );
+        let _ = writeln!(out, "# TYPE bank_commands_total counter");
+        for (command, count) in &registry.commands {
+            let _ = writeln!(
+                out,
+                "bank_commands_total{{command=\"{}\"}} {}",
+                command, count
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP bank_responses_total Responses sent to ATMs, by command and response."
+        );
+        let _ = writeln!(out, "# TYPE bank_responses_total counter");
+        for ((command, response), count) in &registry.responses {
+            let _ = writeln!(
+                out,
+                "bank_responses_total{{command=\"{}\",response=\"{}\"}} {}",
+                command, response, count
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP bank_command_duration_seconds Time to process an ATM command."
+        );
+        let _ = writeln!(out, "# TYPE bank_command_duration_seconds histogram");
+        for (command, histogram) in &registry.command_seconds {
+            histogram.render(
+                &mut out,
+                "bank_command_duration_seconds",
+                &format!("command=\"{}\"", command),
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP bank_failed_pin_attempts_total Commands rejected for a wrong PIN."
+        );
+        let _ = writeln!(out, "# TYPE bank_failed_pin_attempts_total counter");
+        let _ = writeln!(
+            out,
+            "bank_failed_pin_attempts_total {}",
+            registry.failed_pins
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP bank_store_commit_duration_seconds Time to persist the changes of one command."
+        );
+        let _ = writeln!(out, "# TYPE bank_store_commit_duration_seconds histogram");
+        registry
+            .store_commit_seconds
+            .render(&mut out, "bank_store_commit_duration_seconds", "");
+
+        out
+    }
+}
+
+// Serves GET /metrics over plain HTTP until the process exits
+pub fn serve(listener: TcpListener, state: Arc<State>) {
+    if let Ok(address) = listener.local_addr() {
+        info!(%address, "Serving metrics");
+    }
+    for stream in listener.incoming() {
+        if state.is_shutting_down() {
+            break;
+        }
+        let result = stream.and_then(|stream| respond(stream, &state));
+        if let Err(e) = result {
+            warn!(error = %e, "Error serving metrics");
+        }
+    }
+}
+
+fn respond(mut stream: TcpStream, state: &State) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers; the request has no body
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let connected = state.sessions.lock().unwrap().list().len();
+            ("200 OK", metrics().render(connected))
+        }
+        _ => ("404 Not Found", "Not found\n".to_string()),
+    };
+    write!(
+        stream,
+        concat!(
+            "HTTP/1.1 {}\r\n",
+            "Content-Type: text/plain; version=0.0.4\r\n",
+            "Content-Length: {}\r\n",
+            "Connection: close\r\n\r\n{}"
+        ),
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
@@ -0,0 +1,176 @@
+use common::{
+    BinRange, CardNumber, Command, Response, Stream, find_issuer, receive_response, send_command,
+};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use tracing::error;
+
+pub const ROUTING_FILE: &str = "routing.json";
+
+// What to do with valid cards whose BIN belongs to another issuer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForeignCards {
+    Reject,
+    Forward { socket_path: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingConfig {
+    pub own_issuer: String,
+    pub bin_ranges: Vec<BinRange>,
+    pub foreign_cards: ForeignCards,
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        let own_issuer = "ATM Bank".to_string();
+        RoutingConfig {
+            bin_ranges: vec![
+                BinRange {
+                    low: 123456,
+                    high: 123456,
+                    issuer: own_issuer.clone(),
+                },
+                BinRange {
+                    low: 987654,
+                    high: 987654,
+                    issuer: own_issuer.clone(),
+                },
+                BinRange {
+                    low: 400000,
+                    high: 499999,
+                    issuer: "Visa".to_string(),
+                },
+                BinRange {
+                    low: 510000,
+                    high: 559999,
+                    issuer: "Mastercard".to_string(),
+                },
+            ],
+            own_issuer,
+            foreign_cards: ForeignCards::Reject,
+        }
+    }
+}
+
+impl RoutingConfig {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        if !path.exists() {
+            return Ok(RoutingConfig::default());
+        }
+
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+        serde_json::from_reader(reader).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid {}: {}", path.display(), e),
+            )
+        })
+    }
+}
+
+pub enum Route {
+    OnUs,
+    Foreign(String),
+    Unsupported,
+    InvalidCardNumber,
+}
+
+// Hands commands for cards of other issuers to a card network
+pub trait NetworkHandler: Send {
+    fn forward(&mut self, issuer: &str, command: &Command) -> Response;
+}
+
+pub struct RejectForeignCards;
+
+impl NetworkHandler for RejectForeignCards {
+    fn forward(&mut self, _issuer: &str, _command: &Command) -> Response {
+        Response::ErrorCardNotSupported
+    }
+}
+
+// Relays commands to a network gateway speaking the same protocol as the bank
+pub struct SocketNetwork {
+    socket_path: String,
+}
+
+impl SocketNetwork {
+    fn relay(&self, command: &Command) -> io::Result<Response> {
+        let mut stream = Stream::Unix(UnixStream::connect(&self.socket_path)?);
+        send_command(&mut stream, command)?;
+        receive_response(&mut stream)
+    }
+}
+
+impl NetworkHandler for SocketNetwork {
+    fn forward(&mut self, issuer: &str, command: &Command) -> Response {
+        match self.relay(command) {
+            Ok(response) => response,
+            Err(e) => {
+                error!(issuer, error = %e, "Error forwarding command");
+                Response::ErrorServerInternal
+            }
+        }
+    }
+}
+
+pub struct Router {
+    config: RoutingConfig,
+    network: Box<dyn NetworkHandler>,
+}
+
+impl Router {
+    pub fn new(config: RoutingConfig) -> Self {
+        let network: Box<dyn NetworkHandler> = match &config.foreign_cards {
+            ForeignCards::Reject => Box::new(RejectForeignCards),
+            ForeignCards::Forward { socket_path } => Box::new(SocketNetwork {
+                socket_path: socket_path.clone(),
+            }),
+        };
+        Router { config, network }
+    }
+
+    pub fn route(&self, command: &Command) -> Route {
+        let card_number = match command {
+            Command::RegisterTerminal { .. }
+            | Command::ValidateCardKey { .. }
+            | Command::ReportCassettes { .. } => return Route::OnUs,
+            Command::Withdraw { card_number, .. }
+            | Command::CheckBalance { card_number, .. }
+            | Command::ReverseWithdrawal { card_number, .. }
+            | Command::WithdrawalAdvice { card_number, .. } => card_number,
+        };
+
+        let Ok(card_number) = CardNumber::parse(card_number) else {
+            return Route::InvalidCardNumber;
+        };
+
+        match find_issuer(&self.config.bin_ranges, &card_number) {
+            Some(issuer) if issuer == self.config.own_issuer => Route::OnUs,
+            Some(issuer) => Route::Foreign(issuer.to_string()),
+            None => Route::Unsupported,
+        }
+    }
+
+    pub fn is_on_us(&self, card_number: &CardNumber) -> bool {
+        find_issuer(&self.config.bin_ranges, card_number) == Some(self.config.own_issuer.as_str())
+    }
+
+    // First BIN of the bank's own ranges, used when issuing new cards
+    pub fn own_bin(&self) -> Option<u32> {
+        self.config
+            .bin_ranges
+            .iter()
+            .find(|range| range.issuer == self.config.own_issuer)
+            .map(|range| range.low)
+    }
+
+    pub fn forward(&mut self, issuer: &str, command: &Command) -> Response {
+        self.network.forward(issuer, command)
+    }
+}
@@ -0,0 +1,560 @@
+use crate::admin::{ADMIN_TOKEN_FILE, handle_admin_command, load_admin_token, reload_routing};
+use crate::audit::{AUDIT_FILE, AuditLog};
+use crate::config::Config;
+use crate::handler::Bank;
+use crate::logging::{Redacted, mask_card, variant_name};
+use crate::metrics::{self, metrics};
+use crate::routing::{ROUTING_FILE, Router, RoutingConfig};
+use crate::session::Sessions;
+use crate::settlement::{CASSETTE_REPORTS_FILE, CassetteReports};
+use crate::store::open_store;
+use crate::terminal::{TERMINALS_FILE, Terminals};
+use common::{
+    AdminRequest, AdminResponse, Command, Response, Stream, receive_command, receive_message,
+    send_message, send_response,
+};
+use serde_json::{Value, json};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::fs;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, field, info, info_span, warn};
+
+// How long a wrong admin token is held before it is answered
+const ADMIN_FAILURE_DELAY: Duration = Duration::from_secs(1);
+
+// Everything the connection handlers share
+pub struct State {
+    pub config: Config,
+    pub bank: Mutex<Bank>,
+    pub sessions: Mutex<Sessions>,
+    pub terminals: Mutex<Terminals>,
+    audit: Mutex<AuditLog>,
+    admin_token: String,
+    // Held while a wrong admin token is answered
+    admin_failures: Mutex<()>,
+    shutting_down: AtomicBool,
+}
+
+impl State {
+    pub fn new(
+        config: Config,
+        bank: Bank,
+        terminals: Terminals,
+        audit: AuditLog,
+        admin_token: String,
+    ) -> Self {
+        State {
+            config,
+            bank: Mutex::new(bank),
+            sessions: Mutex::new(Sessions::default()),
+            terminals: Mutex::new(terminals),
+            audit: Mutex::new(audit),
+            admin_token,
+            admin_failures: Mutex::new(()),
+            shutting_down: AtomicBool::new(false),
+        }
+    }
+
+    // A failure to audit is logged but does not fail the command
+    pub fn audit(&self, actor: &str, event: &str, details: Value) {
+        if let Err(e) = self.audit.lock().unwrap().record(actor, event, details) {
+            error!(error = %e, event, "Cannot write to the audit log");
+        }
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    // Stops every accept loop and stops reading from ATMs; `drain` then waits
+    // for the commands already received
+    pub fn shut_down(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        self.sessions.lock().unwrap().stop_reading();
+
+        // Wake the listeners blocked in accept so they see the flag
+        let _ = UnixStream::connect(&self.config.socket_path);
+        let _ = UnixStream::connect(&self.config.admin_socket_path);
+        if let Some(address) = &self.config.tcp_address {
+            let _ = TcpStream::connect(address);
+        }
+    }
+
+    // Waits until every ATM connection has closed, then cuts off those still
+    // open at the deadline
+    fn drain(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let open = self.sessions.lock().unwrap().count();
+            if open == 0 {
+                return;
+            }
+            if Instant::now() >= deadline {
+                warn!(
+                    sessions = open,
+                    "Disconnecting sessions still open at the deadline"
+                );
+                self.sessions.lock().unwrap().kick_all();
+                return;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+fn handle_client(mut stream: Stream, state: Arc<State>) -> io::Result<()> {
+    let session_id = {
+        let mut sessions = state.sessions.lock().unwrap();
+        // Accepted just before shutdown, after the others were told to stop
+        if state.is_shutting_down() {
+            return Ok(());
+        }
+        if sessions.count() >= state.config.max_connections {
+            warn!(peer = %stream.peer(), "Refused a connection over max_connections");
+            return Ok(());
+        }
+        sessions.register(stream.try_clone()?)
+    };
+    let span = info_span!("session", session_id, terminal = field::Empty);
+    let _entered = span.enter();
+    debug!(peer = %stream.peer(), "Session started");
+
+    let result = match register_terminal(&mut stream, &state) {
+        Ok(Some(terminal_id)) => {
+            span.record("terminal", terminal_id.as_str());
+            state
+                .sessions
+                .lock()
+                .unwrap()
+                .set_terminal(session_id, &terminal_id);
+            serve_session(&mut stream, &state, session_id, &terminal_id)
+        }
+        Ok(None) => Ok(()),
+        Err(e) => Err(e),
+    };
+    state.sessions.lock().unwrap().remove(session_id);
+    result
+}
+
+// Reads the ATM's RegisterTerminal command and returns its terminal id if the
+// bank knows it; anything else ends the connection
+fn register_terminal(stream: &mut Stream, state: &State) -> io::Result<Option<String>> {
+    let command = match receive_command(stream) {
+        Ok(command) => command,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let Command::RegisterTerminal {
+        terminal_id,
+        location,
+    } = command
+    else {
+        warn!(
+            command = ?Redacted(&command),
+            "Rejected a client that did not register as a terminal"
+        );
+        state.audit(
+            "unknown",
+            &variant_name(&command),
+            json!({ "outcome": variant_name(&Response::ErrorUnknownTerminal) }),
+        );
+        send_response(stream, &Response::ErrorUnknownTerminal)?;
+        return Ok(None);
+    };
+
+    let known = state
+        .terminals
+        .lock()
+        .unwrap()
+        .register(&terminal_id, &location)?;
+    let response = if known {
+        info!(terminal = %terminal_id, location = %location, "Terminal registered");
+        Response::TerminalRegistered
+    } else {
+        warn!(terminal = %terminal_id, "Rejected unknown terminal");
+        Response::ErrorUnknownTerminal
+    };
+    state.audit(
+        &format!("terminal:{}", terminal_id),
+        "RegisterTerminal",
+        json!({ "location": location, "outcome": variant_name(&response) }),
+    );
+    send_response(stream, &response)?;
+    Ok(known.then_some(terminal_id))
+}
+
+fn serve_session(
+    stream: &mut Stream,
+    state: &State,
+    session_id: u64,
+    terminal_id: &str,
+) -> io::Result<()> {
+    loop {
+        match receive_command(stream) {
+            Ok(command) => {
+                debug!(command = ?Redacted(&command), "Received command");
+                state.sessions.lock().unwrap().record(session_id, &command);
+                let name = variant_name(&command);
+                let card = card_number(&command).map(mask_card);
+
+                let started = Instant::now();
+                let response = state.bank.lock().unwrap().handle(terminal_id, command)?;
+
+                let outcome = variant_name(&response);
+                metrics().command(&name, &outcome, started.elapsed());
+                info!(
+                    command = %name,
+                    card = card.as_deref(),
+                    outcome = %outcome,
+                    "Handled command"
+                );
+                let mut details = json!({ "outcome": outcome });
+                if let Some(card) = card {
+                    details["card"] = json!(card);
+                }
+                state.audit(&format!("terminal:{}", terminal_id), &name, details);
+                send_response(stream, &response)?;
+            }
+            Err(e) => {
+                if e.kind() == io::ErrorKind::UnexpectedEof {
+                    info!("Client disconnected");
+                    break;
+                } else {
+                    warn!(error = %e, "Error receiving command");
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn card_number(command: &Command) -> Option<&str> {
+    match command {
+        Command::Withdraw { card_number, .. }
+        | Command::CheckBalance { card_number, .. }
+        | Command::ReverseWithdrawal { card_number, .. }
+        | Command::WithdrawalAdvice { card_number, .. } => Some(card_number),
+        _ => None,
+    }
+}
+
+fn handle_admin_client(mut stream: Stream, state: Arc<State>) -> io::Result<()> {
+    let _entered = info_span!("admin").entered();
+    loop {
+        match receive_message::<AdminRequest>(&mut stream) {
+            Ok(request) => {
+                let name = variant_name(&request.command);
+                if !tokens_match(&request.token, &state.admin_token) {
+                    warn!(command = %name, "Rejected admin command with a wrong token");
+                    // Failures from every connection wait their turn, so
+                    // tokens can only be guessed one at a time and slowly
+                    {
+                        let _throttle = state.admin_failures.lock().unwrap();
+                        thread::sleep(ADMIN_FAILURE_DELAY);
+                    }
+                    state.audit(
+                        "admin",
+                        &name,
+                        json!({ "outcome": variant_name(&AdminResponse::ErrorUnauthorized) }),
+                    );
+                    send_message(&mut stream, &AdminResponse::ErrorUnauthorized)?;
+                    continue;
+                }
+                let command = format!("{:?}", Redacted(&request.command));
+                info!(command = %command, "Received admin command");
+
+                let response = handle_admin_command(&state, request.command)?;
+
+                state.audit(
+                    "admin",
+                    &name,
+                    json!({ "command": command, "outcome": variant_name(&response) }),
+                );
+                send_message(&mut stream, &response)?;
+                if matches!(response, AdminResponse::ShuttingDown) {
+                    state.shut_down();
+                    break;
+                }
+            }
+            Err(e) => {
+                if e.kind() != io::ErrorKind::UnexpectedEof {
+                    warn!(error = %e, "Error receiving admin command");
+                }
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Takes as long whichever byte differs, so the time a rejection takes does not
+// tell how much of a guess was right
+fn tokens_match(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// SIGINT and SIGTERM shut the bank down, a second one without waiting for
+// the ATMs; SIGHUP reloads the configuration like `bank-admin reload`
+fn handle_signals(mut signals: Signals, state: Arc<State>) {
+    for signal in signals.forever() {
+        let name = match signal {
+            SIGHUP => "SIGHUP",
+            SIGINT => "SIGINT",
+            _ => "SIGTERM",
+        };
+        if signal == SIGHUP {
+            let response = reload_routing(&state);
+            if let AdminResponse::ErrorRouting { message } = &response {
+                error!(error = %message, "Routing configuration not reloaded");
+            }
+            state.audit(
+                "bank",
+                "ReloadRouting",
+                json!({ "signal": name, "outcome": variant_name(&response) }),
+            );
+        } else if !state.is_shutting_down() {
+            info!(signal = name, "Shutting down");
+            state.audit("bank", "Shutdown", json!({ "signal": name }));
+            state.shut_down();
+        } else {
+            warn!(signal = name, "Disconnecting every ATM now");
+            state.sessions.lock().unwrap().kick_all();
+        }
+    }
+}
+
+enum Listener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+impl Listener {
+    fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Unix(listener) => listener.accept().map(|(stream, _)| Stream::Unix(stream)),
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nodelay(true)?;
+                Ok(Stream::Tcp(stream))
+            }
+        }
+    }
+}
+
+fn bind(path: &Path) -> io::Result<UnixListener> {
+    // Remove the socket file if it already exists
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    info!(path = %path.display(), "Listening");
+    Ok(listener)
+}
+
+// The socket is bound in a directory only the owner can enter and made
+// owner-only there before it is moved into place, so no other user can connect
+// in between
+fn bind_private(path: &Path) -> io::Result<UnixListener> {
+    let mut dir = path.as_os_str().to_owned();
+    dir.push(format!(".{}.d", std::process::id()));
+    let dir = PathBuf::from(dir);
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let private = dir.join("socket");
+    let listener = UnixListener::bind(&private).and_then(|listener| {
+        fs::set_permissions(&private, fs::Permissions::from_mode(0o600))?;
+        // Replaces a socket left behind by a previous run
+        fs::rename(&private, path)?;
+        Ok(listener)
+    });
+    fs::remove_dir_all(&dir)?;
+    let listener = listener?;
+    info!(path = %path.display(), "Listening");
+    Ok(listener)
+}
+
+fn serve<F>(listener: Listener, state: Arc<State>, kind: &'static str, handler: F)
+where
+    F: Fn(Stream, Arc<State>) -> io::Result<()> + Copy + Send + 'static,
+{
+    loop {
+        let stream = listener.accept();
+        if state.is_shutting_down() {
+            break;
+        }
+        match stream {
+            Ok(stream) => {
+                debug!(kind, "New connection");
+                let state = Arc::clone(&state);
+                thread::spawn(move || {
+                    if let Err(e) = handler(stream, state) {
+                        error!(kind, error = %e, "Connection failed");
+                    }
+                });
+            }
+            Err(e) => {
+                error!(error = %e, "Error accepting connection");
+            }
+        }
+    }
+}
+
+// A bank serving ATMs, on its own sockets and data directory. `run` starts
+// one for the bank binary; tests start them in-process
+pub struct Server {
+    state: Arc<State>,
+    // Accept loops; each returns once the bank is shutting down
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Server {
+    // Loads the bank's files and returns once the sockets accept connections
+    pub fn start(config: Config) -> io::Result<Self> {
+        info!(data_dir = %config.data_dir.display(), "Bank server starting");
+
+        let router = Router::new(RoutingConfig::load(&config.data_file(ROUTING_FILE))?);
+        let bank = Bank::new(
+            open_store(&config)?,
+            router,
+            CassetteReports::load(config.data_file(CASSETTE_REPORTS_FILE))?,
+            config.max_withdrawal,
+        )?;
+        info!(accounts = bank.accounts.len(), "Loaded accounts");
+
+        let (audit, verified) = AuditLog::open(config.data_file(AUDIT_FILE))?;
+        let verified = match verified {
+            Ok(records) => {
+                info!(records, "Audit log verified");
+                json!({ "verified_records": records })
+            }
+            Err(problem) => {
+                error!(problem = %problem, path = %audit.path().display(), "Audit log chain is broken");
+                json!({ "chain_problem": problem })
+            }
+        };
+
+        let terminals = Terminals::load(config.data_file(TERMINALS_FILE))?;
+        let admin_token = load_admin_token(&config.data_file(ADMIN_TOKEN_FILE))?;
+        let state = Arc::new(State::new(config, bank, terminals, audit, admin_token));
+        state.audit("bank", "ServerStarted", verified);
+        let config = &state.config;
+
+        let listener = bind(&config.socket_path)?;
+
+        // Only the user running the bank may send admin commands
+        let admin_listener = bind_private(&config.admin_socket_path)?;
+
+        let tcp_listener = match &config.tcp_address {
+            Some(address) => {
+                let tcp_listener = TcpListener::bind(address)?;
+                info!(address = %tcp_listener.local_addr()?, "Listening");
+                Some(tcp_listener)
+            }
+            None => None,
+        };
+
+        if config.metrics_address != "off" {
+            let metrics_listener = TcpListener::bind(&config.metrics_address)?;
+            let metrics_state = Arc::clone(&state);
+            thread::spawn(move || metrics::serve(metrics_listener, metrics_state));
+        }
+
+        let mut threads = Vec::new();
+        let client_state = Arc::clone(&state);
+        threads.push(thread::spawn(move || {
+            serve(
+                Listener::Unix(listener),
+                client_state,
+                "client",
+                handle_client,
+            )
+        }));
+        let admin_state = Arc::clone(&state);
+        threads.push(thread::spawn(move || {
+            serve(
+                Listener::Unix(admin_listener),
+                admin_state,
+                "admin client",
+                handle_admin_client,
+            )
+        }));
+        if let Some(tcp_listener) = tcp_listener {
+            let tcp_state = Arc::clone(&state);
+            threads.push(thread::spawn(move || {
+                serve(
+                    Listener::Tcp(tcp_listener),
+                    tcp_state,
+                    "client",
+                    handle_client,
+                )
+            }));
+        }
+
+        Ok(Server { state, threads })
+    }
+
+    pub fn state(&self) -> &Arc<State> {
+        &self.state
+    }
+
+    // Stops taking connections and waits for the bank to wind down
+    pub fn stop(self) {
+        self.state.shut_down();
+        self.wait();
+    }
+
+    // Returns once the bank has been shut down and every ATM has finished or
+    // been cut off
+    pub fn wait(self) {
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+        let state = self.state;
+        let config = &state.config;
+
+        info!("Bank server shutting down");
+        state.drain(Duration::from_secs(config.shutdown_timeout));
+        // Every change was committed as it was made; this only tidies the store
+        if let Err(e) = state.bank.lock().unwrap().flush() {
+            error!(error = %e, "Error flushing the store");
+        }
+        state.audit("bank", "ServerStopped", json!({}));
+        let _ = fs::remove_file(&config.socket_path);
+        let _ = fs::remove_file(&config.admin_socket_path);
+    }
+}
+
+pub fn run(config: Config) -> io::Result<()> {
+    crate::logging::init(&config);
+
+    // Registered before the sockets exist so a signal never finds them
+    // without a handler that removes them; signals that come before the
+    // handler thread starts wait for it
+    let signals = Signals::new([SIGINT, SIGTERM, SIGHUP])?;
+    let server = Server::start(config)?;
+    let signal_state = Arc::clone(server.state());
+    thread::spawn(move || handle_signals(signals, signal_state));
+
+    server.wait();
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use chrono::{DateTime, Local};
+use common::{CardNumber, Command, SessionSummary, Stream};
+use std::collections::HashMap;
+use std::net::Shutdown;
+
+struct Session {
+    terminal_id: Option<String>,
+    connected_at: DateTime<Local>,
+    commands: u64,
+    card: Option<String>,
+    // Second handle on the client's socket so the session can be closed from outside
+    stream: Stream,
+}
+
+// ATM connections currently being served
+#[derive(Default)]
+pub struct Sessions {
+    next_id: u64,
+    sessions: HashMap<u64, Session>,
+}
+
+impl Sessions {
+    pub fn register(&mut self, stream: Stream) -> u64 {
+        self.next_id += 1;
+        self.sessions.insert(
+            self.next_id,
+            Session {
+                terminal_id: None,
+                connected_at: Local::now(),
+                commands: 0,
+                card: None,
+                stream,
+            },
+        );
+        self.next_id
+    }
+
+    pub fn count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn remove(&mut self, session_id: u64) {
+        self.sessions.remove(&session_id);
+    }
+
+    pub fn set_terminal(&mut self, session_id: u64, terminal_id: &str) {
+        if let Some(session) = self.sessions.get_mut(&session_id) {
+            session.terminal_id = Some(terminal_id.to_string());
+        }
+    }
+
+    pub fn record(&mut self, session_id: u64, command: &Command) {
+        let Some(session) = self.sessions.get_mut(&session_id) else {
+            return;
+        };
+        session.commands += 1;
+        if let Command::Withdraw { card_number, .. } | Command::CheckBalance { card_number, .. } =
+            command
+            && let Ok(card_number) = CardNumber::parse(card_number)
+        {
+            session.card = Some(card_number.masked());
+        }
+    }
+
+    pub fn list(&self) -> Vec<SessionSummary> {
+        let mut sessions: Vec<SessionSummary> = self
+            .sessions
+            .iter()
+            .map(|(session_id, session)| SessionSummary {
+                session_id: *session_id,
+                terminal_id: session.terminal_id.clone(),
+                connected_at: session.connected_at,
+                commands: session.commands,
+                card: session.card.clone(),
+            })
+            .collect();
+        sessions.sort_by_key(|session| session.session_id);
+        sessions
+    }
+
+    // Closes the connection; the client's handler notices on its next read
+    pub fn kick(&mut self, session_id: u64) -> bool {
+        match self.sessions.remove(&session_id) {
+            Some(session) => {
+                let _ = session.stream.shutdown(Shutdown::Both);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Disconnects every session of the terminal and returns how many there were
+    pub fn kick_terminal(&mut self, terminal_id: &str) -> usize {
+        let session_ids: Vec<u64> = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.terminal_id.as_deref() == Some(terminal_id))
+            .map(|(session_id, _)| *session_id)
+            .collect();
+        for session_id in &session_ids {
+            self.kick(*session_id);
+        }
+        session_ids.len()
+    }
+
+    // Each handler finishes the command it is on, then reads end of input
+    pub fn stop_reading(&self) {
+        for session in self.sessions.values() {
+            let _ = session.stream.shutdown(Shutdown::Read);
+        }
+    }
+
+    pub fn kick_all(&mut self) {
+        for (_, session) in self.sessions.drain() {
+            let _ = session.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use crate::ledger::{Ledger, LedgerAccount, PostingKind};
+use chrono::{DateTime, Local, NaiveDate};
+use common::{
+    CassetteCount, SettlementStatus, TerminalSettlement, from_cents, write_atomic,
+    write_json_atomic,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+pub const CASSETTE_REPORTS_FILE: &str = "cassette_reports.json";
+const SETTLEMENTS_DIR: &str = "settlements";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CassetteReport {
+    terminal_id: String,
+    business_date: NaiveDate,
+    cassettes: Vec<CassetteCount>,
+    received_at: DateTime<Local>,
+}
+
+// The last cassette report of every ATM for every business day
+pub struct CassetteReports {
+    path: PathBuf,
+    reports: HashMap<(String, NaiveDate), CassetteReport>,
+}
+
+impl CassetteReports {
+    pub fn load(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        if !path.exists() {
+            return Ok(CassetteReports {
+                path,
+                reports: HashMap::new(),
+            });
+        }
+
+        let file = File::open(&path)?;
+        let reader = BufReader::new(file);
+        let reports: Vec<CassetteReport> = serde_json::from_reader(reader).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid {}: {}", path.display(), e),
+            )
+        })?;
+        Ok(CassetteReports {
+            path,
+            reports: reports
+                .into_iter()
+                .map(|report| ((report.terminal_id.clone(), report.business_date), report))
+                .collect(),
+        })
+    }
+
+    // Replaces the terminal's earlier report for the same day
+    pub fn record(
+        &mut self,
+        terminal_id: String,
+        business_date: NaiveDate,
+        cassettes: Vec<CassetteCount>,
+    ) -> io::Result<()> {
+        let report = CassetteReport {
+            terminal_id: terminal_id.clone(),
+            business_date,
+            cassettes,
+            received_at: Local::now(),
+        };
+        self.reports.insert((terminal_id, business_date), report);
+        self.save()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let mut reports: Vec<&CassetteReport> = self.reports.values().collect();
+        reports.sort_by(|a, b| {
+            (a.business_date, &a.terminal_id).cmp(&(b.business_date, &b.terminal_id))
+        });
+        write_json_atomic(&self.path, &reports)
+    }
+
+    // Value of the notes the terminal says it paid out on the day
+    fn dispensed(&self, terminal_id: &str, date: NaiveDate) -> Option<i64> {
+        self.reports
+            .get(&(terminal_id.to_string(), date))
+            .map(|report| {
+                report
+                    .cassettes
+                    .iter()
+                    .map(|cassette| cassette.denomination as i64 * cassette.dispensed as i64 * 100)
+                    .sum()
+            })
+    }
+}
+
+// Compares the withdrawals booked against each ATM on `date` with the
+// notes the ATM reported dispensing that day
+pub fn settle(
+    ledger: &Ledger,
+    reports: &CassetteReports,
+    date: NaiveDate,
+) -> Vec<TerminalSettlement> {
+    // terminal id -> (number of withdrawals, total in cents)
+    let mut booked: BTreeMap<String, (usize, i64)> = BTreeMap::new();
+    for posting in ledger.postings() {
+        if posting.created_at.date_naive() != date {
+            continue;
+        }
+        match (posting.kind, &posting.credit, &posting.debit) {
+            (PostingKind::Withdrawal, LedgerAccount::AtmCash(terminal_id), _) => {
+                let entry = booked.entry(terminal_id.clone()).or_default();
+                entry.0 += 1;
+                entry.1 += posting.amount;
+            }
+            // Dated like the withdrawal it cancels
+            (PostingKind::Reversal, _, LedgerAccount::AtmCash(terminal_id)) => {
+                let entry = booked.entry(terminal_id.clone()).or_default();
+                entry.0 = entry.0.saturating_sub(1);
+                entry.1 -= posting.amount;
+            }
+            _ => {}
+        }
+    }
+    for (terminal_id, business_date) in reports.reports.keys() {
+        if *business_date == date {
+            booked.entry(terminal_id.clone()).or_default();
+        }
+    }
+
+    booked
+        .into_iter()
+        .map(|(terminal_id, (withdrawals, bank_total))| {
+            let atm_total = reports.dispensed(&terminal_id, date);
+            let status = match atm_total {
+                None => SettlementStatus::MissingReport,
+                Some(atm_total) if atm_total == bank_total => SettlementStatus::Balanced,
+                Some(_) => SettlementStatus::Discrepancy,
+            };
+            TerminalSettlement {
+                terminal_id,
+                withdrawals,
+                bank_total: from_cents(bank_total),
+                atm_total: atm_total.map(from_cents),
+                difference: atm_total.map(|atm_total| from_cents(atm_total - bank_total)),
+                status,
+            }
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct SettlementReport<'a> {
+    date: NaiveDate,
+    generated_at: DateTime<Local>,
+    terminals: &'a [TerminalSettlement],
+}
+
+// Writes settlements/settlement-<date>.csv and .json under `data_dir`,
+// replacing earlier runs for the same day
+pub fn write_report(
+    data_dir: &Path,
+    date: NaiveDate,
+    terminals: &[TerminalSettlement],
+) -> io::Result<(PathBuf, PathBuf)> {
+    let dir = data_dir.join(SETTLEMENTS_DIR);
+    fs::create_dir_all(&dir)?;
+    let base = dir.join(format!("settlement-{}", date));
+
+    let csv_path = base.with_extension("csv");
+    write_atomic(&csv_path, |csv| {
+        writeln!(
+            csv,
+            "date,terminal_id,withdrawals,bank_total,atm_total,difference,status"
+        )?;
+        let optional =
+            |amount: Option<f64>| amount.map(|a| format!("{:.2}", a)).unwrap_or_default();
+        for terminal in terminals {
+            writeln!(
+                csv,
+                "{},{},{},{:.2},{},{},{}",
+                date,
+                terminal.terminal_id,
+                terminal.withdrawals,
+                terminal.bank_total,
+                optional(terminal.atm_total),
+                optional(terminal.difference),
+                status_name(terminal.status)
+            )?;
+        }
+        Ok(())
+    })?;
+
+    let json_path = base.with_extension("json");
+    write_json_atomic(
+        &json_path,
+        &SettlementReport {
+            date,
+            generated_at: Local::now(),
+            terminals,
+        },
+    )?;
+
+    Ok((csv_path, json_path))
+}
+
+fn status_name(status: SettlementStatus) -> &'static str {
+    match status {
+        SettlementStatus::Balanced => "balanced",
+        SettlementStatus::Discrepancy => "discrepancy",
+        SettlementStatus::MissingReport => "missing_report",
+    }
+}
@@ -0,0 +1,174 @@
+use super::{Change, History};
+use crate::ledger::{Posting, Voided};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+pub const LEDGER_FILE: &str = "ledger.log";
+
+// Records read back from a log, and how much of the file they take up
+pub(super) struct Records<T> {
+    pub records: Vec<T>,
+    pub valid_len: u64,
+    pub file_len: u64,
+}
+
+// Every intact record in a log written with `record_line`; none if the file
+// does not exist. A record cut short by a crash at the end is dropped, while a
+// damaged record followed by others is an error
+pub(super) fn read_records<T: DeserializeOwned>(path: &Path) -> io::Result<Records<T>> {
+    let mut contents = Vec::new();
+    match File::open(path) {
+        Ok(mut file) => file.read_to_end(&mut contents)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(e),
+    };
+
+    let mut records = Vec::new();
+    let mut valid_len = 0;
+    let mut rest = &contents[..];
+    while !rest.is_empty() {
+        let Some(end) = rest.iter().position(|&byte| byte == b'\n') else {
+            break;
+        };
+        let Some(record) = parse_record(&rest[..end]) else {
+            if rest[end + 1..]
+                .iter()
+                .any(|byte| !byte.is_ascii_whitespace())
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} is corrupt at record {}",
+                        path.display(),
+                        records.len() + 1
+                    ),
+                ));
+            }
+            break;
+        };
+        records.push(record);
+        valid_len += end + 1;
+        rest = &rest[end + 1..];
+    }
+
+    if valid_len < contents.len() {
+        warn!(
+            path = %path.display(),
+            "Dropped an incomplete record at the end of the log"
+        );
+    }
+    Ok(Records {
+        records,
+        valid_len: valid_len as u64,
+        file_len: contents.len() as u64,
+    })
+}
+
+// "<crc32 of the record> <record as JSON>\n", so a torn record is recognised
+pub(super) fn record_line<T: Serialize>(record: &T) -> io::Result<String> {
+    let record = serde_json::to_string(record)?;
+    Ok(format!(
+        "{:08x} {}\n",
+        crc32fast::hash(record.as_bytes()),
+        record
+    ))
+}
+
+fn parse_record<T: DeserializeOwned>(line: &[u8]) -> Option<T> {
+    let line = std::str::from_utf8(line).ok()?;
+    let (checksum, record) = line.split_once(' ')?;
+    if u32::from_str_radix(checksum, 16).ok()? != crc32fast::hash(record.as_bytes()) {
+        return None;
+    }
+    serde_json::from_str(record).ok()
+}
+
+pub(super) fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[derive(Serialize, Deserialize)]
+enum Entry {
+    Posted(Posting),
+    Voided(Voided),
+}
+
+// The postings and reversal markers of the stores that keep accounts in JSON
+// files, appended as they are made and never rewritten. Each record holds the
+// entries of one command, so a command is either booked in full or not at all
+pub struct Journal {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl Journal {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Journal {
+            path: path.into(),
+            file: None,
+        }
+    }
+
+    // Also cuts off a torn record at the end, so the next one is appended
+    // after the last intact record
+    pub fn load(&mut self) -> io::Result<History> {
+        let read = read_records::<Vec<Entry>>(&self.path)?;
+        if read.valid_len < read.file_len {
+            let file = OpenOptions::new().write(true).open(&self.path)?;
+            file.set_len(read.valid_len)?;
+            file.sync_all()?;
+        }
+        let mut history = History::default();
+        for entry in read.records.into_iter().flatten() {
+            match entry {
+                Entry::Posted(posting) => history.postings.push(posting),
+                Entry::Voided(voided) => history.voided.push(voided),
+            }
+        }
+        Ok(history)
+    }
+
+    // Appends and syncs the postings and markers among `changes`, if any. Returns the
+    // length the journal had before, to `truncate` it back to when the rest of
+    // the command cannot be stored
+    pub fn append(&mut self, changes: &[Change]) -> io::Result<Option<u64>> {
+        let entries: Vec<Entry> = changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::Posted(posting) => Some(Entry::Posted(posting.clone())),
+                Change::Voided(voided) => Some(Entry::Voided(voided.clone())),
+                _ => None,
+            })
+            .collect();
+        if entries.is_empty() {
+            return Ok(None);
+        }
+
+        let line = record_line(&entries)?;
+        if self.file.is_none() {
+            self.file = Some(open_append(&self.path)?);
+        }
+        let file = self.file.as_mut().unwrap();
+        let len = file.metadata()?.len();
+        if let Err(e) = file
+            .write_all(line.as_bytes())
+            .and_then(|_| file.sync_data())
+        {
+            self.truncate(Some(len))?;
+            return Err(e);
+        }
+        Ok(Some(len))
+    }
+
+    pub fn truncate(&mut self, len: Option<u64>) -> io::Result<()> {
+        let (Some(len), Some(file)) = (len, self.file.as_mut()) else {
+            return Ok(());
+        };
+        file.set_len(len)?;
+        file.sync_all()
+    }
+}
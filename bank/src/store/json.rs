@@ -0,0 +1,206 @@
+use super::journal::{Journal, LEDGER_FILE};
+use super::{AccountStore, Change, History, sample_accounts};
+use crate::ledger::LedgerAccount;
+use crate::logging::mask_card;
+use common::{Account, CardNumber, CardNumberError, sync_parent_dir, temp_path, write_synced};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use tracing::{error, warn};
+
+pub const ACCOUNTS_FILE: &str = "accounts.json";
+
+// Every account in one JSON file, rewritten on each change, and the postings
+// in a journal next to it that is only ever appended to
+pub struct JsonStore {
+    path: PathBuf,
+    // The previous generation of the accounts file, kept for recovery
+    backup_path: PathBuf,
+    pub(super) ledger: Journal,
+}
+
+impl JsonStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let backup_path = path.with_extension("json.bak");
+        let ledger = Journal::new(path.with_file_name(LEDGER_FILE));
+        JsonStore {
+            path,
+            backup_path,
+            ledger,
+        }
+    }
+
+    // The new contents are written and synced to a temporary file before being
+    // renamed over the accounts file, so a crash leaves either the old or the
+    // new generation on disk, never a partial one
+    pub(super) fn save(&self, accounts: &HashMap<String, Account>) -> io::Result<()> {
+        let temp_path = temp_path(&self.path);
+        write_synced(&temp_path, |writer| {
+            serde_json::to_writer_pretty(writer, accounts)?;
+            Ok(())
+        })?;
+
+        // Keep the current generation as the backup without ever removing the
+        // accounts file itself
+        if self.path.exists() {
+            if self.backup_path.exists() {
+                fs::remove_file(&self.backup_path)?;
+            }
+            fs::hard_link(&self.path, &self.backup_path)?;
+        }
+        fs::rename(&temp_path, &self.path)?;
+        sync_parent_dir(&self.path)
+    }
+}
+
+impl AccountStore for JsonStore {
+    // Fails instead of starting with no accounts when neither the accounts
+    // file nor its backup can be read
+    fn load(&mut self) -> io::Result<HashMap<String, Account>> {
+        if !self.path.exists() && !self.backup_path.exists() {
+            // Create some sample accounts if the file doesn't exist
+            let accounts = sample_accounts();
+            self.save(&accounts)?;
+            return Ok(accounts);
+        }
+
+        let error = match read_accounts(&self.path) {
+            Ok(accounts) => return Ok(accounts),
+            Err(e) => e,
+        };
+        error!(path = %self.path.display(), error = %error, "Cannot read accounts");
+
+        match read_accounts(&self.backup_path) {
+            Ok(accounts) => {
+                warn!(
+                    accounts = accounts.len(),
+                    path = %self.backup_path.display(),
+                    "Recovered accounts from the backup; changes after it was written are lost"
+                );
+                Ok(accounts)
+            }
+            Err(backup_error) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: {}; backup {}: {}",
+                    self.path.display(),
+                    error,
+                    self.backup_path.display(),
+                    backup_error
+                ),
+            )),
+        }
+    }
+
+    fn load_history(&mut self) -> io::Result<History> {
+        self.ledger.load()
+    }
+
+    // The postings go first. Balances are rebuilt from them at startup, so a
+    // crash before the accounts file is saved loses nothing
+    fn commit(
+        &mut self,
+        accounts: &HashMap<String, Account>,
+        changes: &[Change],
+    ) -> io::Result<()> {
+        let len = self.ledger.append(changes)?;
+        if let Err(e) = self.save(accounts) {
+            self.ledger.truncate(len)?;
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+// Refuses card numbers that fail the Luhn check, as numbers saved before they
+// were validated may; `repair_card_numbers` gives them their check digit
+fn read_accounts(path: &Path) -> io::Result<HashMap<String, Account>> {
+    let saved = read_saved(path)?;
+    let unrepaired: Vec<String> = saved
+        .values()
+        .filter_map(|value| value.get("card_number")?.as_str())
+        .filter(|number| CardNumber::parse(number) == Err(CardNumberError::ChecksumMismatch))
+        .map(mask_card)
+        .collect();
+    if !unrepaired.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "card numbers failing the Luhn check: {}; run bank-admin repair-card-numbers",
+                unrepaired.join(", ")
+            ),
+        ));
+    }
+
+    let mut accounts = HashMap::new();
+    for value in saved.into_values() {
+        let account: Account = serde_json::from_value(value)?;
+        let card_number = account.card_number.to_string();
+        if accounts.insert(card_number.clone(), account).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("card {} appears twice", mask_card(&card_number)),
+            ));
+        }
+    }
+    Ok(accounts)
+}
+
+fn read_saved(path: &Path) -> io::Result<HashMap<String, serde_json::Value>> {
+    let file = File::open(path)?;
+    Ok(serde_json::from_reader(BufReader::new(file))?)
+}
+
+// Gives each card number in the accounts file that fails the Luhn check and
+// nothing else the check digit its other digits call for, and returns the
+// old and new numbers. Run while the bank is stopped. Cards that already have
+// postings under their old number are left for the operator, since the ledger
+// is never rewritten
+pub fn repair_card_numbers(path: &Path) -> io::Result<Vec<(String, CardNumber)>> {
+    let mut saved = read_saved(path)?;
+    let history = Journal::new(path.with_file_name(LEDGER_FILE)).load()?;
+    let mut repaired = Vec::new();
+    for value in saved.values_mut() {
+        let Some(serde_json::Value::String(number)) = value.get_mut("card_number") else {
+            continue;
+        };
+        if CardNumber::parse(number) != Err(CardNumberError::ChecksumMismatch) {
+            continue;
+        }
+        let customer = LedgerAccount::Customer(number.clone());
+        if history
+            .postings
+            .iter()
+            .any(|posting| posting.debit == customer || posting.credit == customer)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("card {} has postings in the ledger", number),
+            ));
+        }
+        let card_number = CardNumber::with_check_digit(&number[..number.len() - 1])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        repaired.push((number.clone(), card_number.clone()));
+        *number = card_number.to_string();
+    }
+    if repaired.is_empty() {
+        return Ok(repaired);
+    }
+
+    // Keyed by the new numbers, which must not clash with another account
+    let mut accounts = HashMap::new();
+    for value in saved.into_values() {
+        let account: Account = serde_json::from_value(value)?;
+        let card_number = account.card_number.to_string();
+        if accounts.insert(card_number.clone(), account).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("card {} appears twice", card_number),
+            ));
+        }
+    }
+    JsonStore::new(path).save(&accounts)?;
+    Ok(repaired)
+}
@@ -0,0 +1,55 @@
+use super::{AccountStore, Change, History};
+use common::Account;
+use std::collections::HashMap;
+use std::io;
+
+// Keeps accounts only for the life of the process, for tests and demos
+#[derive(Default)]
+pub struct MemoryStore {
+    accounts: HashMap<String, Account>,
+    history: History,
+}
+
+impl MemoryStore {
+    pub fn new(accounts: HashMap<String, Account>) -> Self {
+        MemoryStore {
+            accounts,
+            history: History::default(),
+        }
+    }
+}
+
+impl AccountStore for MemoryStore {
+    fn load(&mut self) -> io::Result<HashMap<String, Account>> {
+        Ok(self.accounts.clone())
+    }
+
+    fn load_history(&mut self) -> io::Result<History> {
+        Ok(History {
+            postings: self.history.postings.clone(),
+            voided: self.history.voided.clone(),
+        })
+    }
+
+    fn commit(
+        &mut self,
+        accounts: &HashMap<String, Account>,
+        changes: &[Change],
+    ) -> io::Result<()> {
+        for change in changes {
+            match change {
+                Change::Updated(card_number) => {
+                    if let Some(account) = accounts.get(card_number) {
+                        self.accounts.insert(card_number.clone(), account.clone());
+                    }
+                }
+                Change::Removed(card_number) => {
+                    self.accounts.remove(card_number);
+                }
+                Change::Posted(posting) => self.history.postings.push(posting.clone()),
+                Change::Voided(voided) => self.history.voided.push(voided.clone()),
+            }
+        }
+        Ok(())
+    }
+}
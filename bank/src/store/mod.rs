@@ -0,0 +1,129 @@
+mod journal;
+mod json;
+mod memory;
+mod sqlite;
+mod wal;
+
+pub use journal::LEDGER_FILE;
+pub use json::{ACCOUNTS_FILE, JsonStore, repair_card_numbers};
+pub use memory::MemoryStore;
+pub use sqlite::{DATABASE_FILE, SqliteStore};
+pub use wal::{WAL_FILE, WAL_SNAPSHOT_FILE, WalStore};
+
+use crate::config::Config;
+use crate::ledger::{Posting, Voided};
+use chrono::Local;
+use common::{Account, CardNumber, CardStatus, default_expiry, write_json_atomic};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const SNAPSHOTS_DIR: &str = "snapshots";
+
+// Something a command did, to be persisted together with the rest of it
+#[derive(Debug, Clone)]
+pub enum Change {
+    // The account with this card number was created or modified
+    Updated(String),
+    Removed(String),
+    Posted(Posting),
+    Voided(Voided),
+}
+
+// What the books hold besides the accounts, in the order it was committed
+#[derive(Default)]
+pub struct History {
+    pub postings: Vec<Posting>,
+    pub voided: Vec<Voided>,
+}
+
+// Where the bank keeps its accounts. The bank works on the map returned by
+// `load` and hands each command's changes to `commit` before replying
+pub trait AccountStore: Send {
+    fn load(&mut self) -> io::Result<HashMap<String, Account>>;
+
+    // Every posting and reversal marker committed so far. The bank rebuilds
+    // the balances of the accounts from the postings
+    fn load_history(&mut self) -> io::Result<History>;
+
+    // `accounts` is the state after the command
+    fn commit(&mut self, accounts: &HashMap<String, Account>, changes: &[Change])
+    -> io::Result<()>;
+
+    // Called once the server has stopped taking commands. Every commit is
+    // already durable; stores may tidy up for a faster next start
+    fn flush(&mut self, _accounts: &HashMap<String, Account>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Opens the configured store: json (the default), wal, sqlite or memory
+pub fn open_store(config: &Config) -> io::Result<Box<dyn AccountStore>> {
+    match config.store.as_str() {
+        "json" => Ok(Box::new(JsonStore::new(config.data_file(ACCOUNTS_FILE)))),
+        "wal" => Ok(Box::new(WalStore::new(
+            config.data_file(WAL_SNAPSHOT_FILE),
+            config.data_file(WAL_FILE),
+        ))),
+        "sqlite" => Ok(Box::new(SqliteStore::open(
+            config.data_file(DATABASE_FILE),
+        )?)),
+        "memory" => Ok(Box::new(MemoryStore::new(sample_accounts()))),
+        kind => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Unknown store \"{}\", expected json, wal, sqlite or memory",
+                kind
+            ),
+        )),
+    }
+}
+
+// The accounts a new store starts with
+pub fn sample_accounts() -> HashMap<String, Account> {
+    let mut accounts = HashMap::new();
+    let today = Local::now().date_naive();
+
+    accounts.insert(
+        "1234567890123452".to_string(),
+        Account {
+            card_key: "key123".to_string(),
+            card_number: CardNumber::parse("1234567890123452").unwrap(),
+            pin: "1234".to_string(),
+            balance: 1000.0,
+            name: "John Doe".to_string(),
+            status: CardStatus::Active,
+            issued_on: today,
+            expires_on: default_expiry(),
+        },
+    );
+
+    accounts.insert(
+        "9876543210987658".to_string(),
+        Account {
+            card_key: "key456".to_string(),
+            card_number: CardNumber::parse("9876543210987658").unwrap(),
+            pin: "4321".to_string(),
+            balance: 500.0,
+            name: "Jane Smith".to_string(),
+            status: CardStatus::Active,
+            issued_on: today,
+            expires_on: default_expiry(),
+        },
+    );
+
+    accounts
+}
+
+// Writes a timestamped JSON copy of the accounts, whatever the store
+pub fn write_snapshot(config: &Config, accounts: &HashMap<String, Account>) -> io::Result<PathBuf> {
+    let dir = config.data_file(SNAPSHOTS_DIR);
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!(
+        "accounts-{}.json",
+        Local::now().format("%Y%m%d-%H%M%S%.3f")
+    ));
+    write_json_atomic(&path, accounts)?;
+    Ok(path)
+}
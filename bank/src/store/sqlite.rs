@@ -0,0 +1,410 @@
+use super::{ACCOUNTS_FILE, AccountStore, Change, History, JsonStore, sample_accounts};
+use crate::ledger::{Posting, Voided};
+use chrono::{DateTime, Local};
+use common::{Account, CardNumber, CardStatus};
+use rusqlite::{Connection, OptionalExtension, Row, Transaction as DbTransaction, params};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use tracing::info;
+
+pub const DATABASE_FILE: &str = "bank.db";
+
+// Bumped with every change to the tables below; stored in PRAGMA user_version
+const SCHEMA_VERSION: i64 = 1;
+
+const ACCOUNTS_SCHEMA: &str = "
+    CREATE TABLE accounts (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        balance REAL NOT NULL
+    );
+    CREATE TABLE cards (
+        card_number TEXT PRIMARY KEY,
+        account_id INTEGER NOT NULL REFERENCES accounts(id),
+        card_key TEXT NOT NULL UNIQUE,
+        pin TEXT NOT NULL,
+        status TEXT NOT NULL,
+        issued_on TEXT NOT NULL,
+        expires_on TEXT NOT NULL
+    );
+";
+
+// Each row is one posting between two ledger accounts. Customer accounts are
+// named after their card and kept after an account is closed, so there is no
+// foreign key. The description of a posting made from an ATM names the id the
+// ATM gave it, so the same withdrawal, reversal or advice is never booked twice
+const TRANSACTIONS_SCHEMA: &str = "
+    CREATE TABLE transactions (
+        id INTEGER PRIMARY KEY,
+        kind TEXT NOT NULL,
+        debit TEXT NOT NULL,
+        credit TEXT NOT NULL,
+        amount INTEGER NOT NULL CHECK (amount > 0),
+        description TEXT,
+        terminal_id TEXT,
+        created_at TEXT NOT NULL
+    );
+    CREATE UNIQUE INDEX transactions_by_reference ON transactions (terminal_id, description)
+        WHERE terminal_id IS NOT NULL;
+";
+
+// Withdrawals reversed before they were booked, refused if they arrive after all
+const VOIDED_SCHEMA: &str = "
+    CREATE TABLE voided_withdrawals (
+        terminal_id TEXT NOT NULL,
+        withdrawal_id TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        PRIMARY KEY (terminal_id, withdrawal_id)
+    );
+";
+
+// The ledger in a SQLite database: account balances, the cards giving
+// access to them and every posting made
+pub struct SqliteStore {
+    connection: Connection,
+    path: PathBuf,
+    created: bool,
+}
+
+impl SqliteStore {
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let mut connection = Connection::open(&path).map_err(to_io_error)?;
+        connection
+            .execute_batch("PRAGMA foreign_keys = ON;")
+            .map_err(to_io_error)?;
+        let created = migrate(&mut connection)
+            .and_then(|created| Ok(created || is_empty(&connection)?))
+            .map_err(to_io_error)?;
+        Ok(SqliteStore {
+            connection,
+            path,
+            created,
+        })
+    }
+}
+
+// Creates the tables in a new database and reports whether it was new
+fn migrate(connection: &mut Connection) -> rusqlite::Result<bool> {
+    let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version == SCHEMA_VERSION {
+        return Ok(false);
+    }
+    if version > SCHEMA_VERSION {
+        return Err(rusqlite::Error::InvalidParameterName(format!(
+            "database schema version {} is newer than this bank ({})",
+            version, SCHEMA_VERSION
+        )));
+    }
+
+    let transaction = connection.transaction()?;
+    transaction.execute_batch(ACCOUNTS_SCHEMA)?;
+    transaction.execute_batch(TRANSACTIONS_SCHEMA)?;
+    transaction.execute_batch(VOIDED_SCHEMA)?;
+    transaction.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    transaction.commit()?;
+    Ok(true)
+}
+
+// The tables are created before the accounts are imported into them, so a
+// crash in between leaves an empty database, which is filled like a new one
+fn is_empty(connection: &Connection) -> rusqlite::Result<bool> {
+    connection.query_row(
+        "SELECT NOT EXISTS (SELECT 1 FROM accounts)
+            AND NOT EXISTS (SELECT 1 FROM transactions)
+            AND NOT EXISTS (SELECT 1 FROM voided_withdrawals)",
+        [],
+        |row| row.get(0),
+    )
+}
+
+impl AccountStore for SqliteStore {
+    fn load(&mut self) -> io::Result<HashMap<String, Account>> {
+        if self.created {
+            self.created = false;
+            // A new database takes over the accounts and history of the JSON
+            // store next to it, if any
+            let import_path = self.path.with_file_name(ACCOUNTS_FILE);
+            let (accounts, history) = if import_path.exists() {
+                let mut store = JsonStore::new(&import_path);
+                let accounts = store.load()?;
+                let history = store.load_history()?;
+                info!(
+                    accounts = accounts.len(),
+                    postings = history.postings.len(),
+                    from = %import_path.display(),
+                    to = %self.path.display(),
+                    "Migrated accounts"
+                );
+                (accounts, history)
+            } else {
+                (sample_accounts(), History::default())
+            };
+            let changes: Vec<Change> = accounts
+                .keys()
+                .cloned()
+                .map(Change::Updated)
+                .chain(history.postings.into_iter().map(Change::Posted))
+                .chain(history.voided.into_iter().map(Change::Voided))
+                .collect();
+            self.commit(&accounts, &changes)?;
+            return Ok(accounts);
+        }
+
+        let mut statement = self
+            .connection
+            .prepare(
+                "SELECT cards.card_number, cards.card_key, cards.pin, accounts.balance,
+                        accounts.name, cards.status, cards.issued_on, cards.expires_on
+                 FROM cards JOIN accounts ON accounts.id = cards.account_id",
+            )
+            .map_err(to_io_error)?;
+        let accounts = statement
+            .query_map([], read_account)
+            .map_err(to_io_error)?
+            .map(|account| account.map(|account| (account.card_number.to_string(), account)))
+            .collect::<Result<_, _>>()
+            .map_err(to_io_error)?;
+        Ok(accounts)
+    }
+
+    fn load_history(&mut self) -> io::Result<History> {
+        let postings = self
+            .connection
+            .prepare(
+                "SELECT kind, debit, credit, amount, description, created_at, terminal_id
+                 FROM transactions ORDER BY id",
+            )
+            .and_then(|mut statement| {
+                statement
+                    .query_map([], read_posting)?
+                    .collect::<Result<_, _>>()
+            })
+            .map_err(to_io_error)?;
+        let voided = self
+            .connection
+            .prepare(
+                "SELECT terminal_id, withdrawal_id, created_at
+                 FROM voided_withdrawals ORDER BY rowid",
+            )
+            .and_then(|mut statement| {
+                statement
+                    .query_map([], read_voided)?
+                    .collect::<Result<_, _>>()
+            })
+            .map_err(to_io_error)?;
+        Ok(History { postings, voided })
+    }
+
+    // All changes of one command are written in a single database transaction,
+    // so a balance never changes without its transaction row or the other way round
+    fn commit(
+        &mut self,
+        accounts: &HashMap<String, Account>,
+        changes: &[Change],
+    ) -> io::Result<()> {
+        let transaction = self.connection.transaction().map_err(to_io_error)?;
+        for change in changes {
+            apply(&transaction, accounts, change).map_err(to_io_error)?;
+        }
+        transaction.commit().map_err(to_io_error)
+    }
+}
+
+fn apply(
+    transaction: &DbTransaction,
+    accounts: &HashMap<String, Account>,
+    change: &Change,
+) -> rusqlite::Result<()> {
+    match change {
+        Change::Updated(card_number) => {
+            let Some(account) = accounts.get(card_number) else {
+                return Ok(());
+            };
+            match account_id(transaction, card_number)? {
+                Some(account_id) => update_account(transaction, account_id, account),
+                None => insert_account(transaction, account),
+            }
+        }
+        Change::Removed(card_number) => {
+            let Some(account_id) = account_id(transaction, card_number)? else {
+                return Ok(());
+            };
+            transaction.execute(
+                "DELETE FROM cards WHERE card_number = ?1",
+                params![card_number],
+            )?;
+            transaction.execute("DELETE FROM accounts WHERE id = ?1", params![account_id])?;
+            Ok(())
+        }
+        Change::Posted(posting) => insert_posting(transaction, posting),
+        Change::Voided(voided) => {
+            transaction.execute(
+                "INSERT OR IGNORE INTO voided_withdrawals (terminal_id, withdrawal_id, created_at)
+                 VALUES (?1, ?2, ?3)",
+                params![
+                    voided.terminal_id,
+                    voided.withdrawal_id,
+                    voided.created_at.to_rfc3339(),
+                ],
+            )?;
+            Ok(())
+        }
+    }
+}
+
+fn account_id(transaction: &DbTransaction, card_number: &str) -> rusqlite::Result<Option<i64>> {
+    transaction
+        .query_row(
+            "SELECT account_id FROM cards WHERE card_number = ?1",
+            params![card_number],
+            |row| row.get(0),
+        )
+        .optional()
+}
+
+fn insert_account(transaction: &DbTransaction, account: &Account) -> rusqlite::Result<()> {
+    transaction.execute(
+        "INSERT INTO accounts (name, balance) VALUES (?1, ?2)",
+        params![account.name, account.balance],
+    )?;
+    let account_id = transaction.last_insert_rowid();
+    transaction.execute(
+        "INSERT INTO cards (card_number, account_id, card_key, pin, status, issued_on, expires_on)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            account.card_number.as_str(),
+            account_id,
+            account.card_key,
+            account.pin,
+            status_name(account.status),
+            account.issued_on,
+            account.expires_on,
+        ],
+    )?;
+    Ok(())
+}
+
+fn update_account(
+    transaction: &DbTransaction,
+    account_id: i64,
+    account: &Account,
+) -> rusqlite::Result<()> {
+    transaction.execute(
+        "UPDATE accounts SET name = ?1, balance = ?2 WHERE id = ?3",
+        params![account.name, account.balance, account_id],
+    )?;
+    transaction.execute(
+        "UPDATE cards SET card_key = ?1, pin = ?2, status = ?3, issued_on = ?4, expires_on = ?5
+         WHERE card_number = ?6",
+        params![
+            account.card_key,
+            account.pin,
+            status_name(account.status),
+            account.issued_on,
+            account.expires_on,
+            account.card_number.as_str(),
+        ],
+    )?;
+    Ok(())
+}
+
+fn insert_posting(transaction: &DbTransaction, posting: &Posting) -> rusqlite::Result<()> {
+    transaction.execute(
+        "INSERT INTO transactions
+             (kind, debit, credit, amount, description, created_at, terminal_id)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            posting.kind.as_str(),
+            posting.debit.to_string(),
+            posting.credit.to_string(),
+            posting.amount,
+            posting.description,
+            posting.created_at.to_rfc3339(),
+            posting.terminal_id,
+        ],
+    )?;
+    Ok(())
+}
+
+fn read_posting(row: &Row) -> rusqlite::Result<Posting> {
+    let text_error = |column: usize, message: String| {
+        rusqlite::Error::FromSqlConversionFailure(
+            column,
+            rusqlite::types::Type::Text,
+            message.into(),
+        )
+    };
+    let kind: String = row.get(0)?;
+    let debit: String = row.get(1)?;
+    let credit: String = row.get(2)?;
+    let created_at: String = row.get(5)?;
+    Ok(Posting {
+        kind: kind.parse().map_err(|e| text_error(0, e))?,
+        debit: debit.parse().map_err(|e| text_error(1, e))?,
+        credit: credit.parse().map_err(|e| text_error(2, e))?,
+        amount: row.get(3)?,
+        description: row.get(4)?,
+        terminal_id: row.get(6)?,
+        created_at: DateTime::parse_from_rfc3339(&created_at)
+            .map_err(|e| text_error(5, e.to_string()))?
+            .with_timezone(&Local),
+    })
+}
+
+fn read_voided(row: &Row) -> rusqlite::Result<Voided> {
+    let created_at: String = row.get(2)?;
+    Ok(Voided {
+        terminal_id: row.get(0)?,
+        withdrawal_id: row.get(1)?,
+        created_at: DateTime::parse_from_rfc3339(&created_at)
+            .map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    2,
+                    rusqlite::types::Type::Text,
+                    e.to_string().into(),
+                )
+            })?
+            .with_timezone(&Local),
+    })
+}
+
+fn read_account(row: &Row) -> rusqlite::Result<Account> {
+    let card_number: String = row.get(0)?;
+    let status: String = row.get(5)?;
+    Ok(Account {
+        card_number: CardNumber::parse(&card_number).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+        })?,
+        card_key: row.get(1)?,
+        pin: row.get(2)?,
+        balance: row.get(3)?,
+        name: row.get(4)?,
+        status: parse_status(&status).ok_or_else(|| {
+            rusqlite::Error::FromSqlConversionFailure(
+                5,
+                rusqlite::types::Type::Text,
+                format!("unknown card status {}", status).into(),
+            )
+        })?,
+        issued_on: row.get(6)?,
+        expires_on: row.get(7)?,
+    })
+}
+
+// Statuses are stored under the same names used in accounts.json
+fn status_name(status: CardStatus) -> String {
+    match serde_json::to_value(status) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => format!("{:?}", status),
+    }
+}
+
+fn parse_status(name: &str) -> Option<CardStatus> {
+    serde_json::from_value(serde_json::Value::String(name.to_string())).ok()
+}
+
+fn to_io_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
@@ -0,0 +1,170 @@
+use super::journal::{open_append, read_records, record_line};
+use super::{ACCOUNTS_FILE, AccountStore, Change, History, JsonStore};
+use common::Account;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+pub const WAL_FILE: &str = "accounts.wal";
+pub const WAL_SNAPSHOT_FILE: &str = "accounts.snapshot.json";
+
+// Records appended before the log is folded into a new snapshot
+const SNAPSHOT_INTERVAL: usize = 1000;
+
+#[derive(Serialize, Deserialize)]
+enum Entry {
+    Put(Account),
+    Delete(String),
+}
+
+// Appends the changes of each command to a log instead of rewriting every
+// account, and rebuilds the accounts from the last snapshot plus the log.
+// Each log line is "<crc32 of the record> <record as JSON>", so a record cut
+// short by a crash is recognised and dropped on the next start
+pub struct WalStore {
+    snapshot: JsonStore,
+    snapshot_path: PathBuf,
+    log_path: PathBuf,
+    log: Option<File>,
+    records: usize,
+}
+
+impl WalStore {
+    pub fn new(snapshot_path: impl Into<PathBuf>, log_path: impl Into<PathBuf>) -> Self {
+        let snapshot_path = snapshot_path.into();
+        WalStore {
+            snapshot: JsonStore::new(&snapshot_path),
+            snapshot_path,
+            log_path: log_path.into(),
+            log: None,
+            records: 0,
+        }
+    }
+
+    fn log(&mut self) -> io::Result<&mut File> {
+        if self.log.is_none() {
+            self.log = Some(open_append(&self.log_path)?);
+        }
+        Ok(self.log.as_mut().unwrap())
+    }
+
+    // Applies every intact record to `accounts` and returns how many there were
+    fn replay(&self, accounts: &mut HashMap<String, Account>) -> io::Result<usize> {
+        let records = read_records::<Vec<Entry>>(&self.log_path)?.records;
+        for entry in records.iter().flatten() {
+            match entry {
+                Entry::Put(account) => {
+                    accounts.insert(account.card_number.to_string(), account.clone());
+                }
+                Entry::Delete(card_number) => {
+                    accounts.remove(card_number);
+                }
+            }
+        }
+        Ok(records.len())
+    }
+
+    // Folds the log into a new snapshot and starts an empty log
+    fn write_snapshot(&mut self, accounts: &HashMap<String, Account>) -> io::Result<()> {
+        self.snapshot.save(accounts)?;
+        let log = self.log()?;
+        log.set_len(0)?;
+        log.sync_all()?;
+        self.records = 0;
+        Ok(())
+    }
+
+    // Whatever got written of a record that could not be appended and synced
+    // is cut off again, so it is neither replayed nor left in front of the
+    // next record
+    fn append(&mut self, entries: &[Entry]) -> io::Result<()> {
+        let line = record_line(&entries)?;
+        let log = self.log()?;
+        let len = log.metadata()?.len();
+        if let Err(e) = log.write_all(line.as_bytes()).and_then(|_| log.sync_data()) {
+            log.set_len(len)?;
+            log.sync_all()?;
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+impl AccountStore for WalStore {
+    fn load(&mut self) -> io::Result<HashMap<String, Account>> {
+        let new_store = !self.snapshot_path.exists() && !self.log_path.exists();
+        // A new store takes over the accounts of the JSON store next to it
+        let import_path = self.snapshot_path.with_file_name(ACCOUNTS_FILE);
+        let mut accounts = if new_store && import_path.exists() {
+            let accounts = JsonStore::new(&import_path).load()?;
+            info!(
+                accounts = accounts.len(),
+                from = %import_path.display(),
+                "Imported accounts"
+            );
+            self.snapshot.save(&accounts)?;
+            accounts
+        } else {
+            self.snapshot.load()?
+        };
+
+        let records = self.replay(&mut accounts)?;
+        if records > 0 {
+            info!(records, path = %self.log_path.display(), "Replayed the log");
+        }
+        // Also discards a torn record left at the end of the log
+        self.write_snapshot(&accounts)?;
+        Ok(accounts)
+    }
+
+    fn commit(
+        &mut self,
+        accounts: &HashMap<String, Account>,
+        changes: &[Change],
+    ) -> io::Result<()> {
+        // Postings and reversal markers are kept in the ledger journal next
+        // to the snapshot
+        let len = self.snapshot.ledger.append(changes)?;
+        let entries: Vec<Entry> = changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::Updated(card_number) => accounts.get(card_number).cloned().map(Entry::Put),
+                Change::Removed(card_number) => Some(Entry::Delete(card_number.clone())),
+                Change::Posted(_) | Change::Voided(_) => None,
+            })
+            .collect();
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(e) = self.append(&entries) {
+            self.snapshot.ledger.truncate(len)?;
+            return Err(e);
+        }
+
+        self.records += 1;
+        // The command is durable once it is in the log, so it is not failed
+        // when folding the log does not work out; the next commit tries again
+        if self.records >= SNAPSHOT_INTERVAL
+            && let Err(e) = self.write_snapshot(accounts)
+        {
+            warn!(error = %e, "Cannot write a snapshot");
+        }
+        Ok(())
+    }
+
+    fn load_history(&mut self) -> io::Result<History> {
+        self.snapshot.ledger.load()
+    }
+
+    // Saves replaying the log on the next start
+    fn flush(&mut self, accounts: &HashMap<String, Account>) -> io::Result<()> {
+        if self.records > 0 {
+            self.write_snapshot(accounts)?;
+        }
+        Ok(())
+    }
+}
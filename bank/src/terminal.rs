@@ -0,0 +1,117 @@
+use chrono::{DateTime, Local};
+use common::{TerminalSummary, write_json_atomic};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+use tracing::warn;
+
+pub const TERMINALS_FILE: &str = "terminals.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Terminal {
+    terminal_id: String,
+    location: String,
+    #[serde(default)]
+    last_seen: Option<DateTime<Local>>,
+}
+
+// The ATMs allowed to connect, by terminal id
+pub struct Terminals {
+    path: PathBuf,
+    terminals: BTreeMap<String, Terminal>,
+}
+
+impl Terminals {
+    pub fn load(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        if !path.exists() {
+            // A new bank knows the ATM the client runs as unless told otherwise
+            let terminal = Terminal {
+                terminal_id: "ATM-0001".to_string(),
+                location: "Head office".to_string(),
+                last_seen: None,
+            };
+            return Ok(Terminals {
+                path,
+                terminals: BTreeMap::from([(terminal.terminal_id.clone(), terminal)]),
+            });
+        }
+
+        let file = File::open(&path)?;
+        let reader = BufReader::new(file);
+        let terminals: Vec<Terminal> = serde_json::from_reader(reader).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid {}: {}", path.display(), e),
+            )
+        })?;
+        Ok(Terminals {
+            path,
+            terminals: terminals
+                .into_iter()
+                .map(|terminal| (terminal.terminal_id.clone(), terminal))
+                .collect(),
+        })
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let terminals: Vec<&Terminal> = self.terminals.values().collect();
+        write_json_atomic(&self.path, &terminals)
+    }
+
+    // Accepts a connecting ATM if its id is registered. The location it
+    // reports is only checked against the registry, never stored
+    pub fn register(&mut self, terminal_id: &str, location: &str) -> io::Result<bool> {
+        let Some(terminal) = self.terminals.get_mut(terminal_id) else {
+            return Ok(false);
+        };
+        if terminal.location != location {
+            warn!(
+                terminal = terminal_id,
+                reported = location,
+                registered = %terminal.location,
+                "Terminal reports a different location"
+            );
+        }
+        terminal.last_seen = Some(Local::now());
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn add(&mut self, terminal_id: String, location: String) -> io::Result<bool> {
+        if self.terminals.contains_key(&terminal_id) {
+            return Ok(false);
+        }
+        self.terminals.insert(
+            terminal_id.clone(),
+            Terminal {
+                terminal_id,
+                location,
+                last_seen: None,
+            },
+        );
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn remove(&mut self, terminal_id: &str) -> io::Result<bool> {
+        if self.terminals.remove(terminal_id).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn list(&self) -> Vec<TerminalSummary> {
+        self.terminals
+            .values()
+            .map(|terminal| TerminalSummary {
+                terminal_id: terminal.terminal_id.clone(),
+                location: terminal.location.clone(),
+                last_seen: terminal.last_seen,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,273 @@
+// Helpers for tests against a real bank: a server started in this process
+// with its sockets and files in a directory of its own, and clients speaking
+// the ATM and admin protocols to it
+use crate::admin::{ADMIN_TOKEN_FILE, load_admin_token};
+use crate::config::Config;
+use crate::server::{Server, State};
+use crate::store::ACCOUNTS_FILE;
+use chrono::Local;
+use common::{
+    Account, AdminCommand, AdminRequest, AdminResponse, CardNumber, CardStatus, Command, Endpoint,
+    Response, Stream, default_expiry, receive_message, receive_response, send_command,
+    send_message,
+};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// The terminal a new bank knows, which `TestBank::connect` registers as
+pub const TEST_TERMINAL: &str = "ATM-0001";
+
+static NEXT_DIR: AtomicU64 = AtomicU64::new(0);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+// Removed with everything in it when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> io::Result<Self> {
+        let n = NEXT_DIR.fetch_add(1, Ordering::SeqCst);
+        let path = std::env::temp_dir().join(format!("bank-test-{}-{}", process::id(), n));
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        fs::create_dir_all(&path)?;
+        Ok(TempDir(path))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// A bank running in this process. It starts with the sample accounts in the
+// memory store unless told otherwise, and is stopped when dropped
+pub struct TestBank {
+    server: Option<Server>,
+    config: Config,
+    // Dropped after the server has stopped
+    _dir: TempDir,
+}
+
+impl TestBank {
+    pub fn start() -> io::Result<Self> {
+        TestBank::start_with(|_| Ok(()))
+    }
+
+    // Starts with these accounts in a JSON store
+    pub fn with_accounts(accounts: impl IntoIterator<Item = Account>) -> io::Result<Self> {
+        let accounts: HashMap<String, Account> = accounts
+            .into_iter()
+            .map(|account| (account.card_number.to_string(), account))
+            .collect();
+        TestBank::start_with(|config| {
+            config.store = "json".to_string();
+            let file = File::create(config.data_file(ACCOUNTS_FILE))?;
+            serde_json::to_writer(file, &accounts)?;
+            Ok(())
+        })
+    }
+
+    // `prepare` may change the settings and write files such as routing.json
+    // to the data directory before the bank starts
+    pub fn start_with(prepare: impl FnOnce(&mut Config) -> io::Result<()>) -> io::Result<Self> {
+        let dir = TempDir::new()?;
+        let mut config = Config {
+            socket_path: dir.0.join("atm.sock"),
+            admin_socket_path: dir.0.join("admin.sock"),
+            metrics_address: "off".to_string(),
+            data_dir: dir.0.clone(),
+            store: "memory".to_string(),
+            shutdown_timeout: 1,
+            ..Config::default()
+        };
+        prepare(&mut config)?;
+        let server = Server::start(config.clone())?;
+        Ok(TestBank {
+            server: Some(server),
+            config,
+            _dir: dir,
+        })
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.config.data_dir
+    }
+
+    pub fn state(&self) -> &Arc<State> {
+        self.server.as_ref().unwrap().state()
+    }
+
+    // The account as the bank holds it now
+    pub fn account(&self, card_number: &str) -> Option<Account> {
+        let bank = self.state().bank.lock().unwrap();
+        bank.accounts.get(card_number).cloned()
+    }
+
+    // A client registered as TEST_TERMINAL
+    pub fn connect(&self) -> io::Result<TestClient> {
+        self.connect_as(TEST_TERMINAL)
+    }
+
+    // Fails unless the bank accepts the terminal
+    pub fn connect_as(&self, terminal_id: &str) -> io::Result<TestClient> {
+        let mut client = self.connect_unregistered()?;
+        match client.register(terminal_id)? {
+            Response::TerminalRegistered => Ok(client),
+            other => Err(io::Error::other(format!(
+                "terminal {} not registered: {:?}",
+                terminal_id, other
+            ))),
+        }
+    }
+
+    // A connection that has not sent RegisterTerminal yet
+    pub fn connect_unregistered(&self) -> io::Result<TestClient> {
+        let stream = Stream::connect(&Endpoint::Unix(self.config.socket_path.clone()))?;
+        Ok(TestClient { stream })
+    }
+
+    // Sends one command over the admin socket with the bank's token
+    pub fn admin(&self, command: AdminCommand) -> io::Result<AdminResponse> {
+        let token = load_admin_token(&self.config.data_file(ADMIN_TOKEN_FILE))?;
+        let mut stream = Stream::connect(&Endpoint::Unix(self.config.admin_socket_path.clone()))?;
+        send_message(&mut stream, &AdminRequest { token, command })?;
+        receive_message(&mut stream)
+    }
+
+    // Stops the bank and starts it again on the same data directory.
+    // Connections made before are closed, and the memory store starts over
+    pub fn restart(&mut self) -> io::Result<()> {
+        if let Some(server) = self.server.take() {
+            server.stop();
+        }
+        self.server = Some(Server::start(self.config.clone())?);
+        Ok(())
+    }
+
+    // Shuts the bank down and waits for it, as dropping it does
+    pub fn stop(mut self) {
+        if let Some(server) = self.server.take() {
+            server.stop();
+        }
+    }
+}
+
+impl Drop for TestBank {
+    fn drop(&mut self) {
+        if let Some(server) = self.server.take() {
+            server.stop();
+        }
+    }
+}
+
+// One connection to the bank, as an ATM would make it
+pub struct TestClient {
+    stream: Stream,
+}
+
+impl TestClient {
+    pub fn request(&mut self, command: &Command) -> io::Result<Response> {
+        send_command(&mut self.stream, command)?;
+        receive_response(&mut self.stream)
+    }
+
+    pub fn register(&mut self, terminal_id: &str) -> io::Result<Response> {
+        self.request(&Command::RegisterTerminal {
+            terminal_id: terminal_id.to_string(),
+            location: "Test lab".to_string(),
+        })
+    }
+
+    pub fn validate_card_key(&mut self, card_key: &str) -> io::Result<Response> {
+        self.request(&Command::ValidateCardKey {
+            card_key: card_key.to_string(),
+        })
+    }
+
+    pub fn check_balance(&mut self, card_number: &str, pin: &str) -> io::Result<Response> {
+        self.request(&Command::CheckBalance {
+            card_number: card_number.to_string(),
+            pin: pin.to_string(),
+        })
+    }
+
+    // Under a new withdrawal id, which is returned to reverse it with
+    pub fn withdraw(
+        &mut self,
+        card_number: &str,
+        pin: &str,
+        amount: f64,
+    ) -> io::Result<(String, Response)> {
+        let withdrawal_id = self.next_id();
+        let response = self.request(&Command::Withdraw {
+            withdrawal_id: withdrawal_id.clone(),
+            card_number: card_number.to_string(),
+            pin: pin.to_string(),
+            amount,
+        })?;
+        Ok((withdrawal_id, response))
+    }
+
+    pub fn reverse_withdrawal(
+        &mut self,
+        withdrawal_id: &str,
+        card_number: &str,
+        amount: f64,
+    ) -> io::Result<Response> {
+        self.request(&Command::ReverseWithdrawal {
+            withdrawal_id: withdrawal_id.to_string(),
+            card_number: card_number.to_string(),
+            amount,
+        })
+    }
+
+    pub fn withdrawal_advice(
+        &mut self,
+        advice_id: &str,
+        card_number: &str,
+        amount: f64,
+    ) -> io::Result<Response> {
+        self.request(&Command::WithdrawalAdvice {
+            advice_id: advice_id.to_string(),
+            card_number: card_number.to_string(),
+            amount,
+            approved_at: Local::now(),
+        })
+    }
+
+    // Unique across connections and restarts, like the ids an ATM gives its
+    // withdrawals
+    pub fn next_id(&mut self) -> String {
+        let n = NEXT_ID.fetch_add(1, Ordering::SeqCst) + 1;
+        format!("test-{}-{}", process::id(), n)
+    }
+}
+
+// An active card of the bank's own, valid until the default expiry
+pub fn test_account(card_number: &str, card_key: &str, pin: &str, balance: f64) -> Account {
+    Account {
+        card_key: card_key.to_string(),
+        card_number: CardNumber::parse(card_number).unwrap(),
+        pin: pin.to_string(),
+        balance,
+        name: "Test Customer".to_string(),
+        status: CardStatus::Active,
+        issued_on: Local::now().date_naive(),
+        expires_on: default_expiry(),
+    }
+}
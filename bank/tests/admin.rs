@@ -0,0 +1,161 @@
+// Admin commands that open and close accounts, against a bank started
+// in-process
+use bank::testing::TestBank;
+use common::{AdminCommand, AdminResponse, CardNumber, Response};
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+
+// Sample account every new bank has, with the highest account number
+const JOHN: &str = "1234567890123452";
+
+fn create(bank: &TestBank, initial_balance: f64, card_number: Option<&str>) -> AdminResponse {
+    bank.admin(AdminCommand::CreateAccount {
+        name: "New Customer".to_string(),
+        pin: "2468".to_string(),
+        initial_balance,
+        card_number: card_number.map(str::to_string),
+    })
+    .unwrap()
+}
+
+fn close(bank: &TestBank, card_number: &str) -> AdminResponse {
+    bank.admin(AdminCommand::CloseAccount {
+        card_number: card_number.to_string(),
+    })
+    .unwrap()
+}
+
+// Created with this opening balance, then emptied at the ATM and closed
+fn open_and_close(bank: &TestBank, initial_balance: f64) -> io::Result<String> {
+    let AdminResponse::AccountCreated { card_number, .. } = create(bank, initial_balance, None)
+    else {
+        panic!("account not created");
+    };
+    let mut client = bank.connect()?;
+    client.withdraw(&card_number, "2468", initial_balance)?;
+    let response = close(bank, &card_number);
+    assert!(
+        matches!(response, AdminResponse::AccountClosed),
+        "{:?}",
+        response
+    );
+    Ok(card_number)
+}
+
+#[test]
+fn create_account() -> io::Result<()> {
+    let bank = TestBank::start()?;
+    let AdminResponse::AccountCreated {
+        card_number,
+        card_key,
+    } = create(&bank, 150.0, None)
+    else {
+        panic!("account not created");
+    };
+    let john = CardNumber::parse(JOHN).unwrap();
+    let expected = CardNumber::from_parts(john.bin(), john.account_number() + 1).unwrap();
+    assert_eq!(card_number, expected.to_string());
+
+    let mut client = bank.connect()?;
+    assert_eq!(
+        client.validate_card_key(&card_key)?,
+        Response::ValidateCardKeySuccess {
+            card_number: card_number.clone()
+        }
+    );
+    assert_eq!(
+        client.check_balance(&card_number, "2468")?,
+        Response::CheckBalanceSuccess { amount: 150.0 }
+    );
+    let response = create(&bank, 0.0, Some(&card_number));
+    assert!(
+        matches!(response, AdminResponse::ErrorAccountExists),
+        "{:?}",
+        response
+    );
+    let response = create(&bank, -1.0, None);
+    assert!(
+        matches!(response, AdminResponse::ErrorInvalidAmount),
+        "{:?}",
+        response
+    );
+    Ok(())
+}
+
+#[test]
+fn close_account() -> io::Result<()> {
+    let bank = TestBank::start()?;
+    let AdminResponse::AccountCreated { card_number, .. } = create(&bank, 80.0, None) else {
+        panic!("account not created");
+    };
+    let response = close(&bank, &card_number);
+    assert!(
+        matches!(response, AdminResponse::ErrorBalanceNotZero { .. }),
+        "{:?}",
+        response
+    );
+    let mut client = bank.connect()?;
+    client.withdraw(&card_number, "2468", 80.0)?;
+    let response = close(&bank, &card_number);
+    assert!(
+        matches!(response, AdminResponse::AccountClosed),
+        "{:?}",
+        response
+    );
+    assert!(bank.account(&card_number).is_none());
+    let response = close(&bank, &card_number);
+    assert!(
+        matches!(response, AdminResponse::ErrorAccountNotFound),
+        "{:?}",
+        response
+    );
+    Ok(())
+}
+
+#[test]
+fn closed_card_numbers_are_not_issued_again() -> io::Result<()> {
+    let mut bank = TestBank::start_with(|config| {
+        config.store = "json".to_string();
+        Ok(())
+    })?;
+    let closed = open_and_close(&bank, 50.0)?;
+    let AdminResponse::AccountCreated { card_number, .. } = create(&bank, 0.0, None) else {
+        panic!("account not created");
+    };
+    assert_ne!(card_number, closed);
+    let response = create(&bank, 0.0, Some(&closed));
+    assert!(
+        matches!(response, AdminResponse::ErrorCardNumberUsed),
+        "{:?}",
+        response
+    );
+
+    // The books remember it across a restart
+    bank.restart()?;
+    let response = create(&bank, 0.0, Some(&closed));
+    assert!(
+        matches!(response, AdminResponse::ErrorCardNumberUsed),
+        "{:?}",
+        response
+    );
+    Ok(())
+}
+
+#[test]
+fn admin_socket_is_private() -> io::Result<()> {
+    let bank = TestBank::start()?;
+    let path = &bank.config().admin_socket_path;
+    assert_eq!(fs::metadata(path)?.permissions().mode() & 0o777, 0o600);
+    // Nothing is left of the directory it was bound in
+    for entry in fs::read_dir(path.parent().unwrap())? {
+        assert!(!entry?.file_type()?.is_dir());
+    }
+    let response = bank.admin(AdminCommand::ListAccounts)?;
+    assert!(
+        matches!(response, AdminResponse::Accounts { .. }),
+        "{:?}",
+        response
+    );
+    Ok(())
+}
@@ -0,0 +1,462 @@
+// Every ATM command against a bank started in-process, with its answers for
+// good and bad input
+use bank::routing::{ForeignCards, ROUTING_FILE, RoutingConfig};
+use bank::store::ACCOUNTS_FILE;
+use bank::testing::{TestBank, test_account};
+use chrono::{Duration, Local};
+use common::{
+    AdminCommand, AdminResponse, CardNumber, CardStatus, CassetteCount, Command, Endpoint,
+    Response, Stream,
+};
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+// Sample accounts every new bank has
+const JOHN: &str = "1234567890123452";
+const JANE: &str = "9876543210987658";
+
+fn own_card(account_number: u64) -> String {
+    CardNumber::from_parts(123456, account_number)
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn register_terminal() -> io::Result<()> {
+    let bank = TestBank::start()?;
+    let mut client = bank.connect_unregistered()?;
+    assert_eq!(client.register("ATM-0001")?, Response::TerminalRegistered);
+    // Only the first command registers
+    assert_eq!(client.register("ATM-0001")?, Response::ErrorServerInternal);
+
+    let mut unknown = bank.connect_unregistered()?;
+    assert_eq!(
+        unknown.register("ATM-9999")?,
+        Response::ErrorUnknownTerminal
+    );
+
+    let mut unregistered = bank.connect_unregistered()?;
+    assert_eq!(
+        unregistered.validate_card_key("key123")?,
+        Response::ErrorUnknownTerminal
+    );
+    Ok(())
+}
+
+#[test]
+fn oversized_message_is_refused() -> io::Result<()> {
+    let bank = TestBank::start()?;
+    let mut stream = Stream::connect(&Endpoint::Unix(bank.config().socket_path.clone()))?;
+    stream.write_all(&u32::MAX.to_be_bytes())?;
+    // The bank hangs up instead of waiting for 4 GiB
+    let mut buffer = [0u8; 1];
+    assert_eq!(stream.read(&mut buffer)?, 0);
+    // and goes on serving others
+    assert!(bank.connect().is_ok());
+    Ok(())
+}
+
+#[test]
+fn added_terminal_may_register() -> io::Result<()> {
+    let bank = TestBank::start()?;
+    assert!(bank.connect_as("ATM-0002").is_err());
+    let response = bank.admin(AdminCommand::AddTerminal {
+        terminal_id: "ATM-0002".to_string(),
+        location: "Branch".to_string(),
+    })?;
+    assert!(matches!(response, AdminResponse::TerminalAdded));
+    bank.connect_as("ATM-0002")?;
+    Ok(())
+}
+
+#[test]
+fn validate_card_key() -> io::Result<()> {
+    let bank = TestBank::start()?;
+    let mut client = bank.connect()?;
+    assert_eq!(
+        client.validate_card_key("key123")?,
+        Response::ValidateCardKeySuccess {
+            card_number: JOHN.to_string()
+        }
+    );
+    assert_eq!(
+        client.validate_card_key("nope")?,
+        Response::ValidateCardKeyErrorInvalid
+    );
+    Ok(())
+}
+
+#[test]
+fn card_status_is_checked() -> io::Result<()> {
+    let mut blocked = test_account(&own_card(1), "blocked", "1111", 100.0);
+    blocked.status = CardStatus::Blocked;
+    let mut lost = test_account(&own_card(2), "lost", "2222", 100.0);
+    lost.status = CardStatus::Lost;
+    let mut expired = test_account(&own_card(3), "expired", "3333", 100.0);
+    expired.expires_on = Local::now().date_naive() - Duration::days(1);
+    let bank = TestBank::with_accounts([blocked.clone(), lost.clone(), expired.clone()])?;
+    let mut client = bank.connect()?;
+
+    for (account, error) in [
+        (&blocked, Response::ErrorCardBlocked),
+        (&lost, Response::ErrorCardLost),
+        (&expired, Response::ErrorCardExpired),
+    ] {
+        assert_eq!(client.validate_card_key(&account.card_key)?, error);
+        assert_eq!(
+            client.check_balance(account.card_number.as_str(), &account.pin)?,
+            error
+        );
+        let (_, response) = client.withdraw(account.card_number.as_str(), &account.pin, 10.0)?;
+        assert_eq!(response, error);
+    }
+    Ok(())
+}
+
+#[test]
+fn frozen_card_is_blocked() -> io::Result<()> {
+    let bank = TestBank::start()?;
+    let mut client = bank.connect()?;
+    let response = bank.admin(AdminCommand::FreezeAccount {
+        card_number: JANE.to_string(),
+    })?;
+    assert!(matches!(response, AdminResponse::AccountFrozen));
+    assert_eq!(
+        client.check_balance(JANE, "4321")?,
+        Response::ErrorCardBlocked
+    );
+    Ok(())
+}
+
+#[test]
+fn check_balance() -> io::Result<()> {
+    let bank = TestBank::start()?;
+    let mut client = bank.connect()?;
+    assert_eq!(
+        client.check_balance(JOHN, "1234")?,
+        Response::CheckBalanceSuccess { amount: 1000.0 }
+    );
+    assert_eq!(
+        client.check_balance(JOHN, "9999")?,
+        Response::ErrorInvalidPin
+    );
+    assert_eq!(
+        client.check_balance(&own_card(42), "1234")?,
+        Response::ErrorCardNotFound
+    );
+    Ok(())
+}
+
+#[test]
+fn card_numbers_are_routed() -> io::Result<()> {
+    let bank = TestBank::start()?;
+    let mut client = bank.connect()?;
+    // Fails the Luhn check
+    assert_eq!(
+        client.check_balance("1234567890123453", "1234")?,
+        Response::ErrorInvalidCardNumber
+    );
+    // No issuer has this BIN
+    let unknown = CardNumber::from_parts(600000, 1).unwrap().to_string();
+    assert_eq!(
+        client.check_balance(&unknown, "1234")?,
+        Response::ErrorCardNotSupported
+    );
+    // Another issuer's card, and foreign cards are rejected by default
+    let visa = CardNumber::from_parts(411111, 1).unwrap().to_string();
+    assert_eq!(
+        client.check_balance(&visa, "1234")?,
+        Response::ErrorCardNotSupported
+    );
+    Ok(())
+}
+
+#[test]
+fn unreachable_network_is_an_internal_error() -> io::Result<()> {
+    let bank = TestBank::start_with(|config| {
+        let routing = RoutingConfig {
+            foreign_cards: ForeignCards::Forward {
+                socket_path: config.data_file("no-network.sock").display().to_string(),
+            },
+            ..RoutingConfig::default()
+        };
+        let file = File::create(config.data_file(ROUTING_FILE))?;
+        serde_json::to_writer(file, &routing)?;
+        Ok(())
+    })?;
+    let mut client = bank.connect()?;
+    let visa = CardNumber::from_parts(411111, 1).unwrap().to_string();
+    let (_, response) = client.withdraw(&visa, "1234", 20.0)?;
+    assert_eq!(response, Response::ErrorServerInternal);
+    Ok(())
+}
+
+#[test]
+fn withdraw() -> io::Result<()> {
+    let bank = TestBank::start()?;
+    let mut client = bank.connect()?;
+    let (_, response) = client.withdraw(JANE, "4321", 120.0)?;
+    assert_eq!(response, Response::WithdrawSuccess { new_balance: 380.0 });
+    assert_eq!(bank.account(JANE).unwrap().balance, 380.0);
+
+    let (_, response) = client.withdraw(JANE, "4321", 380.01)?;
+    assert_eq!(response, Response::WithdrawErrorInsufficientFunds);
+    let (_, response) = client.withdraw(JANE, "0000", 10.0)?;
+    assert_eq!(response, Response::ErrorInvalidPin);
+    let (_, response) = client.withdraw(&own_card(42), "4321", 10.0)?;
+    assert_eq!(response, Response::ErrorCardNotFound);
+    assert_eq!(bank.account(JANE).unwrap().balance, 380.0);
+    Ok(())
+}
+
+#[test]
+fn withdraw_rejects_invalid_amounts() -> io::Result<()> {
+    let bank = TestBank::start_with(|config| {
+        config.max_withdrawal = 200.0;
+        Ok(())
+    })?;
+    let mut client = bank.connect()?;
+    for amount in [0.0, -5.0, 200.01] {
+        let (_, response) = client.withdraw(JOHN, "1234", amount)?;
+        assert_eq!(response, Response::WithdrawErrorInvalidAmount, "{}", amount);
+    }
+    let (_, response) = client.withdraw(JOHN, "1234", 200.0)?;
+    assert_eq!(response, Response::WithdrawSuccess { new_balance: 800.0 });
+    Ok(())
+}
+
+// Answered the same whichever store keeps the books
+#[test]
+fn withdrawal_id_is_booked_once() -> io::Result<()> {
+    for store in ["memory", "json", "wal", "sqlite"] {
+        let bank = TestBank::start_with(|config| {
+            config.store = store.to_string();
+            Ok(())
+        })?;
+        let mut client = bank.connect()?;
+        let (withdrawal_id, _) = client.withdraw(JOHN, "1234", 100.0)?;
+        let again = client.request(&Command::Withdraw {
+            withdrawal_id: withdrawal_id.clone(),
+            card_number: JOHN.to_string(),
+            pin: "1234".to_string(),
+            amount: 100.0,
+        })?;
+        assert_eq!(again, Response::WithdrawErrorDuplicate, "{}", store);
+        assert_eq!(bank.account(JOHN).unwrap().balance, 900.0, "{}", store);
+        assert_eq!(
+            client.reverse_withdrawal(&withdrawal_id, JOHN, 100.0)?,
+            Response::WithdrawalReversed
+        );
+        assert_eq!(bank.account(JOHN).unwrap().balance, 1000.0, "{}", store);
+    }
+    Ok(())
+}
+
+#[test]
+fn reverse_withdrawal() -> io::Result<()> {
+    let bank = TestBank::start()?;
+    let mut client = bank.connect()?;
+    let (withdrawal_id, _) = client.withdraw(JOHN, "1234", 250.0)?;
+    assert_eq!(bank.account(JOHN).unwrap().balance, 750.0);
+
+    assert_eq!(
+        client.reverse_withdrawal(&withdrawal_id, JOHN, 250.0)?,
+        Response::WithdrawalReversed
+    );
+    assert_eq!(bank.account(JOHN).unwrap().balance, 1000.0);
+    // Sent again after a lost answer, and given back only once
+    assert_eq!(
+        client.reverse_withdrawal(&withdrawal_id, JOHN, 250.0)?,
+        Response::WithdrawalReversed
+    );
+    assert_eq!(bank.account(JOHN).unwrap().balance, 1000.0);
+    // The bank never booked it, so there is nothing to give back
+    assert_eq!(
+        client.reverse_withdrawal("never-booked", JOHN, 250.0)?,
+        Response::WithdrawalNotFound
+    );
+    assert_eq!(bank.account(JOHN).unwrap().balance, 1000.0);
+    // and the withdrawal is refused if it arrives after all
+    let late = client.request(&Command::Withdraw {
+        withdrawal_id: "never-booked".to_string(),
+        card_number: JOHN.to_string(),
+        pin: "1234".to_string(),
+        amount: 250.0,
+    })?;
+    assert_eq!(late, Response::WithdrawErrorReversed);
+    assert_eq!(bank.account(JOHN).unwrap().balance, 1000.0);
+    Ok(())
+}
+
+#[test]
+fn reversal_is_per_terminal() -> io::Result<()> {
+    let bank = TestBank::start()?;
+    bank.admin(AdminCommand::AddTerminal {
+        terminal_id: "ATM-0002".to_string(),
+        location: "Branch".to_string(),
+    })?;
+    let mut first = bank.connect()?;
+    let mut second = bank.connect_as("ATM-0002")?;
+    let (withdrawal_id, _) = first.withdraw(JOHN, "1234", 100.0)?;
+    assert_eq!(
+        second.reverse_withdrawal(&withdrawal_id, JOHN, 100.0)?,
+        Response::WithdrawalNotFound
+    );
+    assert_eq!(bank.account(JOHN).unwrap().balance, 900.0);
+    Ok(())
+}
+
+#[test]
+fn withdrawal_advice() -> io::Result<()> {
+    let bank = TestBank::start()?;
+    let mut client = bank.connect()?;
+    assert_eq!(
+        client.withdrawal_advice("advice-1", JANE, 100.0)?,
+        Response::AdviceRecorded
+    );
+    assert_eq!(bank.account(JANE).unwrap().balance, 400.0);
+    // Resent after a lost answer and booked once
+    assert_eq!(
+        client.withdrawal_advice("advice-1", JANE, 100.0)?,
+        Response::AdviceRecorded
+    );
+    assert_eq!(bank.account(JANE).unwrap().balance, 400.0);
+    // The cash is gone, so the account may go overdrawn
+    assert_eq!(
+        client.withdrawal_advice("advice-2", JANE, 450.0)?,
+        Response::AdviceRecorded
+    );
+    assert_eq!(bank.account(JANE).unwrap().balance, -50.0);
+    let (_, response) = client.withdraw(JANE, "4321", 10.0)?;
+    assert_eq!(response, Response::WithdrawErrorInsufficientFunds);
+
+    assert_eq!(
+        client.withdrawal_advice("advice-3", &own_card(42), 10.0)?,
+        Response::ErrorCardNotFound
+    );
+    assert_eq!(
+        client.withdrawal_advice("advice-4", JANE, 0.0)?,
+        Response::WithdrawErrorInvalidAmount
+    );
+    Ok(())
+}
+
+#[test]
+fn advice_is_booked_for_blocked_cards() -> io::Result<()> {
+    let mut blocked = test_account(&own_card(1), "blocked", "1111", 100.0);
+    blocked.status = CardStatus::Blocked;
+    let bank = TestBank::with_accounts([blocked.clone()])?;
+    let mut client = bank.connect()?;
+    assert_eq!(
+        client.withdrawal_advice("advice-1", blocked.card_number.as_str(), 40.0)?,
+        Response::AdviceRecorded
+    );
+    assert_eq!(
+        bank.account(blocked.card_number.as_str()).unwrap().balance,
+        60.0
+    );
+    Ok(())
+}
+
+#[test]
+fn report_cassettes() -> io::Result<()> {
+    let bank = TestBank::start()?;
+    let mut client = bank.connect()?;
+    let command = Command::ReportCassettes {
+        business_date: Local::now().date_naive(),
+        cassettes: vec![CassetteCount {
+            denomination: 20,
+            remaining: 95,
+            dispensed: 5,
+        }],
+    };
+    assert_eq!(client.request(&command)?, Response::CassettesRecorded);
+    Ok(())
+}
+
+#[test]
+fn clients_share_the_bank() -> io::Result<()> {
+    let bank = TestBank::start()?;
+    let mut first = bank.connect()?;
+    let mut second = bank.connect()?;
+    first.withdraw(JOHN, "1234", 300.0)?;
+    assert_eq!(
+        second.check_balance(JOHN, "1234")?,
+        Response::CheckBalanceSuccess { amount: 700.0 }
+    );
+    Ok(())
+}
+
+#[test]
+fn books_balance_after_every_command() -> io::Result<()> {
+    let bank = TestBank::start()?;
+    let mut client = bank.connect()?;
+    let (withdrawal_id, _) = client.withdraw(JOHN, "1234", 60.0)?;
+    client.reverse_withdrawal(&withdrawal_id, JOHN, 60.0)?;
+    client.withdraw(JANE, "4321", 40.0)?;
+    client.withdrawal_advice("advice-1", JANE, 20.0)?;
+    let response = bank.admin(AdminCommand::CheckBooks)?;
+    assert!(
+        matches!(response, AdminResponse::BooksBalanced { .. }),
+        "{:?}",
+        response
+    );
+    Ok(())
+}
+
+#[test]
+fn json_store_saves_each_command() -> io::Result<()> {
+    let account = test_account(&own_card(7), "key7", "7777", 300.0);
+    let bank = TestBank::with_accounts([account.clone()])?;
+    let mut client = bank.connect()?;
+    client.withdraw(account.card_number.as_str(), "7777", 100.0)?;
+    let saved = std::fs::read_to_string(bank.data_dir().join(ACCOUNTS_FILE))?;
+    assert!(saved.contains("200.0"), "{}", saved);
+    Ok(())
+}
+
+fn postings(bank: &TestBank) -> io::Result<usize> {
+    match bank.admin(AdminCommand::CheckBooks)? {
+        AdminResponse::BooksBalanced { postings, .. } => Ok(postings),
+        other => Err(io::Error::other(format!(
+            "books do not balance: {:?}",
+            other
+        ))),
+    }
+}
+
+// The accounts file cannot be replaced while a directory holds its
+// temporary name
+#[test]
+fn failed_commit_leaves_the_bank_unchanged() -> io::Result<()> {
+    let mut bank = TestBank::start_with(|config| {
+        config.store = "json".to_string();
+        Ok(())
+    })?;
+    let booked = postings(&bank)?;
+    let temp = bank.data_dir().join("accounts.json.tmp");
+    std::fs::create_dir(&temp)?;
+
+    let withdraw = Command::Withdraw {
+        withdrawal_id: "w-1".to_string(),
+        card_number: JOHN.to_string(),
+        pin: "1234".to_string(),
+        amount: 100.0,
+    };
+    let mut client = bank.connect()?;
+    assert!(client.request(&withdraw).is_err());
+    assert_eq!(bank.account(JOHN).unwrap().balance, 1000.0);
+    assert_eq!(postings(&bank)?, booked);
+
+    // Nothing of it is remembered, so the ATM may send it again
+    std::fs::remove_dir(&temp)?;
+    let mut client = bank.connect()?;
+    assert_eq!(
+        client.request(&withdraw)?,
+        Response::WithdrawSuccess { new_balance: 900.0 }
+    );
+    bank.restart()?;
+    assert_eq!(bank.account(JOHN).unwrap().balance, 900.0);
+    assert_eq!(postings(&bank)?, booked + 1);
+    Ok(())
+}
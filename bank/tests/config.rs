@@ -0,0 +1,99 @@
+// bank.toml as the bank reads it at startup, with everything wrong in it
+// reported at once
+use bank::config::{Config, Overrides};
+use bank::testing::{TempDir, TestBank};
+use common::{Response, Settings};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+// Sample account every new bank has
+const JOHN: &str = "1234567890123452";
+
+fn load(dir: &TempDir, toml: &str, overrides: &Overrides) -> Result<Config, Vec<String>> {
+    let path = dir.path().join("bank.toml");
+    fs::write(&path, toml).unwrap();
+    Config::load(Some(&path), overrides).map_err(|e| e.0)
+}
+
+#[test]
+fn loads_the_file_and_lets_flags_win() -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let toml = format!(
+        "data_dir = \"{}\"\nstore = \"wal\"\nmax_withdrawal = 200.0\nmax_connections = 4\n",
+        dir.path().display()
+    );
+    let overrides = Overrides {
+        max_connections: Some(8),
+        ..Overrides::default()
+    };
+    let loaded = load(&dir, &toml, &overrides).unwrap();
+    assert_eq!(loaded.data_dir, dir.path());
+    assert_eq!(loaded.store, "wal");
+    assert_eq!(loaded.max_connections, 8);
+
+    let bank = TestBank::start_with(|config| {
+        config.max_withdrawal = loaded.max_withdrawal;
+        Ok(())
+    })?;
+    let mut client = bank.connect()?;
+    let (_, response) = client.withdraw(JOHN, "1234", 250.0)?;
+    assert_eq!(response, Response::WithdrawErrorInvalidAmount);
+    let (_, response) = client.withdraw(JOHN, "1234", 200.0)?;
+    assert_eq!(response, Response::WithdrawSuccess { new_balance: 800.0 });
+    Ok(())
+}
+
+#[test]
+fn refuses_unknown_settings() -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let problems = load(&dir, "max_withdrawl = 100.0\n", &Overrides::default()).unwrap_err();
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].contains("max_withdrawl"), "{:?}", problems);
+    Ok(())
+}
+
+#[test]
+fn reports_every_bad_value() -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let toml = r#"
+socket_path = "/tmp/same.sock"
+admin_socket_path = "/tmp/same.sock"
+tcp_address = "0.0.0.0:7000"
+store = "csv"
+log_format = "xml"
+max_withdrawal = -5.0
+max_connections = 0
+"#;
+    let overrides = Overrides {
+        data_dir: Some(PathBuf::from("/nonexistent/bank")),
+        ..Overrides::default()
+    };
+    let problems = load(&dir, toml, &overrides).unwrap_err();
+    for setting in [
+        "admin_socket_path",
+        "tcp_address",
+        "store",
+        "log_format",
+        "max_withdrawal",
+        "max_connections",
+        "data_dir",
+    ] {
+        assert!(
+            problems.iter().any(|problem| problem.contains(setting)),
+            "{} not reported: {:?}",
+            setting,
+            problems
+        );
+    }
+    assert_eq!(problems.len(), 7, "{:?}", problems);
+    Ok(())
+}
+
+#[test]
+fn refuses_values_of_the_wrong_type() -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let problems = load(&dir, "max_connections = \"many\"\n", &Overrides::default()).unwrap_err();
+    assert!(problems[0].contains("bank.toml"), "{:?}", problems);
+    Ok(())
+}
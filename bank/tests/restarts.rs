@@ -0,0 +1,191 @@
+// The bank stopped and started again on the same data directory, with each
+// store that keeps its data on disk
+use bank::store::{ACCOUNTS_FILE, DATABASE_FILE, SqliteStore, repair_card_numbers};
+use bank::testing::{TempDir, TestBank};
+use common::{AdminCommand, AdminResponse, Command, Response};
+use std::io;
+
+// Sample accounts every new bank has
+const JOHN: &str = "1234567890123452";
+const JANE: &str = "9876543210987658";
+
+fn start(store: &str) -> io::Result<TestBank> {
+    TestBank::start_with(|config| {
+        config.store = store.to_string();
+        Ok(())
+    })
+}
+
+fn check_books(bank: &TestBank) -> io::Result<()> {
+    match bank.admin(AdminCommand::CheckBooks)? {
+        AdminResponse::BooksBalanced { .. } => Ok(()),
+        other => Err(io::Error::other(format!(
+            "books do not balance: {:?}",
+            other
+        ))),
+    }
+}
+
+// What was booked before the restart is still booked once, and can still be
+// reversed
+fn keeps_the_books(store: &str) -> io::Result<()> {
+    let mut bank = start(store)?;
+    let mut client = bank.connect()?;
+    let (withdrawal_id, response) = client.withdraw(JOHN, "1234", 250.0)?;
+    assert_eq!(response, Response::WithdrawSuccess { new_balance: 750.0 });
+    assert_eq!(
+        client.withdrawal_advice("advice-1", JANE, 100.0)?,
+        Response::AdviceRecorded
+    );
+    assert_eq!(
+        client.reverse_withdrawal("never-booked", JOHN, 50.0)?,
+        Response::WithdrawalNotFound
+    );
+
+    bank.restart()?;
+    let mut client = bank.connect()?;
+    assert_eq!(bank.account(JOHN).unwrap().balance, 750.0);
+    assert_eq!(bank.account(JANE).unwrap().balance, 400.0);
+    check_books(&bank)?;
+
+    // The ATM resends an advice whose answer it lost in the restart
+    assert_eq!(
+        client.withdrawal_advice("advice-1", JANE, 100.0)?,
+        Response::AdviceRecorded
+    );
+    assert_eq!(bank.account(JANE).unwrap().balance, 400.0);
+    assert_eq!(
+        client.reverse_withdrawal(&withdrawal_id, JOHN, 250.0)?,
+        Response::WithdrawalReversed
+    );
+    assert_eq!(bank.account(JOHN).unwrap().balance, 1000.0);
+    let late = client.request(&Command::Withdraw {
+        withdrawal_id: "never-booked".to_string(),
+        card_number: JOHN.to_string(),
+        pin: "1234".to_string(),
+        amount: 50.0,
+    })?;
+    assert_eq!(late, Response::WithdrawErrorReversed);
+
+    // and once more, after the reversal
+    bank.restart()?;
+    let mut client = bank.connect()?;
+    assert_eq!(
+        client.reverse_withdrawal(&withdrawal_id, JOHN, 250.0)?,
+        Response::WithdrawalReversed
+    );
+    assert_eq!(bank.account(JOHN).unwrap().balance, 1000.0);
+    match bank.admin(AdminCommand::CheckBooks)? {
+        // Two brought forward, the withdrawal, the advice and the reversal
+        AdminResponse::BooksBalanced { postings, .. } => assert_eq!(postings, 5),
+        other => panic!("{:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn json_store_keeps_the_books() -> io::Result<()> {
+    keeps_the_books("json")
+}
+
+#[test]
+fn wal_store_keeps_the_books() -> io::Result<()> {
+    keeps_the_books("wal")
+}
+
+#[test]
+fn sqlite_store_keeps_the_books() -> io::Result<()> {
+    keeps_the_books("sqlite")
+}
+
+#[test]
+fn balances_are_not_brought_forward_again() -> io::Result<()> {
+    let mut bank = start("json")?;
+    bank.restart()?;
+    bank.restart()?;
+    match bank.admin(AdminCommand::CheckBooks)? {
+        AdminResponse::BooksBalanced { postings, .. } => assert_eq!(postings, 2),
+        other => panic!("{:?}", other),
+    }
+    Ok(())
+}
+
+// The bank stopped after creating the tables of a new database and before
+// importing the accounts into them
+#[test]
+fn empty_database_is_filled_like_a_new_one() -> io::Result<()> {
+    let bank = TestBank::start_with(|config| {
+        config.store = "sqlite".to_string();
+        SqliteStore::open(config.data_file(DATABASE_FILE))?;
+        Ok(())
+    })?;
+    assert_eq!(bank.account(JOHN).unwrap().balance, 1000.0);
+    assert_eq!(bank.account(JANE).unwrap().balance, 500.0);
+    check_books(&bank)
+}
+
+// The ledger still has the withdrawal the backup is missing
+#[test]
+fn corrupt_accounts_file_is_recovered_from_the_backup() -> io::Result<()> {
+    let mut bank = start("json")?;
+    let mut client = bank.connect()?;
+    client.withdraw(JOHN, "1234", 100.0)?;
+    std::fs::write(bank.data_dir().join(ACCOUNTS_FILE), "{\"1234")?;
+
+    bank.restart()?;
+    assert_eq!(bank.account(JOHN).unwrap().balance, 900.0);
+    assert_eq!(bank.account(JANE).unwrap().balance, 500.0);
+    check_books(&bank)
+}
+
+#[test]
+fn unreadable_accounts_and_backup_stop_the_bank() -> io::Result<()> {
+    let mut bank = start("json")?;
+    let mut client = bank.connect()?;
+    client.withdraw(JOHN, "1234", 100.0)?;
+    std::fs::write(bank.data_dir().join(ACCOUNTS_FILE), "{\"1234")?;
+    std::fs::write(bank.data_dir().join("accounts.json.bak"), "")?;
+
+    let error = bank.restart().unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().contains("accounts.json.bak"), "{}", error);
+    Ok(())
+}
+
+// Saved before card numbers were validated, with the old test card number
+#[test]
+fn unrepaired_card_numbers_stop_the_bank() -> io::Result<()> {
+    let data_dir = TempDir::new()?;
+    let accounts = serde_json::json!({
+        "1234567890123456": {
+            "card_key": "key123",
+            "card_number": "1234567890123456",
+            "pin": "1234",
+            "balance": 1000.0,
+            "name": "John Doe"
+        }
+    });
+    std::fs::write(data_dir.path().join(ACCOUNTS_FILE), accounts.to_string())?;
+    let start = || {
+        TestBank::start_with(|config| {
+            config.store = "json".to_string();
+            config.data_dir = data_dir.path().to_path_buf();
+            Ok(())
+        })
+    };
+
+    let error = start().err().unwrap();
+    assert!(error.to_string().contains("xxxxxxxxxxxx3456"), "{}", error);
+
+    let repaired = repair_card_numbers(&data_dir.path().join(ACCOUNTS_FILE))?;
+    assert_eq!(repaired.len(), 1);
+    assert_eq!(repaired[0].0, "1234567890123456");
+    assert_eq!(repaired[0].1.as_str(), JOHN);
+    let bank = start()?;
+    let mut client = bank.connect()?;
+    assert_eq!(
+        client.check_balance(JOHN, "1234")?,
+        Response::CheckBalanceSuccess { amount: 1000.0 }
+    );
+    check_books(&bank)
+}
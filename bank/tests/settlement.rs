@@ -0,0 +1,109 @@
+// End-of-day settlement of what each ATM paid out against what the bank booked
+use bank::testing::{TEST_TERMINAL, TestBank, TestClient};
+use chrono::Local;
+use common::{
+    AdminCommand, AdminResponse, CassetteCount, Command, Response, SettlementStatus,
+    TerminalSettlement,
+};
+use std::fs;
+use std::io;
+
+// Sample accounts every new bank has
+const JOHN: &str = "1234567890123452";
+const JANE: &str = "9876543210987658";
+
+fn connect_new(bank: &TestBank, terminal_id: &str) -> io::Result<TestClient> {
+    let response = bank.admin(AdminCommand::AddTerminal {
+        terminal_id: terminal_id.to_string(),
+        location: "Branch".to_string(),
+    })?;
+    assert!(
+        matches!(response, AdminResponse::TerminalAdded),
+        "{:?}",
+        response
+    );
+    bank.connect_as(terminal_id)
+}
+
+fn report_twenties(client: &mut TestClient, dispensed: u32) -> io::Result<()> {
+    let response = client.request(&Command::ReportCassettes {
+        business_date: Local::now().date_naive(),
+        cassettes: vec![CassetteCount {
+            denomination: 20,
+            remaining: 100 - dispensed,
+            dispensed,
+        }],
+    })?;
+    assert_eq!(response, Response::CassettesRecorded);
+    Ok(())
+}
+
+fn terminal<'a>(terminals: &'a [TerminalSettlement], terminal_id: &str) -> &'a TerminalSettlement {
+    terminals
+        .iter()
+        .find(|terminal| terminal.terminal_id == terminal_id)
+        .unwrap()
+}
+
+#[test]
+fn settles_each_atm_against_its_report() -> io::Result<()> {
+    let bank = TestBank::start()?;
+
+    // Paid out what it reports; the reversed withdrawal does not count
+    let mut balanced = bank.connect()?;
+    balanced.withdraw(JOHN, "1234", 100.0)?;
+    let (withdrawal_id, _) = balanced.withdraw(JOHN, "1234", 60.0)?;
+    balanced.reverse_withdrawal(&withdrawal_id, JOHN, 60.0)?;
+    report_twenties(&mut balanced, 5)?;
+
+    // Reports one note fewer than the bank booked
+    let mut short = connect_new(&bank, "ATM-0002")?;
+    short.withdraw(JANE, "4321", 80.0)?;
+    report_twenties(&mut short, 3)?;
+
+    let mut silent = connect_new(&bank, "ATM-0003")?;
+    silent.withdraw(JOHN, "1234", 40.0)?;
+
+    let AdminResponse::Settled {
+        terminals,
+        csv_path,
+        json_path,
+        ..
+    } = bank.admin(AdminCommand::Settle { date: None })?
+    else {
+        panic!("not settled");
+    };
+    assert_eq!(terminals.len(), 3);
+
+    let terminal_1 = terminal(&terminals, TEST_TERMINAL);
+    assert_eq!(terminal_1.status, SettlementStatus::Balanced);
+    assert_eq!(terminal_1.withdrawals, 1);
+    assert_eq!(terminal_1.bank_total, 100.0);
+    assert_eq!(terminal_1.difference, Some(0.0));
+
+    let terminal_2 = terminal(&terminals, "ATM-0002");
+    assert_eq!(terminal_2.status, SettlementStatus::Discrepancy);
+    assert_eq!(terminal_2.atm_total, Some(60.0));
+    assert_eq!(terminal_2.difference, Some(-20.0));
+
+    let terminal_3 = terminal(&terminals, "ATM-0003");
+    assert_eq!(terminal_3.status, SettlementStatus::MissingReport);
+    assert_eq!(terminal_3.bank_total, 40.0);
+    assert_eq!(terminal_3.atm_total, None);
+
+    let csv = fs::read_to_string(csv_path)?;
+    assert_eq!(csv.lines().count(), 4, "{}", csv);
+    assert!(
+        csv.contains(",ATM-0002,1,80.00,60.00,-20.00,discrepancy"),
+        "{}",
+        csv
+    );
+    assert!(
+        csv.contains(",ATM-0003,1,40.00,,,missing_report"),
+        "{}",
+        csv
+    );
+    let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(json_path)?)?;
+    assert_eq!(json["terminals"].as_array().unwrap().len(), 3);
+    Ok(())
+}
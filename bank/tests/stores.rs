@@ -0,0 +1,126 @@
+// The stores on their own, with their files damaged or failing under them
+use bank::store::{AccountStore, Change, WAL_FILE, WAL_SNAPSHOT_FILE, WalStore};
+use bank::testing::TempDir;
+use common::Account;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+// Sample account every new store has
+const JOHN: &str = "1234567890123452";
+
+// Set for the child process that appends with a file size limit
+const APPEND_DIR_VAR: &str = "WAL_APPEND_DIR";
+
+fn wal_store(dir: &Path) -> WalStore {
+    WalStore::new(dir.join(WAL_SNAPSHOT_FILE), dir.join(WAL_FILE))
+}
+
+// Commits John's account with this balance, one log record each
+fn commit_balances(
+    store: &mut WalStore,
+    accounts: &mut HashMap<String, Account>,
+    balances: &[f64],
+) -> io::Result<()> {
+    for &balance in balances {
+        accounts.get_mut(JOHN).unwrap().balance = balance;
+        store.commit(accounts, &[Change::Updated(JOHN.to_string())])?;
+    }
+    Ok(())
+}
+
+fn john_after_restart(dir: &Path) -> io::Result<f64> {
+    let accounts = wal_store(dir).load()?;
+    Ok(accounts[JOHN].balance)
+}
+
+#[test]
+fn wal_drops_a_torn_record_at_the_end() -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let mut store = wal_store(dir.path());
+    let mut accounts = store.load()?;
+    commit_balances(&mut store, &mut accounts, &[900.0, 800.0])?;
+    drop(store);
+
+    let mut log = OpenOptions::new()
+        .append(true)
+        .open(dir.path().join(WAL_FILE))?;
+    log.write_all(b"0badc0de [{\"Put\":{\"card_key\"")?;
+    drop(log);
+
+    assert_eq!(john_after_restart(dir.path())?, 800.0);
+    // and the next start finds nothing left of it
+    assert_eq!(fs::metadata(dir.path().join(WAL_FILE))?.len(), 0);
+    assert_eq!(john_after_restart(dir.path())?, 800.0);
+    Ok(())
+}
+
+#[test]
+fn wal_refuses_a_corrupt_record_in_the_middle() -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let mut store = wal_store(dir.path());
+    let mut accounts = store.load()?;
+    commit_balances(&mut store, &mut accounts, &[900.0, 800.0])?;
+    drop(store);
+
+    let path = dir.path().join(WAL_FILE);
+    let log = fs::read_to_string(&path)?;
+    fs::write(&path, log.replacen("900.0", "990.0", 1))?;
+
+    let error = wal_store(dir.path()).load().unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().contains("record 1"), "{}", error);
+    Ok(())
+}
+
+// The append runs in a child process, since a file size limit would hold for
+// every test running in this one
+#[test]
+fn wal_cuts_off_a_failed_append() -> io::Result<()> {
+    if let Some(dir) = std::env::var_os(APPEND_DIR_VAR) {
+        return append_past_limit(Path::new(&dir));
+    }
+
+    let dir = TempDir::new()?;
+    let status = process::Command::new(std::env::current_exe()?)
+        .args(["wal_cuts_off_a_failed_append", "--exact", "--quiet"])
+        .env(APPEND_DIR_VAR, dir.path())
+        .status()?;
+    assert!(status.success());
+    // The failed commit was rolled back in memory, so it is not replayed
+    assert_eq!(john_after_restart(dir.path())?, 700.0);
+    Ok(())
+}
+
+fn set_file_size_limit(limit: u64) -> io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: limit,
+        rlim_max: libc::RLIM_INFINITY,
+    };
+    // Past the limit a write fails with EFBIG instead of stopping the process
+    unsafe {
+        libc::signal(libc::SIGXFSZ, libc::SIG_IGN);
+        if libc::setrlimit(libc::RLIMIT_FSIZE, &limit) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn append_past_limit(dir: &Path) -> io::Result<()> {
+    let mut store = wal_store(dir);
+    let mut accounts = store.load()?;
+    commit_balances(&mut store, &mut accounts, &[900.0])?;
+
+    // Room for half of the next record
+    let len = fs::metadata(dir.join(WAL_FILE))?.len();
+    set_file_size_limit(len + len / 2)?;
+    let error = commit_balances(&mut store, &mut accounts, &[800.0]).unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(libc::EFBIG));
+    assert_eq!(fs::metadata(dir.join(WAL_FILE))?.len(), len);
+
+    set_file_size_limit(libc::RLIM_INFINITY)?;
+    commit_balances(&mut store, &mut accounts, &[700.0])
+}
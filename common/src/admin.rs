@@ -0,0 +1,206 @@
+use crate::{Account, CardStatus};
+use chrono::{DateTime, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+// Every admin command carries the credential configured on the bank server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminRequest {
+    pub token: String,
+    pub command: AdminCommand,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AdminCommand {
+    ListAccounts,
+    CreateAccount {
+        name: String,
+        pin: String,
+        initial_balance: f64,
+        // Generated from the bank's own BIN range when not given
+        card_number: Option<String>,
+    },
+    CloseAccount {
+        card_number: String,
+    },
+    IssueCardKey {
+        card_number: String,
+    },
+    ResetPin {
+        card_number: String,
+        new_pin: String,
+    },
+    UnlockCard {
+        card_number: String,
+    },
+    AdjustBalance {
+        card_number: String,
+        amount: f64,
+        reason: String,
+    },
+    FreezeAccount {
+        card_number: String,
+    },
+    ListSessions,
+    KickSession {
+        session_id: u64,
+    },
+    Snapshot,
+    ReloadRouting,
+    Shutdown,
+    CheckBooks,
+    // Settles the given business day, today when not given
+    Settle {
+        date: Option<NaiveDate>,
+    },
+    ListTerminals,
+    AddTerminal {
+        terminal_id: String,
+        location: String,
+    },
+    // Also disconnects the terminal if it is connected
+    RemoveTerminal {
+        terminal_id: String,
+    },
+}
+
+// An account as shown to operators, without the PIN or card key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountSummary {
+    pub card_number: String,
+    pub name: String,
+    pub balance: f64,
+    pub status: CardStatus,
+    pub issued_on: NaiveDate,
+    pub expires_on: NaiveDate,
+}
+
+impl From<&Account> for AccountSummary {
+    fn from(account: &Account) -> Self {
+        AccountSummary {
+            card_number: account.card_number.to_string(),
+            name: account.name.clone(),
+            balance: account.balance,
+            status: account.status,
+            issued_on: account.issued_on,
+            expires_on: account.expires_on,
+        }
+    }
+}
+
+// A connected ATM as shown to operators
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSummary {
+    pub session_id: u64,
+    // None until the ATM has registered
+    pub terminal_id: Option<String>,
+    pub connected_at: DateTime<Local>,
+    pub commands: u64,
+    // Masked number of the last card used in the session
+    pub card: Option<String>,
+}
+
+// An ATM the bank accepts connections from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalSummary {
+    pub terminal_id: String,
+    pub location: String,
+    pub last_seen: Option<DateTime<Local>>,
+}
+
+// One line of the trial balance; positive balances are credits
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerBalance {
+    pub account: String,
+    pub balance: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SettlementStatus {
+    Balanced,
+    Discrepancy,
+    MissingReport,
+}
+
+// What one ATM paid out on a business day according to the bank and to the ATM
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalSettlement {
+    pub terminal_id: String,
+    pub withdrawals: usize,
+    pub bank_total: f64,
+    // Notes dispensed times their denomination, from the ATM's last cassette report
+    pub atm_total: Option<f64>,
+    pub difference: Option<f64>,
+    pub status: SettlementStatus,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AdminResponse {
+    Accounts {
+        accounts: Vec<AccountSummary>,
+    },
+    AccountCreated {
+        card_number: String,
+        card_key: String,
+    },
+    AccountClosed,
+    CardKeyIssued {
+        card_key: String,
+    },
+    PinReset,
+    CardUnlocked,
+    BalanceAdjusted {
+        new_balance: f64,
+    },
+    AccountFrozen,
+    Sessions {
+        sessions: Vec<SessionSummary>,
+    },
+    SessionKicked,
+    SnapshotWritten {
+        path: String,
+    },
+    RoutingReloaded,
+    ShuttingDown,
+    BooksBalanced {
+        postings: usize,
+        balances: Vec<LedgerBalance>,
+    },
+    Settled {
+        date: NaiveDate,
+        terminals: Vec<TerminalSettlement>,
+        csv_path: String,
+        json_path: String,
+    },
+    Terminals {
+        terminals: Vec<TerminalSummary>,
+    },
+    TerminalAdded,
+    TerminalRemoved,
+
+    ErrorAccountNotFound,
+    ErrorAccountExists,
+    // The number belonged to a closed account; its postings stay in the books
+    ErrorCardNumberUsed,
+    ErrorInvalidCardNumber,
+    ErrorInvalidPinFormat,
+    ErrorInvalidAmount,
+    ErrorMissingReason,
+    ErrorBalanceNotZero {
+        balance: f64,
+    },
+    ErrorInsufficientFunds,
+    ErrorCardNotBlocked,
+    ErrorSessionNotFound,
+    ErrorTerminalExists,
+    ErrorInvalidTerminalId,
+    ErrorTerminalNotFound,
+    ErrorRouting {
+        message: String,
+    },
+    ErrorBooksUnbalanced {
+        problems: Vec<String>,
+    },
+    ErrorUnauthorized,
+    ErrorServerInternal,
+}
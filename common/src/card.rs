@@ -0,0 +1,253 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+pub const MIN_CARD_NUMBER_LENGTH: usize = 12;
+pub const MAX_CARD_NUMBER_LENGTH: usize = 19;
+const BIN_LENGTH: usize = 6;
+// Of the card numbers from_parts builds
+const ACCOUNT_NUMBER_DIGITS: u32 = 9;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CardNumberError {
+    InvalidLength(usize),
+    InvalidCharacter(char),
+    ChecksumMismatch,
+    // from_parts was given a BIN or account number with too many digits
+    PartsOutOfRange,
+}
+
+impl fmt::Display for CardNumberError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CardNumberError::InvalidLength(len) => write!(
+                f,
+                "card number must have {} to {} digits, got {}",
+                MIN_CARD_NUMBER_LENGTH, MAX_CARD_NUMBER_LENGTH, len
+            ),
+            CardNumberError::InvalidCharacter(c) => {
+                write!(f, "card number contains invalid character '{}'", c)
+            }
+            CardNumberError::ChecksumMismatch => write!(f, "card number fails the Luhn check"),
+            CardNumberError::PartsOutOfRange => write!(
+                f,
+                "a BIN has 6 digits and an account number at most {}",
+                ACCOUNT_NUMBER_DIGITS
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CardNumberError {}
+
+// A primary account number that passed length and Luhn validation
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CardNumber(String);
+
+impl CardNumber {
+    pub fn parse(input: &str) -> Result<Self, CardNumberError> {
+        // Spaces and dashes are commonly used to group the digits
+        let digits: String = input.chars().filter(|c| !matches!(c, ' ' | '-')).collect();
+
+        if let Some(c) = digits.chars().find(|c| !c.is_ascii_digit()) {
+            return Err(CardNumberError::InvalidCharacter(c));
+        }
+        if !(MIN_CARD_NUMBER_LENGTH..=MAX_CARD_NUMBER_LENGTH).contains(&digits.len()) {
+            return Err(CardNumberError::InvalidLength(digits.len()));
+        }
+        if !luhn_valid(&digits) {
+            return Err(CardNumberError::ChecksumMismatch);
+        }
+
+        Ok(CardNumber(digits))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn last_four(&self) -> &str {
+        &self.0[self.0.len() - 4..]
+    }
+
+    // All but the last four digits replaced with 'x', e.g. xxxxxxxxxxxx3452
+    pub fn masked(&self) -> String {
+        format!("{}{}", "x".repeat(self.0.len() - 4), self.last_four())
+    }
+
+    // Bank identification number: the leading six digits
+    pub fn bin(&self) -> u32 {
+        self.0[..BIN_LENGTH].parse().unwrap()
+    }
+
+    // Digits between the BIN and the check digit
+    pub fn account_number(&self) -> u64 {
+        self.0[BIN_LENGTH..self.0.len() - 1].parse().unwrap()
+    }
+
+    // Builds a 16-digit card number from a BIN and an account number plus the Luhn check digit
+    pub fn from_parts(bin: u32, account_number: u64) -> Result<Self, CardNumberError> {
+        if bin >= 10u32.pow(BIN_LENGTH as u32) || account_number >= 10u64.pow(ACCOUNT_NUMBER_DIGITS)
+        {
+            return Err(CardNumberError::PartsOutOfRange);
+        }
+        CardNumber::with_check_digit(&format!("{:06}{:09}", bin, account_number))
+    }
+
+    // These digits followed by the Luhn check digit that makes them valid
+    pub fn with_check_digit(partial: &str) -> Result<Self, CardNumberError> {
+        if let Some(c) = partial.chars().find(|c| !c.is_ascii_digit()) {
+            return Err(CardNumberError::InvalidCharacter(c));
+        }
+        let check_digit = (0..10)
+            .find(|digit| luhn_valid(&format!("{}{}", partial, digit)))
+            .unwrap();
+        CardNumber::parse(&format!("{}{}", partial, check_digit))
+    }
+}
+
+impl FromStr for CardNumber {
+    type Err = CardNumberError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CardNumber::parse(s)
+    }
+}
+
+impl TryFrom<String> for CardNumber {
+    type Error = CardNumberError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        CardNumber::parse(&value)
+    }
+}
+
+impl From<CardNumber> for String {
+    fn from(card_number: CardNumber) -> Self {
+        card_number.0
+    }
+}
+
+impl fmt::Display for CardNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+fn luhn_valid(digits: &str) -> bool {
+    let sum: u32 = digits
+        .bytes()
+        .rev()
+        .enumerate()
+        .map(|(i, b)| {
+            let digit = u32::from(b - b'0');
+            if i % 2 == 1 {
+                let doubled = digit * 2;
+                if doubled > 9 { doubled - 9 } else { doubled }
+            } else {
+                digit
+            }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+// Inclusive range of BINs belonging to one card issuer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BinRange {
+    pub low: u32,
+    pub high: u32,
+    pub issuer: String,
+}
+
+impl BinRange {
+    pub fn contains(&self, card_number: &CardNumber) -> bool {
+        (self.low..=self.high).contains(&card_number.bin())
+    }
+}
+
+pub fn find_issuer<'a>(ranges: &'a [BinRange], card_number: &CardNumber) -> Option<&'a str> {
+    ranges
+        .iter()
+        .find(|range| range.contains(card_number))
+        .map(|range| range.issuer.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_the_luhn_digit() {
+        assert!(CardNumber::parse("4111111111111111").is_ok());
+        assert!(CardNumber::parse("1234567890123452").is_ok());
+        assert_eq!(
+            CardNumber::parse("4111111111111112"),
+            Err(CardNumberError::ChecksumMismatch)
+        );
+        assert_eq!(
+            CardNumber::parse("1234567890123456"),
+            Err(CardNumberError::ChecksumMismatch)
+        );
+    }
+
+    #[test]
+    fn takes_12_to_19_digits() {
+        assert!(CardNumber::parse("123456789015").is_ok());
+        assert!(CardNumber::parse("1234567890123456785").is_ok());
+        assert_eq!(
+            CardNumber::parse("12345678901"),
+            Err(CardNumberError::InvalidLength(11))
+        );
+        assert_eq!(
+            CardNumber::parse("12345678901234567850"),
+            Err(CardNumberError::InvalidLength(20))
+        );
+    }
+
+    #[test]
+    fn drops_spaces_and_dashes() {
+        let card_number = CardNumber::parse("4111 1111-1111 1111").unwrap();
+        assert_eq!(card_number.as_str(), "4111111111111111");
+        assert_eq!(
+            CardNumber::parse("4111.1111.1111.1111"),
+            Err(CardNumberError::InvalidCharacter('.'))
+        );
+    }
+
+    #[test]
+    fn masks_all_but_the_last_four() {
+        let card_number = CardNumber::parse("1234567890123452").unwrap();
+        assert_eq!(card_number.masked(), "xxxxxxxxxxxx3452");
+        assert_eq!(card_number.last_four(), "3452");
+        let card_number = CardNumber::parse("123456789015").unwrap();
+        assert_eq!(card_number.masked(), "xxxxxxxx9015");
+    }
+
+    #[test]
+    fn builds_from_parts() {
+        let card_number = CardNumber::from_parts(123456, 789012345).unwrap();
+        assert_eq!(card_number.as_str(), "1234567890123452");
+        assert_eq!(card_number.bin(), 123456);
+        assert_eq!(card_number.account_number(), 789012345);
+        assert_eq!(
+            CardNumber::from_parts(1_000_000, 1).unwrap_err(),
+            CardNumberError::PartsOutOfRange
+        );
+        assert_eq!(
+            CardNumber::from_parts(123456, 1_000_000_000).unwrap_err(),
+            CardNumberError::PartsOutOfRange
+        );
+    }
+
+    #[test]
+    fn adds_the_check_digit() {
+        let card_number = CardNumber::with_check_digit("411111111111111").unwrap();
+        assert_eq!(card_number.as_str(), "4111111111111111");
+        assert_eq!(
+            CardNumber::with_check_digit("41111111111111x").unwrap_err(),
+            CardNumberError::InvalidCharacter('x')
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use serde::de::DeserializeOwned;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+// Everything wrong with the configuration, reported together at startup
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid configuration")?;
+        for problem in &self.0 {
+            write!(f, "\n  {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Settings read from a TOML file, then overridden by the environment and
+// then by command-line flags
+pub trait Settings: Default + DeserializeOwned {
+    // Read from the working directory when no file is given
+    const FILE: &'static str;
+
+    type Overrides;
+
+    // Returns the variables that could not be parsed
+    fn apply_env(&mut self) -> Vec<String>;
+
+    fn apply(&mut self, overrides: &Self::Overrides);
+
+    // Returns everything wrong with the settings
+    fn validate(&self) -> Vec<String>;
+
+    // `path` must exist if given; otherwise FILE is read if present
+    fn load(path: Option<&Path>, overrides: &Self::Overrides) -> Result<Self, ConfigError> {
+        let mut settings = read::<Self>(path)?;
+        let mut problems = settings.apply_env();
+        settings.apply(overrides);
+        problems.extend(settings.validate());
+        if problems.is_empty() {
+            Ok(settings)
+        } else {
+            Err(ConfigError(problems))
+        }
+    }
+}
+
+fn read<S: Settings>(path: Option<&Path>) -> Result<S, ConfigError> {
+    let path = match path {
+        Some(path) => path,
+        None if Path::new(S::FILE).exists() => Path::new(S::FILE),
+        None => return Ok(S::default()),
+    };
+    let text = fs::read_to_string(path)
+        .map_err(|e| ConfigError(vec![format!("{}: {}", path.display(), e)]))?;
+    toml::from_str(&text)
+        .map_err(|e| ConfigError(vec![format!("{}: {}", path.display(), e.message())]))
+}
+
+// Unset and empty are the same
+pub fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+pub fn parse_env<T: FromStr>(name: &str, setting: &mut T, problems: &mut Vec<String>) {
+    if let Some(value) = env_var(name) {
+        match value.parse() {
+            Ok(value) => *setting = value,
+            Err(_) => problems.push(format!("{} \"{}\" is not a valid number", name, value)),
+        }
+    }
+}
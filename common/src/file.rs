@@ -0,0 +1,52 @@
+use serde::Serialize;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// Replaces the file at `path` so that a crash leaves either its old or its
+// new contents on disk, never part of them. `write` fills a temporary file
+// next to it, which is synced and renamed over it
+pub fn write_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let temp_path = temp_path(path);
+    write_synced(&temp_path, write)?;
+    fs::rename(&temp_path, path)?;
+    sync_parent_dir(path)
+}
+
+pub fn write_json_atomic<T: Serialize + ?Sized>(path: &Path, value: &T) -> io::Result<()> {
+    write_atomic(path, |writer| {
+        serde_json::to_writer_pretty(writer, value)?;
+        Ok(())
+    })
+}
+
+// Creates or truncates the file and returns once what `write` wrote is on disk
+pub fn write_synced(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write(&mut writer)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()
+}
+
+// The file `write_atomic` writes before renaming it: the name with ".tmp" added
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+// Makes renames and new files in the directory of `path` durable
+pub fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
@@ -1,49 +1,174 @@
+mod admin;
+mod card;
+mod config;
+mod file;
+mod transport;
+
+pub use admin::{
+    AccountSummary, AdminCommand, AdminRequest, AdminResponse, LedgerBalance, SessionSummary,
+    SettlementStatus, TerminalSettlement, TerminalSummary,
+};
+pub use card::{
+    BinRange, CardNumber, CardNumberError, MAX_CARD_NUMBER_LENGTH, MIN_CARD_NUMBER_LENGTH,
+    find_issuer,
+};
+use chrono::{DateTime, Local, Months, NaiveDate};
+pub use config::{ConfigError, Settings, env_var, parse_env};
+pub use file::{sync_parent_dir, temp_path, write_atomic, write_json_atomic, write_synced};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::io::{self, BufReader, BufWriter, Read, Write};
-use std::os::unix::net::UnixStream;
+pub use transport::{Endpoint, Stream};
+
+// Cards are issued for this many months unless stated otherwise
+pub const CARD_VALIDITY_MONTHS: u32 = 48;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CardStatus {
+    #[default]
+    Active,
+    Blocked,
+    Lost,
+    Stolen,
+    Expired,
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Account {
     pub card_key: String,
-    pub card_number: String,
+    pub card_number: CardNumber,
     pub pin: String,
     pub balance: f64,
     pub name: String,
+    // Accounts saved before card metadata existed get an active card issued today
+    #[serde(default)]
+    pub status: CardStatus,
+    #[serde(default = "today")]
+    pub issued_on: NaiveDate,
+    #[serde(default = "default_expiry")]
+    pub expires_on: NaiveDate,
+}
+
+impl Account {
+    pub fn is_expired_on(&self, date: NaiveDate) -> bool {
+        self.status == CardStatus::Expired || date > self.expires_on
+    }
+}
+
+fn today() -> NaiveDate {
+    Local::now().date_naive()
+}
+
+pub fn default_expiry() -> NaiveDate {
+    today() + Months::new(CARD_VALIDITY_MONTHS)
+}
+
+// Amounts travel as f64 but are compared and added up in whole cents
+pub fn to_cents(amount: f64) -> i64 {
+    (amount * 100.0).round() as i64
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+pub fn from_cents(cents: i64) -> f64 {
+    cents as f64 / 100.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Command {
+    // First command on every connection; the bank serves only terminals it knows
+    RegisterTerminal {
+        terminal_id: String,
+        location: String,
+    },
     ValidateCardKey {
         card_key: String,
     },
     Withdraw {
+        // Chosen by the ATM so the withdrawal can be reversed
+        withdrawal_id: String,
         card_number: String,
         pin: String,
         amount: f64,
     },
+    // Sent when the ATM paid nothing out for a withdrawal it got no answer
+    // to. The bank gives back whatever it booked for it, if anything
+    ReverseWithdrawal {
+        withdrawal_id: String,
+        card_number: String,
+        amount: f64,
+    },
     CheckBalance {
         card_number: String,
         pin: String,
     },
+    // Sent by an ATM after every payout with the state of its cassettes
+    ReportCassettes {
+        business_date: NaiveDate,
+        cassettes: Vec<CassetteCount>,
+    },
+    // A withdrawal the ATM approved on its own while the bank was unreachable.
+    // The cash is already paid out, so the bank books it even if that
+    // overdraws the account; `advice_id` makes a resent advice harmless
+    WithdrawalAdvice {
+        advice_id: String,
+        card_number: String,
+        amount: f64,
+        approved_at: DateTime<Local>,
+    },
+}
+
+// One note cassette of an ATM; `dispensed` counts notes paid out on the business date
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CassetteCount {
+    pub denomination: u32,
+    pub remaining: u32,
+    pub dispensed: u32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Response {
+    TerminalRegistered,
+    ErrorUnknownTerminal,
+
     ValidateCardKeySuccess { card_number: String },
     ValidateCardKeyErrorInvalid,
 
     WithdrawSuccess { new_balance: f64 },
     WithdrawErrorInsufficientFunds,
+    WithdrawErrorInvalidAmount,
+    // The withdrawal id was reversed before the withdrawal reached the bank
+    WithdrawErrorReversed,
+    // A withdrawal was already booked under the withdrawal id
+    WithdrawErrorDuplicate,
+    WithdrawalReversed,
+    // Nothing was booked under the withdrawal id; it is refused from now on
+    WithdrawalNotFound,
 
     CheckBalanceSuccess { amount: f64 },
 
+    CassettesRecorded,
+
+    AdviceRecorded,
+
     ErrorServerInternal,
     ErrorInvalidPin,
     ErrorCardNotFound,
+    ErrorInvalidCardNumber,
+    ErrorCardNotSupported,
+    ErrorCardExpired,
+    ErrorCardBlocked,
+    ErrorCardLost,
 }
 
-pub fn send_command(stream: &mut UnixStream, command: &Command) -> io::Result<()> {
-    let serialized = serde_json::to_string(command)?;
+// Largest message either side sends or accepts. A peer announcing more is
+// cut off before anything is allocated for it
+pub const MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+// Every message is sent as a big-endian u32 length followed by that many bytes of JSON
+pub fn send_message<T: Serialize>(stream: &mut impl Write, message: &T) -> io::Result<()> {
+    let serialized = serde_json::to_string(message)?;
+    if serialized.len() > MAX_MESSAGE_SIZE {
+        return Err(too_large(serialized.len()));
+    }
     let mut writer = BufWriter::new(stream);
 
     // Send length as u32 first (4 bytes), then the JSON data
@@ -55,49 +180,50 @@ pub fn send_command(stream: &mut UnixStream, command: &Command) -> io::Result<()
     Ok(())
 }
 
-pub fn receive_response(stream: &mut UnixStream) -> io::Result<Response> {
+pub fn receive_message<T: DeserializeOwned>(stream: &mut impl Read) -> io::Result<T> {
     let mut reader = BufReader::new(stream);
 
     // Read length first (4 bytes)
     let mut len_bytes = [0u8; 4];
     reader.read_exact(&mut len_bytes)?;
     let len = u32::from_be_bytes(len_bytes) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(too_large(len));
+    }
 
     // Read JSON data
     let mut buffer = vec![0u8; len];
     reader.read_exact(&mut buffer)?;
 
-    let response: Response = serde_json::from_slice(&buffer)?;
-    Ok(response)
+    let message = serde_json::from_slice(&buffer)?;
+    Ok(message)
 }
 
-pub fn send_response(stream: &mut UnixStream, response: &Response) -> io::Result<()> {
-    let serialized = serde_json::to_string(response)?;
-    let mut writer = BufWriter::new(stream);
-
-    // Send length as u32 first (4 bytes), then the JSON data
-    let len = serialized.len() as u32;
-    writer.write_all(&len.to_be_bytes())?;
-    writer.write_all(serialized.as_bytes())?;
-    writer.flush()?;
-
-    Ok(())
+fn too_large(len: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "message of {} bytes is over the limit of {}",
+            len, MAX_MESSAGE_SIZE
+        ),
+    )
 }
 
-pub fn receive_command(stream: &mut UnixStream) -> io::Result<Command> {
-    let mut reader = BufReader::new(stream);
+pub fn send_command(stream: &mut Stream, command: &Command) -> io::Result<()> {
+    send_message(stream, command)
+}
 
-    // Read length first (4 bytes)
-    let mut len_bytes = [0u8; 4];
-    reader.read_exact(&mut len_bytes)?;
-    let len = u32::from_be_bytes(len_bytes) as usize;
+pub fn receive_response(stream: &mut Stream) -> io::Result<Response> {
+    receive_message(stream)
+}
 
-    // Read JSON data
-    let mut buffer = vec![0u8; len];
-    reader.read_exact(&mut buffer)?;
+pub fn send_response(stream: &mut Stream, response: &Response) -> io::Result<()> {
+    send_message(stream, response)
+}
 
-    let command: Command = serde_json::from_slice(&buffer)?;
-    Ok(command)
+pub fn receive_command(stream: &mut Stream) -> io::Result<Command> {
+    receive_message(stream)
 }
 
 pub const SOCKET_PATH: &str = "/tmp/atm_bank_socket";
+pub const ADMIN_SOCKET_PATH: &str = "/tmp/atm_bank_admin_socket";